@@ -78,6 +78,8 @@ pub fn execute_send_msgs(
         channel_id,
         msgs,
         callback_id: Some(callback_id),
+        timeout_seconds: None,
+        mode: None,
     };
     let msg = WasmMsg::Execute {
         contract_addr: cfg.simple_ica_controller.into(),
@@ -106,6 +108,7 @@ pub fn execute_ibc_query(
         channel_id,
         msgs,
         callback_id: Some(callback_id),
+        timeout_seconds: None,
     };
     let msg = WasmMsg::Execute {
         contract_addr: cfg.simple_ica_controller.into(),
@@ -128,7 +131,10 @@ pub fn execute_check_remote_balance(
         return Err(ContractError::Unauthorized {});
     }
 
-    let ica_msg = simple_ica_controller::msg::ExecuteMsg::CheckRemoteBalance { channel_id };
+    let ica_msg = simple_ica_controller::msg::ExecuteMsg::CheckRemoteBalance {
+        channel_id,
+        timeout_seconds: None,
+    };
     let msg = WasmMsg::Execute {
         contract_addr: cfg.simple_ica_controller.into(),
         msg: to_binary(&ica_msg)?,
@@ -154,6 +160,7 @@ pub fn execute_send_funds(
     let ica_msg = simple_ica_controller::msg::ExecuteMsg::SendFunds {
         ica_channel_id,
         transfer_channel_id,
+        timeout_seconds: None,
     };
     let msg = WasmMsg::Execute {
         contract_addr: cfg.simple_ica_controller.into(),
@@ -255,6 +262,8 @@ mod tests {
                 channel_id: channel.to_string(),
                 msgs,
                 callback_id: Some("test".to_string()),
+                timeout_seconds: None,
+                mode: None,
             })
             .unwrap(),
             funds: vec![],
@@ -300,6 +309,7 @@ mod tests {
                 channel_id: channel.to_string(),
                 msgs: queries,
                 callback_id: Some(callback.to_string()),
+                timeout_seconds: None,
             })
             .unwrap(),
             funds: vec![],
@@ -4,7 +4,11 @@ use std::fs::create_dir_all;
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use simple_ica_controller::msg::{
-    AccountResponse, AdminResponse, ExecuteMsg, InstantiateMsg, ListAccountsResponse, QueryMsg,
+    AccountResponse, AdminResponse, AllowlistResponse, ChannelSettingsResponse,
+    ClosedAccountResponse, ConfigResponse, CounterpartyPolicyResponse, ExecuteMsg,
+    FailedCallbacksResponse, InstantiateMsg, ListAccountsResponse, ListClosedAccountsResponse,
+    ListClosedSenderAccountsResponse, MigrateMsg, PacketResponse, PendingPacketsResponse,
+    QueriersResponse, QueryMsg,
 };
 
 fn main() {
@@ -15,8 +19,20 @@ fn main() {
 
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(AdminResponse), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(CounterpartyPolicyResponse), &out_dir);
+    export_schema(&schema_for!(AllowlistResponse), &out_dir);
+    export_schema(&schema_for!(QueriersResponse), &out_dir);
     export_schema(&schema_for!(AccountResponse), &out_dir);
+    export_schema(&schema_for!(ChannelSettingsResponse), &out_dir);
     export_schema(&schema_for!(ListAccountsResponse), &out_dir);
+    export_schema(&schema_for!(ClosedAccountResponse), &out_dir);
+    export_schema(&schema_for!(ListClosedAccountsResponse), &out_dir);
+    export_schema(&schema_for!(ListClosedSenderAccountsResponse), &out_dir);
+    export_schema(&schema_for!(PacketResponse), &out_dir);
+    export_schema(&schema_for!(PendingPacketsResponse), &out_dir);
+    export_schema(&schema_for!(FailedCallbacksResponse), &out_dir);
 }
@@ -1,53 +1,171 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, CosmosMsg, Deps, DepsMut, Empty, Env, IbcMsg, MessageInfo, Order, QueryRequest,
-    QueryResponse, Response, StdError, StdResult,
+    to_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Empty, Env, IbcMsg, MessageInfo, Order,
+    QueryRequest, QueryResponse, Reply, Response, StdError, StdResult, SubMsgResult,
 };
+use cw2::set_contract_version;
+use cw_storage_plus::{Bound, Map};
 
-use simple_ica::PacketMsg;
+use simple_ica::{
+    ChannelSettings, CounterpartyPolicy, DispatchMode, PacketMsg, ReceiveIcaResponseMsg,
+};
 
-use crate::ibc::PACKET_LIFETIME;
+use crate::error::ContractError;
+use crate::ibc::{
+    packet_timeout, send_packet, validate_timeout, CALLBACK_ID, DEFAULT_CALLBACK_GAS_LIMIT,
+    DEFAULT_PACKET_LIFETIME, SEND_PACKET_ID,
+};
+use crate::migrations::{migrate_legacy, previous_version, LEGACY_VERSION};
 use crate::msg::{
-    AccountInfo, AccountResponse, AdminResponse, ExecuteMsg, InstantiateMsg, LatestQueryResponse,
-    ListAccountsResponse, QueryMsg,
+    AccountInfo, AccountResponse, AdminResponse, AllowlistResponse, ChannelSettingsResponse,
+    ClosedAccountInfo, ClosedAccountResponse, ClosedSenderAccountInfo, ConfigResponse,
+    CounterpartyPolicyResponse, ExecuteMsg, FailedCallbackInfo, FailedCallbacksResponse,
+    InstantiateMsg, LatestQueryResponse, ListAccountsResponse, ListClosedAccountsResponse,
+    ListClosedSenderAccountsResponse, MigrateMsg, PacketInfo, PacketResponse,
+    PendingPacketsResponse, QueriersResponse, QueryMsg,
+};
+use crate::state::{
+    channel_settings, AccessPolicy, AccountData, Config, FailedCallback, ACCOUNTS, ALLOWLIST,
+    CLOSED_ACCOUNTS, CLOSED_SENDER_ACCOUNTS, CONFIG, COUNTERPARTY_POLICY, FAILED_CALLBACKS,
+    FAILED_CALLBACK_COUNT, LATEST_QUERIES, PACKETS, PENDING_PACKETS, QUERIERS, SENDER_ACCOUNTS,
+    SENDING_CALLBACK, SENDING_PACKET,
 };
-use crate::state::{Config, ACCOUNTS, CONFIG, LATEST_QUERIES};
+
+pub const CONTRACT_NAME: &str = "crates.io:simple-ica-controller";
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
-    _msg: InstantiateMsg,
-) -> StdResult<Response> {
-    let cfg = Config { admin: info.sender };
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    let default_timeout = validate_timeout(msg.default_timeout.unwrap_or(DEFAULT_PACKET_LIFETIME))?;
+    let cfg = Config {
+        admin: info.sender,
+        default_timeout,
+        access_policy: msg.access_policy.unwrap_or(AccessPolicy::AdminOnly),
+        callback_gas_limit: msg.callback_gas_limit.unwrap_or(DEFAULT_CALLBACK_GAS_LIMIT),
+    };
     CONFIG.save(deps.storage, &cfg)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new().add_attribute("action", "instantiate"))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // only the 0.2.0 layout needs migrating, later versions just record the new version
+    let previous_version = previous_version(deps.storage)?;
+    if previous_version <= LEGACY_VERSION {
+        migrate_legacy(deps.storage)?;
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("previous_version", previous_version.to_string())
+        .add_attribute("version", CONTRACT_VERSION))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::UpdateAdmin { admin } => execute_update_admin(deps, info, admin),
+        ExecuteMsg::UpdateDefaultTimeout { default_timeout } => {
+            execute_update_default_timeout(deps, info, default_timeout)
+        }
+        ExecuteMsg::UpdateAccessPolicy { access_policy } => {
+            execute_update_access_policy(deps, info, access_policy)
+        }
+        ExecuteMsg::UpdateCallbackGasLimit { callback_gas_limit } => {
+            execute_update_callback_gas_limit(deps, info, callback_gas_limit)
+        }
+        ExecuteMsg::UpdateAllowlist { add, remove } => {
+            execute_update_allowlist(deps, info, add, remove)
+        }
+        ExecuteMsg::UpdateCounterpartyPolicy { policy } => {
+            execute_update_counterparty_policy(deps, info, policy)
+        }
+        ExecuteMsg::UpdateQueriers { add, remove } => {
+            execute_update_queriers(deps, info, add, remove)
+        }
         ExecuteMsg::SendMsgs {
             channel_id,
             msgs,
             callback_id,
-        } => execute_send_msgs(deps, env, info, channel_id, msgs, callback_id),
-        ExecuteMsg::CheckRemoteBalance { channel_id } => {
-            execute_check_remote_balance(deps, env, info, channel_id)
-        }
+            timeout_seconds,
+            mode,
+        } => execute_send_msgs(
+            deps,
+            env,
+            info,
+            channel_id,
+            msgs,
+            callback_id,
+            timeout_seconds,
+            mode,
+        ),
+        ExecuteMsg::RegisterAccount {
+            channel_id,
+            timeout_seconds,
+        } => execute_register_account(deps, env, info, channel_id, timeout_seconds),
+        ExecuteMsg::CheckRemoteBalance {
+            channel_id,
+            timeout_seconds,
+        } => execute_check_remote_balance(deps, env, info, channel_id, timeout_seconds),
         ExecuteMsg::IbcQuery {
             channel_id,
             msgs,
             callback_id,
-        } => execute_ibc_query(deps, env, info, channel_id, msgs, callback_id),
+            timeout_seconds,
+        } => execute_ibc_query(
+            deps,
+            env,
+            info,
+            channel_id,
+            msgs,
+            callback_id,
+            timeout_seconds,
+        ),
         ExecuteMsg::SendFunds {
             ica_channel_id,
             transfer_channel_id,
-        } => execute_send_funds(deps, env, info, ica_channel_id, transfer_channel_id),
+            timeout_seconds,
+        } => execute_send_funds(
+            deps,
+            env,
+            info,
+            ica_channel_id,
+            transfer_channel_id,
+            timeout_seconds,
+        ),
+        ExecuteMsg::ReclaimFunds {
+            channel_id,
+            closed_channel_id,
+            timeout_seconds,
+        } => execute_reclaim_funds(
+            deps,
+            env,
+            info,
+            channel_id,
+            closed_channel_id,
+            timeout_seconds,
+        ),
+        ExecuteMsg::MigrateAccount {
+            channel_id,
+            code_id,
+            msg,
+            timeout_seconds,
+        } => execute_migrate_account(deps, env, info, channel_id, code_id, msg, timeout_seconds),
+        ExecuteMsg::RetryCallback { id, drop } => execute_retry_callback(deps, info, id, drop),
     }
 }
 
@@ -55,11 +173,11 @@ pub fn execute_update_admin(
     deps: DepsMut,
     info: MessageInfo,
     new_admin: String,
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
     // auth check
     let mut cfg = CONFIG.load(deps.storage)?;
     if info.sender != cfg.admin {
-        return Err(StdError::generic_err("Only admin may set new admin"));
+        return Err(StdError::generic_err("Only admin may set new admin").into());
     }
     cfg.admin = deps.api.addr_validate(&new_admin)?;
     CONFIG.save(deps.storage, &cfg)?;
@@ -69,6 +187,157 @@ pub fn execute_update_admin(
         .add_attribute("new_admin", cfg.admin))
 }
 
+pub fn execute_update_default_timeout(
+    deps: DepsMut,
+    info: MessageInfo,
+    default_timeout: u64,
+) -> Result<Response, ContractError> {
+    // auth check
+    let mut cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may set default timeout").into());
+    }
+    cfg.default_timeout = validate_timeout(default_timeout)?;
+    CONFIG.save(deps.storage, &cfg)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_default_timeout")
+        .add_attribute("default_timeout", default_timeout.to_string()))
+}
+
+pub fn execute_update_access_policy(
+    deps: DepsMut,
+    info: MessageInfo,
+    access_policy: AccessPolicy,
+) -> Result<Response, ContractError> {
+    // auth check
+    let mut cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may set access policy").into());
+    }
+    cfg.access_policy = access_policy;
+    CONFIG.save(deps.storage, &cfg)?;
+
+    Ok(Response::new().add_attribute("action", "handle_update_access_policy"))
+}
+
+pub fn execute_update_counterparty_policy(
+    deps: DepsMut,
+    info: MessageInfo,
+    policy: CounterpartyPolicy,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may set counterparty policy").into());
+    }
+    COUNTERPARTY_POLICY.save(deps.storage, &policy)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_counterparty_policy")
+        .add_attribute("open", policy.is_open().to_string()))
+}
+
+pub fn execute_update_callback_gas_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    callback_gas_limit: u64,
+) -> Result<Response, ContractError> {
+    // auth check
+    let mut cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may set callback gas limit").into());
+    }
+    cfg.callback_gas_limit = callback_gas_limit;
+    CONFIG.save(deps.storage, &cfg)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_callback_gas_limit")
+        .add_attribute("callback_gas_limit", callback_gas_limit.to_string()))
+}
+
+pub fn execute_update_allowlist(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update allowlist").into());
+    }
+    update_addresses(deps, ALLOWLIST, add, remove)?;
+
+    Ok(Response::new().add_attribute("action", "handle_update_allowlist"))
+}
+
+pub fn execute_update_queriers(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update queriers").into());
+    }
+    update_addresses(deps, QUERIERS, add, remove)?;
+
+    Ok(Response::new().add_attribute("action", "handle_update_queriers"))
+}
+
+fn update_addresses(
+    deps: DepsMut,
+    addresses: Map<&Addr, Empty>,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> StdResult<()> {
+    for addr in add {
+        let addr = deps.api.addr_validate(&addr)?;
+        addresses.save(deps.storage, &addr, &Empty {})?;
+    }
+    for addr in remove {
+        let addr = deps.api.addr_validate(&addr)?;
+        addresses.remove(deps.storage, &addr);
+    }
+    Ok(())
+}
+
+// ensures the sender may send packets under the current access policy
+fn check_can_send(deps: Deps, cfg: &Config, sender: &Addr) -> Result<(), ContractError> {
+    let allowed = match cfg.access_policy {
+        AccessPolicy::AdminOnly => sender == &cfg.admin,
+        AccessPolicy::Allowlist => sender == &cfg.admin || ALLOWLIST.has(deps.storage, sender),
+        AccessPolicy::Open => true,
+    };
+    if !allowed {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+// queries cannot move funds, so queriers may send them as well as anyone who can send
+fn check_can_query(deps: Deps, cfg: &Config, sender: &Addr) -> Result<(), ContractError> {
+    if QUERIERS.has(deps.storage, sender) {
+        return Ok(());
+    }
+    check_can_send(deps, cfg, sender)
+}
+
+// ensures the channel was registered when the handshake completed, and is still open
+fn check_registered(deps: Deps, channel_id: &str) -> Result<AccountData, ContractError> {
+    if CLOSED_ACCOUNTS.has(deps.storage, channel_id) {
+        return Err(ContractError::ChannelClosed(channel_id.to_string()));
+    }
+    let account = ACCOUNTS
+        .may_load(deps.storage, channel_id)?
+        .ok_or_else(|| ContractError::UnregisteredChannel(channel_id.to_string()))?;
+    Ok(account)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn execute_send_msgs(
     deps: DepsMut,
     env: Env,
@@ -76,58 +345,102 @@ pub fn execute_send_msgs(
     channel_id: String,
     msgs: Vec<CosmosMsg>,
     callback_id: Option<String>,
-) -> StdResult<Response> {
+    timeout_seconds: Option<u64>,
+    mode: Option<DispatchMode>,
+) -> Result<Response, ContractError> {
     // auth check
     let cfg = CONFIG.load(deps.storage)?;
-    if info.sender != cfg.admin {
-        return Err(StdError::generic_err("Only admin may send messages"));
-    }
-    // ensure the channel exists (not found if not registered)
-    ACCOUNTS.load(deps.storage, &channel_id)?;
+    check_can_send(deps.as_ref(), &cfg, &info.sender)?;
+    check_registered(deps.as_ref(), &channel_id)?;
 
     // construct a packet to send
-    let sender = info.sender.into();
     let packet = PacketMsg::Dispatch {
-        sender,
+        sender: info.sender.to_string(),
         msgs,
         callback_id,
+        mode: mode.unwrap_or_default(),
     };
-    let msg = IbcMsg::SendPacket {
+    let timeout = packet_timeout(&env, &cfg, timeout_seconds)?;
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        info.sender,
         channel_id,
-        data: to_binary(&packet)?,
-        timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
-    };
+        &packet,
+        timeout,
+    )?;
 
     let res = Response::new()
-        .add_message(msg)
+        .add_submessage(msg)
         .add_attribute("action", "handle_send_msgs");
     Ok(res)
 }
 
 pub fn execute_ibc_query(
-    _deps: DepsMut,
+    deps: DepsMut,
     env: Env,
     info: MessageInfo,
     channel_id: String,
     msgs: Vec<QueryRequest<Empty>>,
     callback_id: Option<String>,
-) -> StdResult<Response> {
+    timeout_seconds: Option<u64>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    check_can_query(deps.as_ref(), &cfg, &info.sender)?;
+    check_registered(deps.as_ref(), &channel_id)?;
+
     // construct a packet to send
-    let sender = info.sender.into();
     let packet = PacketMsg::IbcQuery {
-        sender,
+        sender: info.sender.to_string(),
         msgs,
         callback_id,
     };
-    let msg = IbcMsg::SendPacket {
+    let timeout = packet_timeout(&env, &cfg, timeout_seconds)?;
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        info.sender,
         channel_id,
-        data: to_binary(&packet)?,
-        timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+        &packet,
+        timeout,
+    )?;
+
+    let res = Response::new()
+        .add_submessage(msg)
+        .add_attribute("action", "handle_ibc_query");
+    Ok(res)
+}
+
+pub fn execute_register_account(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    timeout_seconds: Option<u64>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    check_can_send(deps.as_ref(), &cfg, &info.sender)?;
+    check_registered(deps.as_ref(), &channel_id)?;
+
+    // the host creates the account if needed, the ack tells us its address
+    let packet = PacketMsg::WhoAmI {
+        sender: Some(info.sender.to_string()),
     };
+    let timeout = packet_timeout(&env, &cfg, timeout_seconds)?;
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        info.sender,
+        channel_id,
+        &packet,
+        timeout,
+    )?;
 
     let res = Response::new()
-        .add_message(msg)
-        .add_attribute("action", "handle_check_remote_balance");
+        .add_submessage(msg)
+        .add_attribute("action", "handle_register_account");
     Ok(res)
 }
 
@@ -136,25 +449,29 @@ pub fn execute_check_remote_balance(
     env: Env,
     info: MessageInfo,
     channel_id: String,
-) -> StdResult<Response> {
+    timeout_seconds: Option<u64>,
+) -> Result<Response, ContractError> {
     // auth check
     let cfg = CONFIG.load(deps.storage)?;
-    if info.sender != cfg.admin {
-        return Err(StdError::generic_err("Only admin may send messages"));
-    }
-    // ensure the channel exists (not found if not registered)
-    ACCOUNTS.load(deps.storage, &channel_id)?;
+    check_can_send(deps.as_ref(), &cfg, &info.sender)?;
+    check_registered(deps.as_ref(), &channel_id)?;
 
     // construct a packet to send
-    let packet = PacketMsg::Balances {};
-    let msg = IbcMsg::SendPacket {
-        channel_id,
-        data: to_binary(&packet)?,
-        timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+    let packet = PacketMsg::Balances {
+        sender: Some(info.sender.to_string()),
     };
+    let timeout = packet_timeout(&env, &cfg, timeout_seconds)?;
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        info.sender,
+        channel_id,
+        &packet,
+        timeout,
+    )?;
 
     let res = Response::new()
-        .add_message(msg)
+        .add_submessage(msg)
         .add_attribute("action", "handle_check_remote_balance");
     Ok(res)
 }
@@ -165,40 +482,41 @@ pub fn execute_send_funds(
     mut info: MessageInfo,
     ica_channel_id: String,
     transfer_channel_id: String,
-) -> StdResult<Response> {
+    timeout_seconds: Option<u64>,
+) -> Result<Response, ContractError> {
     // intentionally no auth check
+    let cfg = CONFIG.load(deps.storage)?;
 
     // require some funds
     let amount = match info.funds.pop() {
         Some(coin) => coin,
         None => {
-            return Err(StdError::generic_err(
-                "you must send the coins you wish to ibc transfer",
-            ))
+            return Err(
+                StdError::generic_err("you must send the coins you wish to ibc transfer").into(),
+            )
         }
     };
     // if there are any more coins, reject the message
     if !info.funds.is_empty() {
-        return Err(StdError::generic_err("you can only ibc transfer one coin"));
+        return Err(StdError::generic_err("you can only ibc transfer one coin").into());
     }
 
-    // load remote account
-    let data = ACCOUNTS.load(deps.storage, &ica_channel_id)?;
-    let remote_addr = match data.remote_addr {
-        Some(addr) => addr,
-        None => {
-            return Err(StdError::generic_err(
-                "We don't have the remote address for this channel",
-            ))
-        }
-    };
+    // load the caller's remote account, the funds are theirs
+    check_registered(deps.as_ref(), &ica_channel_id)?;
+    let remote_addr = SENDER_ACCOUNTS
+        .may_load(deps.storage, (&ica_channel_id, info.sender.as_str()))?
+        .and_then(|account| account.remote_addr)
+        .ok_or_else(|| ContractError::UnknownRemoteAddress {
+            channel_id: ica_channel_id.clone(),
+            sender: info.sender.to_string(),
+        })?;
 
     // construct a packet to send
     let msg = IbcMsg::Transfer {
         channel_id: transfer_channel_id,
         to_address: remote_addr,
         amount,
-        timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+        timeout: packet_timeout(&env, &cfg, timeout_seconds)?.into(),
     };
 
     let res = Response::new()
@@ -207,25 +525,353 @@ pub fn execute_send_funds(
     Ok(res)
 }
 
+pub fn execute_reclaim_funds(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    closed_channel_id: String,
+    timeout_seconds: Option<u64>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    check_can_send(deps.as_ref(), &cfg, &info.sender)?;
+    check_registered(deps.as_ref(), &channel_id)?;
+
+    // construct a packet to send
+    let packet = PacketMsg::ReclaimFunds {
+        sender: info.sender.to_string(),
+        channel_id: closed_channel_id,
+    };
+    let timeout = packet_timeout(&env, &cfg, timeout_seconds)?;
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        info.sender,
+        channel_id,
+        &packet,
+        timeout,
+    )?;
+
+    let res = Response::new()
+        .add_submessage(msg)
+        .add_attribute("action", "handle_reclaim_funds");
+    Ok(res)
+}
+
+pub fn execute_migrate_account(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    code_id: u64,
+    msg: Binary,
+    timeout_seconds: Option<u64>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    check_can_send(deps.as_ref(), &cfg, &info.sender)?;
+    check_registered(deps.as_ref(), &channel_id)?;
+
+    // construct a packet to send
+    let packet = PacketMsg::MigrateAccount {
+        sender: info.sender.to_string(),
+        code_id,
+        msg,
+    };
+    let timeout = packet_timeout(&env, &cfg, timeout_seconds)?;
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        info.sender,
+        channel_id,
+        &packet,
+        timeout,
+    )?;
+
+    let res = Response::new()
+        .add_submessage(msg)
+        .add_attribute("action", "handle_migrate_account");
+    Ok(res)
+}
+
+pub fn execute_retry_callback(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    drop: bool,
+) -> Result<Response, ContractError> {
+    // only the receiver can see its own callbacks
+    let failed = FAILED_CALLBACKS
+        .may_load(deps.storage, (&info.sender, id))?
+        .ok_or(ContractError::UnknownCallback(id))?;
+    FAILED_CALLBACKS.remove(deps.storage, (&info.sender, id));
+
+    let res = Response::new()
+        .add_attribute("action", "handle_retry_callback")
+        .add_attribute("callback_id", &failed.callback.callback_id);
+    if drop {
+        return Ok(res.add_attribute("dropped", "true"));
+    }
+
+    // the caller pays for this one, so no gas limit. If it fails again, it stays queued
+    let msg = ReceiveIcaResponseMsg {
+        id: failed.callback.callback_id,
+        msg: failed.callback.msg,
+    }
+    .into_cosmos_msg(info.sender)?;
+    Ok(res.add_message(msg))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    match reply.id {
+        SEND_PACKET_ID => reply_send_packet(deps, reply),
+        CALLBACK_ID => reply_callback(deps, env, reply),
+        _ => Err(ContractError::InvalidReplyId),
+    }
+}
+
+pub fn reply_send_packet(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    // we use storage to pass info from the caller to the reply
+    let data = SENDING_PACKET.load(deps.storage)?;
+    SENDING_PACKET.remove(deps.storage);
+
+    // the ibc module emits the sequence of the packet it just sent
+    let res = reply.result.into_result().map_err(StdError::generic_err)?;
+    let sequence = res
+        .events
+        .iter()
+        .filter(|event| event.ty == "send_packet")
+        .flat_map(|event| event.attributes.iter())
+        .find(|attr| attr.key == "packet_sequence")
+        .and_then(|attr| attr.value.parse::<u64>().ok());
+    // the packet was sent all the same, we only cannot keep a record of it
+    let sequence = match sequence {
+        Some(sequence) => sequence,
+        None => {
+            return Ok(Response::new()
+                .add_attribute("channel_id", &data.channel_id)
+                .add_attribute("packet_record", "skipped"))
+        }
+    };
+
+    let key = (data.channel_id.as_str(), sequence);
+    PACKETS.save(deps.storage, key, &data)?;
+    PENDING_PACKETS.save(deps.storage, key, &Empty {})?;
+
+    Ok(Response::new()
+        .add_attribute("channel_id", &data.channel_id)
+        .add_attribute("packet_sequence", sequence.to_string()))
+}
+
+// a failed callback is queued to be retried
+pub fn reply_callback(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
+    let callback = SENDING_CALLBACK.load(deps.storage)?;
+    SENDING_CALLBACK.remove(deps.storage);
+
+    let error = match reply.result {
+        SubMsgResult::Ok(_) => return Ok(Response::new()),
+        SubMsgResult::Err(error) => error,
+    };
+    let id = FAILED_CALLBACK_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    FAILED_CALLBACK_COUNT.save(deps.storage, &id)?;
+    let res = Response::new()
+        .add_attribute("action", "callback_failed")
+        .add_attribute("receiver", &callback.receiver)
+        .add_attribute("failed_callback", id.to_string());
+
+    let failed = FailedCallback {
+        error,
+        failed_time: env.block.time,
+        callback,
+    };
+    FAILED_CALLBACKS.save(deps.storage, (&failed.callback.receiver, id), &failed)?;
+    Ok(res)
+}
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
     match msg {
         QueryMsg::Admin {} => to_binary(&query_admin(deps)?),
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::CounterpartyPolicy {} => to_binary(&query_counterparty_policy(deps)?),
+        QueryMsg::Allowlist { start_after, limit } => {
+            to_binary(&query_allowlist(deps, start_after, limit)?)
+        }
+        QueryMsg::Queriers { start_after, limit } => {
+            to_binary(&query_queriers(deps, start_after, limit)?)
+        }
         QueryMsg::Account { channel_id } => to_binary(&query_account(deps, channel_id)?),
+        QueryMsg::ListClosedAccounts { start_after, limit } => {
+            to_binary(&query_list_closed_accounts(deps, start_after, limit)?)
+        }
+        QueryMsg::ClosedAccount { channel_id } => {
+            to_binary(&query_closed_account(deps, channel_id)?)
+        }
+        QueryMsg::ListClosedSenderAccounts {
+            channel_id,
+            start_after,
+            limit,
+        } => to_binary(&query_list_closed_sender_accounts(
+            deps,
+            channel_id,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::ChannelSettings { channel_id } => {
+            to_binary(&query_channel_settings(deps, channel_id)?)
+        }
+        QueryMsg::SenderAccount { channel_id, sender } => {
+            to_binary(&query_sender_account(deps, channel_id, sender)?)
+        }
         QueryMsg::ListAccounts {} => to_binary(&query_list_accounts(deps)?),
-        QueryMsg::LatestQueryResult { channel_id } => {
-            to_binary(&query_latest_ibc_query_result(deps, channel_id)?)
+        QueryMsg::LatestQueryResult { channel_id, sender } => {
+            to_binary(&query_latest_ibc_query_result(deps, channel_id, sender)?)
         }
+        QueryMsg::Packet {
+            channel_id,
+            sequence,
+        } => to_binary(&query_packet(deps, channel_id, sequence)?),
+        QueryMsg::PendingPackets { start_after, limit } => {
+            to_binary(&query_pending_packets(deps, start_after, limit)?)
+        }
+        QueryMsg::FailedCallbacks {
+            receiver,
+            start_after,
+            limit,
+        } => to_binary(&query_failed_callbacks(deps, receiver, start_after, limit)?),
     }
 }
 
+fn query_packet(deps: Deps, channel_id: String, sequence: u64) -> StdResult<PacketResponse> {
+    let packet = PACKETS.load(deps.storage, (&channel_id, sequence))?;
+    Ok(packet.into())
+}
+
+fn query_pending_packets(
+    deps: Deps,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<PendingPacketsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .as_ref()
+        .map(|(channel_id, sequence)| Bound::exclusive((channel_id.as_str(), *sequence)));
+
+    let packets = PENDING_PACKETS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|key| {
+            let (channel_id, sequence) = key?;
+            let packet = PACKETS.load(deps.storage, (&channel_id, sequence))?;
+            Ok(PacketInfo::convert(sequence, packet))
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(PendingPacketsResponse { packets })
+}
+
+fn query_failed_callbacks(
+    deps: Deps,
+    receiver: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<FailedCallbacksResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let receiver = deps.api.addr_validate(&receiver)?;
+    let start = start_after.map(Bound::exclusive);
+
+    let callbacks = FAILED_CALLBACKS
+        .prefix(&receiver)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (id, failed) = item?;
+            Ok(FailedCallbackInfo::convert(id, failed))
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(FailedCallbacksResponse { callbacks })
+}
+
 fn query_account(deps: Deps, channel_id: String) -> StdResult<AccountResponse> {
     let account = ACCOUNTS.load(deps.storage, &channel_id)?;
     Ok(account.into())
 }
 
-fn query_latest_ibc_query_result(deps: Deps, channel_id: String) -> StdResult<LatestQueryResponse> {
-    LATEST_QUERIES.load(deps.storage, &channel_id)
+fn query_closed_account(deps: Deps, channel_id: String) -> StdResult<ClosedAccountResponse> {
+    let account = CLOSED_ACCOUNTS.load(deps.storage, &channel_id)?;
+    Ok(account.into())
+}
+
+fn query_list_closed_accounts(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ListClosedAccountsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+
+    let accounts = CLOSED_ACCOUNTS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (channel_id, account) = item?;
+            Ok(ClosedAccountInfo::convert(channel_id, account))
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListClosedAccountsResponse { accounts })
+}
+
+fn query_list_closed_sender_accounts(
+    deps: Deps,
+    channel_id: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ListClosedSenderAccountsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+
+    let accounts = CLOSED_SENDER_ACCOUNTS
+        .prefix(&channel_id)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (sender, account) = item?;
+            Ok(ClosedSenderAccountInfo::convert(sender, account))
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListClosedSenderAccountsResponse { accounts })
+}
+
+fn query_channel_settings(deps: Deps, channel_id: String) -> StdResult<ChannelSettingsResponse> {
+    // only channels we have an account on
+    ACCOUNTS.load(deps.storage, &channel_id)?;
+    let ChannelSettings { version, order } = channel_settings(deps.storage, &channel_id)?;
+    Ok(ChannelSettingsResponse { version, order })
+}
+
+fn query_sender_account(
+    deps: Deps,
+    channel_id: String,
+    sender: String,
+) -> StdResult<AccountResponse> {
+    let account = SENDER_ACCOUNTS.load(deps.storage, (&channel_id, &sender))?;
+    Ok(account.into())
+}
+
+fn query_latest_ibc_query_result(
+    deps: Deps,
+    channel_id: String,
+    sender: String,
+) -> StdResult<LatestQueryResponse> {
+    LATEST_QUERIES.load(deps.storage, (&channel_id, &sender))
 }
 
 fn query_list_accounts(deps: Deps) -> StdResult<ListAccountsResponse> {
@@ -240,15 +886,73 @@ fn query_list_accounts(deps: Deps) -> StdResult<ListAccountsResponse> {
 }
 
 fn query_admin(deps: Deps) -> StdResult<AdminResponse> {
-    let Config { admin } = CONFIG.load(deps.storage)?;
+    let Config { admin, .. } = CONFIG.load(deps.storage)?;
     Ok(AdminResponse {
         admin: admin.into(),
     })
 }
 
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let Config {
+        admin,
+        default_timeout,
+        access_policy,
+        callback_gas_limit,
+    } = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse {
+        admin: admin.into(),
+        default_timeout,
+        access_policy,
+        callback_gas_limit,
+    })
+}
+
+fn query_counterparty_policy(deps: Deps) -> StdResult<CounterpartyPolicyResponse> {
+    let policy = COUNTERPARTY_POLICY
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    Ok(CounterpartyPolicyResponse { policy })
+}
+
+fn query_allowlist(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AllowlistResponse> {
+    let addresses = list_addresses(deps, ALLOWLIST, start_after, limit)?;
+    Ok(AllowlistResponse { addresses })
+}
+
+fn query_queriers(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<QueriersResponse> {
+    let addresses = list_addresses(deps, QUERIERS, start_after, limit)?;
+    Ok(QueriersResponse { addresses })
+}
+
+fn list_addresses(
+    deps: Deps,
+    addresses: Map<&Addr, Empty>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Vec<String>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after.map(Addr::unchecked);
+    let start = start_after.as_ref().map(Bound::exclusive);
+
+    addresses
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|addr| addr.map(String::from))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ibc::{MAX_PACKET_LIFETIME, MIN_PACKET_LIFETIME};
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
 
     const CREATOR: &str = "creator";
@@ -256,7 +960,11 @@ mod tests {
     #[test]
     fn instantiate_works() {
         let mut deps = mock_dependencies();
-        let msg = InstantiateMsg {};
+        let msg = InstantiateMsg {
+            default_timeout: None,
+            access_policy: None,
+            callback_gas_limit: None,
+        };
         let info = mock_info(CREATOR, &[]);
         let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
         assert_eq!(0, res.messages.len());
@@ -264,4 +972,201 @@ mod tests {
         let admin = query_admin(deps.as_ref()).unwrap();
         assert_eq!(CREATOR, admin.admin.as_str());
     }
+
+    #[test]
+    fn default_timeout_is_configurable() {
+        let mut deps = mock_dependencies();
+
+        // reject timeouts out of range
+        let msg = InstantiateMsg {
+            default_timeout: Some(MAX_PACKET_LIFETIME + 1),
+            access_policy: None,
+            callback_gas_limit: None,
+        };
+        let info = mock_info(CREATOR, &[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTimeout { .. }));
+
+        let msg = InstantiateMsg {
+            default_timeout: Some(600),
+            access_policy: None,
+            callback_gas_limit: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(600, cfg.default_timeout);
+
+        // only the admin can update it
+        let msg = ExecuteMsg::UpdateDefaultTimeout {
+            default_timeout: 7200,
+        };
+        let info = mock_info("someone", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+
+        let info = mock_info(CREATOR, &[]);
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(7200, cfg.default_timeout);
+
+        // and it must still be in range
+        let msg = ExecuteMsg::UpdateDefaultTimeout {
+            default_timeout: MIN_PACKET_LIFETIME - 1,
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTimeout { .. }));
+    }
+
+    #[test]
+    fn access_policy_controls_senders() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            default_timeout: None,
+            access_policy: None,
+            callback_gas_limit: None,
+        };
+        let info = mock_info(CREATOR, &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(AccessPolicy::AdminOnly, cfg.access_policy);
+
+        // register a channel
+        let channel_id = "channel-1";
+        ACCOUNTS
+            .save(deps.as_mut().storage, channel_id, &Default::default())
+            .unwrap();
+
+        let check_balance = |deps: DepsMut, sender: &str| {
+            let msg = ExecuteMsg::CheckRemoteBalance {
+                channel_id: channel_id.into(),
+                timeout_seconds: None,
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), msg)
+        };
+
+        // only the admin by default
+        check_balance(deps.as_mut(), CREATOR).unwrap();
+        let err = check_balance(deps.as_mut(), "alice").unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // only the admin manages the allowlist
+        let msg = ExecuteMsg::UpdateAllowlist {
+            add: vec!["alice".into(), "bob".into()],
+            remove: vec![],
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let msg = ExecuteMsg::UpdateAllowlist {
+            add: vec![],
+            remove: vec!["bob".into()],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let allowlist = query_allowlist(deps.as_ref(), None, None).unwrap();
+        assert_eq!(allowlist.addresses, vec!["alice".to_string()]);
+
+        // the allowlist is ignored unless the policy uses it
+        let err = check_balance(deps.as_mut(), "alice").unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let msg = ExecuteMsg::UpdateAccessPolicy {
+            access_policy: AccessPolicy::Allowlist,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        check_balance(deps.as_mut(), CREATOR).unwrap();
+        check_balance(deps.as_mut(), "alice").unwrap();
+        let err = check_balance(deps.as_mut(), "bob").unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // anyone may send when open
+        let msg = ExecuteMsg::UpdateAccessPolicy {
+            access_policy: AccessPolicy::Open,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let mut res = check_balance(deps.as_mut(), "bob").unwrap();
+        match res.messages.swap_remove(0).msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                let packet: PacketMsg = cosmwasm_std::from_slice(&data).unwrap();
+                assert_eq!(
+                    packet,
+                    PacketMsg::Balances {
+                        sender: Some("bob".into())
+                    }
+                );
+            }
+            o => panic!("Unexpected message: {:?}", o),
+        }
+    }
+
+    #[test]
+    fn ibc_query_checks_auth_and_channel() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            default_timeout: None,
+            access_policy: None,
+            callback_gas_limit: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let ibc_query = |deps: DepsMut, sender: &str, channel_id: &str| {
+            let msg = ExecuteMsg::IbcQuery {
+                channel_id: channel_id.into(),
+                msgs: vec![],
+                callback_id: None,
+                timeout_seconds: None,
+            };
+            execute(deps, mock_env(), mock_info(sender, &[]), msg)
+        };
+
+        // nothing goes to a channel that was never registered
+        let err = ibc_query(deps.as_mut(), CREATOR, "channel-1").unwrap_err();
+        assert_eq!(err, ContractError::UnregisteredChannel("channel-1".into()));
+        ACCOUNTS
+            .save(deps.as_mut().storage, "channel-1", &Default::default())
+            .unwrap();
+
+        // same policy as dispatch
+        ibc_query(deps.as_mut(), CREATOR, "channel-1").unwrap();
+        let err = ibc_query(deps.as_mut(), "alice", "channel-1").unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // only the admin manages the queriers
+        let msg = ExecuteMsg::UpdateQueriers {
+            add: vec!["alice".into()],
+            remove: vec![],
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let queriers = query_queriers(deps.as_ref(), None, None).unwrap();
+        assert_eq!(queriers.addresses, vec!["alice".to_string()]);
+
+        // queriers may query, but not dispatch
+        ibc_query(deps.as_mut(), "alice", "channel-1").unwrap();
+        let msg = ExecuteMsg::SendMsgs {
+            channel_id: "channel-1".into(),
+            msgs: vec![],
+            callback_id: None,
+            timeout_seconds: None,
+            mode: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
 }
@@ -12,9 +12,27 @@ pub enum ContractError {
     #[error("{0}")]
     SimpleIca(#[from] SimpleIcaError),
 
+    #[error("Unauthorized")]
+    Unauthorized {},
+
     #[error("No account for channel {0}")]
     UnregisteredChannel(String),
 
+    #[error("Channel {0} is closed")]
+    ChannelClosed(String),
+
+    #[error("No remote address known for {sender} on channel {channel_id}")]
+    UnknownRemoteAddress { channel_id: String, sender: String },
+
     #[error("remote account changed from {old} to {addr}")]
     RemoteAccountChanged { addr: String, old: String },
+
+    #[error("Timeout must be between {min} and {max} seconds")]
+    InvalidTimeout { min: u64, max: u64 },
+
+    #[error("Invalid reply id")]
+    InvalidReplyId,
+
+    #[error("No failed callback with id {0}")]
+    UnknownCallback(u64),
 }
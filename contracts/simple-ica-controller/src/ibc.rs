@@ -1,39 +1,187 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    from_slice, to_binary, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse,
-    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcMsg, IbcPacketAckMsg,
-    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, StdResult,
+    from_slice, Addr, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannelCloseMsg,
+    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcMsg, IbcOrder, IbcPacketAckMsg,
+    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, Order, StdResult, Storage,
+    SubMsg, Timestamp,
 };
 
+use cw_utils::NativeBalance;
 use simple_ica::{
-    check_order, check_version, BalancesResponse, PacketMsg, ReceiveIcaResponseMsg, StdAck,
-    WhoAmIResponse,
+    connect_settings, open_version, BalancesResponse, MigrateAccountResponse, PacketMsg,
+    ReceiveIcaResponseMsg, ReclaimFundsResponse, StdAck, WhoAmIResponse,
 };
 
 use crate::error::ContractError;
 use crate::msg::LatestQueryResponse;
-use crate::state::{AccountData, ACCOUNTS, LATEST_QUERIES};
+use crate::state::{
+    channel_settings, AccountData, CallbackData, ClosedAccount, ClosedBy, Config, PacketData,
+    PacketStatus, ACCOUNTS, CHANNEL_SETTINGS, CLOSED_ACCOUNTS, CLOSED_SENDER_ACCOUNTS, CONFIG,
+    COUNTERPARTY_POLICY, LATEST_QUERIES, PACKETS, PENDING_PACKETS, SENDER_ACCOUNTS,
+    SENDING_CALLBACK, SENDING_PACKET,
+};
+
+pub const SEND_PACKET_ID: u64 = 1001;
+pub const CALLBACK_ID: u64 = 1002;
+
+/// gas available to each callback, unless configured otherwise
+pub const DEFAULT_CALLBACK_GAS_LIMIT: u64 = 1_000_000;
+
+/// packets live one hour, unless configured otherwise
+pub const DEFAULT_PACKET_LIFETIME: u64 = 60 * 60;
+/// shortest lifetime we accept for a packet (one minute)
+pub const MIN_PACKET_LIFETIME: u64 = 60;
+/// longest lifetime we accept for a packet (one week)
+pub const MAX_PACKET_LIFETIME: u64 = 7 * 24 * 60 * 60;
+
+/// ensures the requested packet lifetime is within the allowed range
+pub fn validate_timeout(seconds: u64) -> Result<u64, ContractError> {
+    if !(MIN_PACKET_LIFETIME..=MAX_PACKET_LIFETIME).contains(&seconds) {
+        return Err(ContractError::InvalidTimeout {
+            min: MIN_PACKET_LIFETIME,
+            max: MAX_PACKET_LIFETIME,
+        });
+    }
+    Ok(seconds)
+}
+
+/// timeout for a new packet, using the caller's lifetime if set and the configured default otherwise
+pub fn packet_timeout(
+    env: &Env,
+    cfg: &Config,
+    timeout_seconds: Option<u64>,
+) -> Result<Timestamp, ContractError> {
+    let seconds = match timeout_seconds {
+        Some(seconds) => validate_timeout(seconds)?,
+        None => cfg.default_timeout,
+    };
+    Ok(env.block.time.plus_seconds(seconds))
+}
+
+/// Creates the submessage to send this packet. We only learn the sequence in the reply,
+/// so we keep the packet info in storage until then (see `reply_send_packet`)
+pub fn send_packet(
+    storage: &mut dyn Storage,
+    env: &Env,
+    sender: Addr,
+    channel_id: String,
+    packet: &PacketMsg,
+    timeout: Timestamp,
+) -> StdResult<SubMsg> {
+    let callback_id = match packet {
+        PacketMsg::Dispatch { callback_id, .. } | PacketMsg::IbcQuery { callback_id, .. } => {
+            callback_id.clone()
+        }
+        _ => None,
+    };
+    let data = PacketData {
+        sender,
+        callback_id,
+        channel_id: channel_id.clone(),
+        kind: packet.into(),
+        status: PacketStatus::Pending,
+        sent_time: env.block.time,
+        timeout_time: timeout,
+        completed_time: None,
+    };
+    SENDING_PACKET.save(storage, &data)?;
 
-// TODO: make configurable?
-/// packets live one hour
-pub const PACKET_LIFETIME: u64 = 60 * 60;
+    let settings = channel_settings(storage, &channel_id)?;
+    let msg = IbcMsg::SendPacket {
+        channel_id,
+        data: packet.encode(settings.version)?,
+        timeout: timeout.into(),
+    };
+    Ok(SubMsg::reply_on_success(msg, SEND_PACKET_ID))
+}
+
+/// Creates the submessage sending a callback to the sender of a packet. If it fails or runs
+/// out of gas, the ack still goes through and the callback is queued (see `reply_callback`)
+pub fn send_callback(
+    storage: &mut dyn Storage,
+    cfg: &Config,
+    callback: CallbackData,
+) -> StdResult<SubMsg> {
+    let msg = ReceiveIcaResponseMsg {
+        id: callback.callback_id.clone(),
+        msg: callback.msg.clone(),
+    }
+    .into_cosmos_msg(&callback.receiver)?;
+    SENDING_CALLBACK.save(storage, &callback)?;
+
+    Ok(SubMsg::reply_always(msg, CALLBACK_ID).with_gas_limit(cfg.callback_gas_limit))
+}
+
+/// Updates the status of a packet we sent. Packets sent before we kept records are ignored
+fn complete_packet(
+    storage: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    sequence: u64,
+    status: PacketStatus,
+) -> StdResult<()> {
+    if let Some(mut data) = PACKETS.may_load(storage, (channel_id, sequence))? {
+        data.status = status;
+        data.completed_time = Some(env.block.time);
+        PACKETS.save(storage, (channel_id, sequence), &data)?;
+        PENDING_PACKETS.remove(storage, (channel_id, sequence));
+    }
+    Ok(())
+}
+
+/// Moves the account of a closed channel to the closed accounts, keeping what we know about it.
+/// The latest query result of the channel is dropped. Closing it again changes nothing
+fn close_account(
+    storage: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    closed_by: ClosedBy,
+) -> StdResult<()> {
+    let closed = |account: AccountData| ClosedAccount {
+        last_update_time: account.last_update_time,
+        remote_addr: account.remote_addr,
+        remote_balance: account.remote_balance,
+        closed_time: env.block.time,
+        closed_by,
+    };
+    if let Some(account) = ACCOUNTS.may_load(storage, channel_id)? {
+        CLOSED_ACCOUNTS.save(storage, channel_id, &closed(account))?;
+        ACCOUNTS.remove(storage, channel_id);
+    }
+    let senders = LATEST_QUERIES
+        .prefix(channel_id)
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for sender in senders {
+        LATEST_QUERIES.remove(storage, (channel_id, &sender));
+    }
+    let accounts = SENDER_ACCOUNTS
+        .prefix(channel_id)
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (sender, account) in accounts {
+        CLOSED_SENDER_ACCOUNTS.save(storage, (channel_id, &sender), &closed(account))?;
+        SENDER_ACCOUNTS.remove(storage, (channel_id, &sender));
+    }
+    Ok(())
+}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-/// enforces ordering and versioing constraints
+/// enforces ordering and versioing constraints, and who may connect
 pub fn ibc_channel_open(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelOpenMsg,
 ) -> Result<Option<Ibc3ChannelOpenResponse>, ContractError> {
-    let channel = msg.channel();
-    check_order(&channel.order)?;
-    check_version(&channel.version)?;
-    if let Some(counter_version) = msg.counterparty_version() {
-        check_version(counter_version)?;
-    }
+    let policy = COUNTERPARTY_POLICY
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    policy.check(msg.channel())?;
+    // ordered channels are only accepted with a version supporting them
+    let version = open_version(&msg)?;
 
-    Ok(None)
+    Ok(Some(Ibc3ChannelOpenResponse { version }))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -42,44 +190,57 @@ pub fn ibc_channel_connect(
     deps: DepsMut,
     env: Env,
     msg: IbcChannelConnectMsg,
-) -> StdResult<IbcBasicResponse> {
+) -> Result<IbcBasicResponse, ContractError> {
     let channel = msg.channel();
     let channel_id = &channel.endpoint.channel_id;
+    let cfg = CONFIG.load(deps.storage)?;
+    let settings = connect_settings(&msg)?;
+    CHANNEL_SETTINGS.save(deps.storage, channel_id, &settings)?;
 
     // create an account holder the channel exists (not found if not registered)
     let data = AccountData::default();
     ACCOUNTS.save(deps.storage, channel_id, &data)?;
 
-    // construct a packet to send
-    let packet = PacketMsg::WhoAmI {};
-    let msg = IbcMsg::SendPacket {
-        channel_id: channel_id.clone(),
-        data: to_binary(&packet)?,
-        timeout: env.block.time.plus_seconds(PACKET_LIFETIME).into(),
+    // construct a packet to send, asking for the admin's account
+    let packet = PacketMsg::WhoAmI {
+        sender: Some(cfg.admin.to_string()),
     };
+    let timeout = env.block.time.plus_seconds(cfg.default_timeout);
+    let msg = send_packet(
+        deps.storage,
+        &env,
+        cfg.admin,
+        channel_id.clone(),
+        &packet,
+        timeout,
+    )?;
 
     Ok(IbcBasicResponse::new()
-        .add_message(msg)
+        .add_submessage(msg)
         .add_attribute("action", "ibc_connect")
-        .add_attribute("channel_id", channel_id))
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("version", settings.version.as_str()))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-/// On closed channel, simply delete the account from our local store
+/// On closed channel, move the account to the closed accounts, noting who closed it
 pub fn ibc_channel_close(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     msg: IbcChannelCloseMsg,
 ) -> StdResult<IbcBasicResponse> {
-    let channel = msg.channel();
-
-    // remove the channel
-    let channel_id = &channel.endpoint.channel_id;
-    ACCOUNTS.remove(deps.storage, channel_id);
+    let closed_by = match msg {
+        IbcChannelCloseMsg::CloseConfirm { .. } => ClosedBy::Counterparty,
+        // CloseInit, the close started on our side
+        _ => ClosedBy::Us,
+    };
+    let channel_id = &msg.channel().endpoint.channel_id;
+    close_account(deps.storage, &env, channel_id, closed_by)?;
 
     Ok(IbcBasicResponse::new()
         .add_attribute("action", "ibc_close")
-        .add_attribute("channel_id", channel_id))
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("closed_by", closed_by.as_str()))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -103,10 +264,19 @@ pub fn ibc_packet_ack(
     // which local channel was this packet send from
     let caller = msg.original_packet.src.channel_id.clone();
     // we need to parse the ack based on our request
-    let original_packet: PacketMsg = from_slice(&msg.original_packet.data)?;
+    let settings = channel_settings(deps.storage, &caller)?;
+    let original_packet = PacketMsg::decode(&msg.original_packet.data, settings.version)?;
     let res: StdAck = from_slice(&msg.acknowledgement.data)?;
 
-    match original_packet {
+    let status = match res {
+        StdAck::Result(_) => PacketStatus::Acknowledged,
+        _ => PacketStatus::Errored,
+    };
+    let sequence = msg.original_packet.sequence;
+    complete_packet(deps.storage, &env, &caller, sequence, status)?;
+    let error_code = res.ica_error().map(|err| err.code);
+
+    let response = match original_packet {
         PacketMsg::Dispatch {
             sender,
             callback_id,
@@ -117,17 +287,37 @@ pub fn ibc_packet_ack(
             callback_id,
             ..
         } => acknowledge_query(deps, env, caller, sender, callback_id, msg),
-        PacketMsg::WhoAmI {} => acknowledge_who_am_i(deps, caller, res),
-        PacketMsg::Balances {} => acknowledge_balances(deps, env, caller, res),
+        PacketMsg::WhoAmI { sender } => {
+            let sender = packet_sender(deps.storage, sender)?;
+            acknowledge_who_am_i(deps, caller, sender, res)
+        }
+        PacketMsg::Balances { sender } => {
+            let sender = packet_sender(deps.storage, sender)?;
+            acknowledge_balances(deps, env, caller, sender, res)
+        }
+        PacketMsg::ReclaimFunds { .. } => acknowledge_reclaim_funds(res),
+        PacketMsg::MigrateAccount { .. } => acknowledge_migrate_account(res),
+    }?;
+    // errors from the host come with a code to match on
+    match error_code {
+        Some(code) => Ok(response.add_attribute("error_code", code.as_str())),
+        None => Ok(response),
+    }
+}
+
+// the sender of a WhoAmI or Balances. Those sent before 0.3.0 have none, and were the admin's
+fn packet_sender(storage: &dyn Storage, sender: Option<String>) -> StdResult<String> {
+    match sender {
+        Some(sender) => Ok(sender),
+        None => Ok(CONFIG.load(storage)?.admin.into()),
     }
 }
 
 // receive PacketMsg::Dispatch response
-#[allow(clippy::unnecessary_wraps)]
 fn acknowledge_dispatch(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
-    _caller: String,
+    caller: String,
     sender: String,
     callback_id: Option<String>,
     ack: IbcPacketAckMsg,
@@ -137,11 +327,16 @@ fn acknowledge_dispatch(
         Some(id) => {
             let msg: StdAck = from_slice(&ack.acknowledgement.data)?;
             // Send IBC packet ack message to another contract
-            let res = res
-                .add_attribute("callback_id", &id)
-                //  In production, you will want to think about gas limits for this callback.
-                .add_message(ReceiveIcaResponseMsg { id, msg }.into_cosmos_msg(sender)?);
-            Ok(res)
+            let cfg = CONFIG.load(deps.storage)?;
+            let callback = CallbackData {
+                receiver: Addr::unchecked(sender),
+                channel_id: caller,
+                sequence: ack.original_packet.sequence,
+                callback_id: id.clone(),
+                msg,
+            };
+            let msg = send_callback(deps.storage, &cfg, callback)?;
+            Ok(res.add_attribute("callback_id", id).add_submessage(msg))
         }
         None => Ok(res),
     }
@@ -160,21 +355,28 @@ fn acknowledge_query(
     // store IBC response for later querying from the smart contract??
     LATEST_QUERIES.save(
         deps.storage,
-        &caller,
+        (&caller, &sender),
         &LatestQueryResponse {
             last_update_time: env.block.time,
             response: msg.clone(),
         },
     )?;
+    let res = IbcBasicResponse::new().add_attribute("action", "acknowledge_ibc_query");
     match callback_id {
         Some(id) => {
             // Send IBC packet ack message to another contract
-            let msg = ReceiveIcaResponseMsg { id, msg }.into_cosmos_msg(sender)?;
-            Ok(IbcBasicResponse::new()
-                .add_attribute("action", "acknowledge_ibc_query")
-                .add_message(msg))
+            let cfg = CONFIG.load(deps.storage)?;
+            let callback = CallbackData {
+                receiver: Addr::unchecked(sender),
+                channel_id: caller,
+                sequence: ack.original_packet.sequence,
+                callback_id: id.clone(),
+                msg,
+            };
+            let msg = send_callback(deps.storage, &cfg, callback)?;
+            Ok(res.add_attribute("callback_id", id).add_submessage(msg))
         }
-        None => Ok(IbcBasicResponse::new().add_attribute("action", "acknowledge_ibc_query")),
+        None => Ok(res),
     }
 }
 
@@ -183,6 +385,7 @@ fn acknowledge_query(
 fn acknowledge_who_am_i(
     deps: DepsMut,
     caller: String,
+    sender: String,
     ack: StdAck,
 ) -> Result<IbcBasicResponse, ContractError> {
     // ignore errors (but mention in log)
@@ -193,20 +396,32 @@ fn acknowledge_who_am_i(
                 .add_attribute("action", "acknowledge_who_am_i")
                 .add_attribute("error", e))
         }
+        // only ever created locally, the host cannot time out a packet
+        StdAck::Timeout {} => {
+            return Ok(IbcBasicResponse::new()
+                .add_attribute("action", "acknowledge_who_am_i")
+                .add_attribute("error", "unexpected timeout ack"))
+        }
     };
 
-    ACCOUNTS.update(deps.storage, &caller, |acct| {
-        match acct {
-            Some(mut acct) => {
-                // set the account the first time
-                if acct.remote_addr.is_none() {
-                    acct.remote_addr = Some(account);
-                }
-                Ok(acct)
-            }
-            None => Err(ContractError::UnregisteredChannel(caller.clone())),
-        }
-    })?;
+    let mut acct = ACCOUNTS
+        .may_load(deps.storage, &caller)?
+        .ok_or_else(|| ContractError::UnregisteredChannel(caller.clone()))?;
+    // the channel account is the first one the host reports (the admin's, from ibc_connect)
+    if acct.remote_addr.is_none() {
+        acct.remote_addr = Some(account.clone());
+        ACCOUNTS.save(deps.storage, &caller, &acct)?;
+    }
+
+    // set the sender's account the first time
+    let key = (caller.as_str(), sender.as_str());
+    let mut sender_acct = SENDER_ACCOUNTS
+        .may_load(deps.storage, key)?
+        .unwrap_or_default();
+    if sender_acct.remote_addr.is_none() {
+        sender_acct.remote_addr = Some(account);
+        SENDER_ACCOUNTS.save(deps.storage, key, &sender_acct)?;
+    }
 
     Ok(IbcBasicResponse::new().add_attribute("action", "acknowledge_who_am_i"))
 }
@@ -216,6 +431,7 @@ fn acknowledge_balances(
     deps: DepsMut,
     env: Env,
     caller: String,
+    sender: String,
     ack: StdAck,
 ) -> Result<IbcBasicResponse, ContractError> {
     // ignore errors (but mention in log)
@@ -226,56 +442,162 @@ fn acknowledge_balances(
                 .add_attribute("action", "acknowledge_balances")
                 .add_attribute("error", e))
         }
+        // only ever created locally, the host cannot time out a packet
+        StdAck::Timeout {} => {
+            return Ok(IbcBasicResponse::new()
+                .add_attribute("action", "acknowledge_balances")
+                .add_attribute("error", "unexpected timeout ack"))
+        }
     };
 
-    ACCOUNTS.update(deps.storage, &caller, |acct| match acct {
-        Some(acct) => {
-            if let Some(old) = acct.remote_addr {
-                if old != account {
-                    return Err(ContractError::RemoteAccountChanged { old, addr: account });
-                }
-            }
-            Ok(AccountData {
-                last_update_time: env.block.time,
-                remote_addr: Some(account),
-                remote_balance: balances,
-            })
+    let acct = ACCOUNTS
+        .may_load(deps.storage, &caller)?
+        .ok_or_else(|| ContractError::UnregisteredChannel(caller.clone()))?;
+    let data = AccountData {
+        last_update_time: env.block.time,
+        remote_addr: Some(account.clone()),
+        remote_balance: balances,
+    };
+
+    let key = (caller.as_str(), sender.as_str());
+    if let Some(old) = SENDER_ACCOUNTS
+        .may_load(deps.storage, key)?
+        .and_then(|acct| acct.remote_addr)
+    {
+        if old != account {
+            return Err(ContractError::RemoteAccountChanged { old, addr: account });
         }
-        None => Err(ContractError::UnregisteredChannel(caller.clone())),
-    })?;
+    }
+    SENDER_ACCOUNTS.save(deps.storage, key, &data)?;
+
+    // other senders' accounts don't touch the channel account
+    match acct.remote_addr {
+        None => ACCOUNTS.save(deps.storage, &caller, &data)?,
+        Some(ref addr) if addr == &account => ACCOUNTS.save(deps.storage, &caller, &data)?,
+        _ => {}
+    }
 
     Ok(IbcBasicResponse::new().add_attribute("action", "acknowledge_balances"))
 }
 
+// receive PacketMsg::ReclaimFunds response
+// the funds are in the sender's account now, we only log it
+fn acknowledge_reclaim_funds(ack: StdAck) -> Result<IbcBasicResponse, ContractError> {
+    let res = IbcBasicResponse::new().add_attribute("action", "acknowledge_reclaim_funds");
+    match ack {
+        StdAck::Result(data) => {
+            let ReclaimFundsResponse { account, amount } = from_slice(&data)?;
+            Ok(res
+                .add_attribute("account", account)
+                .add_attribute("amount", NativeBalance(amount).to_string()))
+        }
+        StdAck::Error(e) => Ok(res.add_attribute("error", e)),
+        StdAck::Timeout {} => Ok(res.add_attribute("error", "unexpected timeout ack")),
+    }
+}
+
+// receive PacketMsg::MigrateAccount response
+// the account runs the new code now, we only log it
+fn acknowledge_migrate_account(ack: StdAck) -> Result<IbcBasicResponse, ContractError> {
+    let res = IbcBasicResponse::new().add_attribute("action", "acknowledge_migrate_account");
+    match ack {
+        StdAck::Result(data) => {
+            let MigrateAccountResponse { account, code_id } = from_slice(&data)?;
+            Ok(res
+                .add_attribute("account", account)
+                .add_attribute("code_id", code_id.to_string()))
+        }
+        StdAck::Error(e) => Ok(res.add_attribute("error", e)),
+        StdAck::Timeout {} => Ok(res.add_attribute("error", "unexpected timeout ack")),
+    }
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-/// we just ignore these now. shall we store some info?
+/// If the original packet requested a callback, we let the sender know it timed out
 pub fn ibc_packet_timeout(
-    _deps: DepsMut,
-    _env: Env,
-    _msg: IbcPacketTimeoutMsg,
-) -> StdResult<IbcBasicResponse> {
-    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout"))
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let (channel_id, sequence) = (&msg.packet.src.channel_id, msg.packet.sequence);
+    let settings = channel_settings(deps.storage, channel_id)?;
+    let original_packet = PacketMsg::decode(&msg.packet.data, settings.version)?;
+    complete_packet(
+        deps.storage,
+        &env,
+        channel_id,
+        sequence,
+        PacketStatus::TimedOut,
+    )?;
+    let mut res = IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout");
+    // a timeout closes an ordered channel, without calling ibc_channel_close
+    if settings.order == IbcOrder::Ordered {
+        close_account(deps.storage, &env, channel_id, ClosedBy::Timeout)?;
+        res = res.add_attribute("channel_closed", channel_id);
+    }
+
+    match original_packet {
+        PacketMsg::Dispatch {
+            sender,
+            callback_id: Some(id),
+            ..
+        }
+        | PacketMsg::IbcQuery {
+            sender,
+            callback_id: Some(id),
+            ..
+        } => {
+            // Send timeout notification to the original sender
+            let cfg = CONFIG.load(deps.storage)?;
+            let callback = CallbackData {
+                receiver: Addr::unchecked(sender),
+                channel_id: channel_id.clone(),
+                sequence,
+                callback_id: id.clone(),
+                msg: StdAck::Timeout {},
+            };
+            let msg = send_callback(deps.storage, &cfg, callback)?;
+            Ok(res.add_attribute("callback_id", id).add_submessage(msg))
+        }
+        _ => Ok(res),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::contract::{execute, instantiate, query};
-    use crate::msg::{AccountResponse, ExecuteMsg, InstantiateMsg, QueryMsg};
+    use crate::contract::{execute, instantiate, query, reply};
+    use crate::msg::{
+        AccountResponse, ChannelSettingsResponse, ClosedAccountInfo, ClosedAccountResponse,
+        ClosedSenderAccountInfo, CounterpartyPolicyResponse, ExecuteMsg, FailedCallbacksResponse,
+        InstantiateMsg, ListAccountsResponse, ListClosedAccountsResponse,
+        ListClosedSenderAccountsResponse, PacketResponse, PendingPacketsResponse, QueryMsg,
+    };
+    use crate::state::{AccessPolicy, PacketKind};
 
     use cosmwasm_std::testing::{
-        mock_dependencies, mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init,
-        mock_ibc_channel_open_try, mock_ibc_packet_ack, mock_info, MockApi, MockQuerier,
-        MockStorage,
+        mock_dependencies, mock_env, mock_ibc_channel_close_confirm, mock_ibc_channel_close_init,
+        mock_ibc_channel_connect_ack, mock_ibc_channel_open_init, mock_ibc_channel_open_try,
+        mock_ibc_packet_ack, mock_ibc_packet_timeout, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        attr, coin, coins, BankMsg, CosmosMsg, Deps, Event, IbcAcknowledgement, OwnedDeps, Reply,
+        ReplyOn, SubMsgResponse, SubMsgResult,
+    };
+    use simple_ica::{
+        CounterpartyPolicy, DispatchMode, DispatchResponse, ErrorCode, IcaError, PortFilter,
+        ProtocolVersion, SimpleIcaError, APP_ORDER, BAD_APP_ORDER, IBC_APP_VERSION,
     };
-    use cosmwasm_std::{coin, coins, BankMsg, CosmosMsg, IbcAcknowledgement, OwnedDeps};
-    use simple_ica::{APP_ORDER, BAD_APP_ORDER, IBC_APP_VERSION};
 
     const CREATOR: &str = "creator";
 
     fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
         let mut deps = mock_dependencies();
-        let msg = InstantiateMsg {};
+        let msg = InstantiateMsg {
+            default_timeout: None,
+            access_policy: None,
+            callback_gas_limit: None,
+        };
         let info = mock_info(CREATOR, &[]);
         let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
         assert_eq!(0, res.messages.len());
@@ -306,16 +628,50 @@ mod tests {
     }
 
     fn who_am_i_response(deps: DepsMut, channel_id: &str, account: impl Into<String>) {
-        let packet = PacketMsg::WhoAmI {};
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(CREATOR.into()),
+        };
         let res = StdAck::success(WhoAmIResponse {
             account: account.into(),
-        });
+        })
+        .unwrap();
         let ack = IbcAcknowledgement::new(res);
         let msg = mock_ibc_packet_ack(channel_id, &packet, ack).unwrap();
         let res = ibc_packet_ack(deps, mock_env(), msg).unwrap();
         assert_eq!(0, res.messages.len());
     }
 
+    // fake the reply to a SendPacket submessage, as the ibc module assigns the sequence
+    fn send_packet_reply(deps: DepsMut, sequence: u64) {
+        let event = Event::new("send_packet")
+            .add_attribute("packet_src_channel", "channel-1234")
+            .add_attribute("packet_sequence", sequence.to_string());
+        let response = Reply {
+            id: SEND_PACKET_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![event],
+                data: None,
+            }),
+        };
+        reply(deps, mock_env(), response).unwrap();
+    }
+
+    fn query_packet(deps: Deps, channel_id: &str, sequence: u64) -> PacketResponse {
+        let q = QueryMsg::Packet {
+            channel_id: channel_id.into(),
+            sequence,
+        };
+        from_slice(&query(deps, mock_env(), q).unwrap()).unwrap()
+    }
+
+    fn query_pending(deps: Deps) -> PendingPacketsResponse {
+        let q = QueryMsg::PendingPackets {
+            start_after: None,
+            limit: None,
+        };
+        from_slice(&query(deps, mock_env(), q).unwrap()).unwrap()
+    }
+
     #[test]
     fn enforce_version_in_handshake() {
         let mut deps = setup();
@@ -330,6 +686,49 @@ mod tests {
         ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
     }
 
+    #[test]
+    fn counterparty_policy_restricts_handshake() {
+        let mut deps = setup();
+        let q = QueryMsg::CounterpartyPolicy {};
+        let res: CounterpartyPolicyResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q).unwrap()).unwrap();
+        assert!(res.policy.is_open());
+
+        // only accept host contracts on one connection
+        let policy = CounterpartyPolicy {
+            connections: Some(vec!["connection-2".into()]),
+            ports: Some(vec![PortFilter::Prefix("wasm.".into())]),
+        };
+        let msg = ExecuteMsg::UpdateCounterpartyPolicy { policy };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("bob", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let mut msg = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        let err = ibc_channel_open(deps.as_mut(), mock_env(), msg.clone()).unwrap_err();
+        assert_eq!(
+            ContractError::SimpleIca(SimpleIcaError::PortNotAllowed("their_port".into())),
+            err
+        );
+        if let IbcChannelOpenMsg::OpenInit { channel } = &mut msg {
+            channel.counterparty_endpoint.port_id = "wasm.host".into();
+        }
+        ibc_channel_open(deps.as_mut(), mock_env(), msg.clone()).unwrap();
+        if let IbcChannelOpenMsg::OpenInit { channel } = &mut msg {
+            channel.connection_id = "connection-3".into();
+        }
+        let err = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(
+            ContractError::SimpleIca(SimpleIcaError::ConnectionNotAllowed("connection-3".into())),
+            err
+        );
+    }
+
     #[test]
     fn proper_handshake_flow() {
         // setup and connect handshake
@@ -362,6 +761,239 @@ mod tests {
         assert_eq!(0, acct.last_update_time.nanos());
     }
 
+    #[test]
+    fn packets_use_channel_version() {
+        let mut deps = setup();
+        let channel_id = "channel-1234";
+        let v3 = ProtocolVersion::V3.as_str();
+
+        // without a version, we propose all we know
+        let msg = mock_ibc_channel_open_init(channel_id, APP_ORDER, "");
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!("simple-ica-v3,simple-ica-v2", res.unwrap().version);
+
+        // the host picks v3
+        let msg = mock_ibc_channel_connect_ack(channel_id, APP_ORDER, v3);
+        let res = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        let q = QueryMsg::ChannelSettings {
+            channel_id: channel_id.into(),
+        };
+        let settings: ChannelSettingsResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q).unwrap()).unwrap();
+        assert_eq!(ProtocolVersion::V3, settings.version);
+        assert_eq!(APP_ORDER, settings.order);
+
+        // so the WhoAmI is sent in v3
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(CREATOR.into()),
+        };
+        let data = match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data.clone(),
+            o => panic!("Unexpected message: {:?}", o),
+        };
+        assert_eq!(packet.encode(ProtocolVersion::V3).unwrap(), data);
+        PacketMsg::decode(&data, ProtocolVersion::V2).unwrap_err();
+
+        // and still read as such when it times out after the channel closed
+        let msg = mock_ibc_channel_close_init(channel_id, APP_ORDER, v3);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        let mut msg = mock_ibc_packet_timeout(channel_id, &packet).unwrap();
+        msg.packet.data = data;
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+    }
+
+    #[test]
+    fn acks_without_sender_are_the_admins() {
+        let mut deps = setup();
+        let channel_id = "channel-1";
+        connect(deps.as_mut(), channel_id);
+
+        // a WhoAmI sent before 0.3.0 names no sender
+        let packet = PacketMsg::WhoAmI { sender: None };
+        let res = StdAck::success(WhoAmIResponse {
+            account: "account-1".into(),
+        })
+        .unwrap();
+        let msg = mock_ibc_packet_ack(channel_id, &packet, IbcAcknowledgement::new(res)).unwrap();
+        ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        let account = SENDER_ACCOUNTS
+            .load(&deps.storage, (channel_id, CREATOR))
+            .unwrap();
+        assert_eq!(Some("account-1".into()), account.remote_addr);
+    }
+
+    #[test]
+    fn ordered_timeout_closes_account() {
+        let mut deps = setup();
+        let channel_id = "channel-1234";
+        let v3 = ProtocolVersion::V3.as_str();
+
+        // ordered channels need v3
+        let msg = mock_ibc_channel_open_init(channel_id, IbcOrder::Ordered, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap_err();
+        let msg = mock_ibc_channel_open_init(channel_id, IbcOrder::Ordered, "");
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(v3, res.unwrap().version);
+        let msg = mock_ibc_channel_connect_ack(channel_id, IbcOrder::Ordered, v3);
+        let res = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        let q = QueryMsg::ChannelSettings {
+            channel_id: channel_id.into(),
+        };
+        let settings: ChannelSettingsResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q).unwrap()).unwrap();
+        assert_eq!(IbcOrder::Ordered, settings.order);
+
+        // the WhoAmI times out, which closes the channel
+        let data = match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data.clone(),
+            o => panic!("Unexpected message: {:?}", o),
+        };
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(CREATOR.into()),
+        };
+        let mut msg = mock_ibc_packet_timeout(channel_id, &packet).unwrap();
+        msg.packet.data = data;
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(3600);
+        let res = ibc_packet_timeout(deps.as_mut(), env.clone(), msg).unwrap();
+        assert!(res.attributes.contains(&attr("channel_closed", channel_id)));
+
+        // the account is moved to the closed accounts
+        ACCOUNTS.load(&deps.storage, channel_id).unwrap_err();
+        let q = QueryMsg::ClosedAccount {
+            channel_id: channel_id.into(),
+        };
+        let acct: ClosedAccountResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q).unwrap()).unwrap();
+        assert_eq!(env.block.time, acct.closed_time);
+        assert_eq!(ClosedBy::Timeout, acct.closed_by);
+
+        // so we send nothing more on it
+        let msg = ExecuteMsg::CheckRemoteBalance {
+            channel_id: channel_id.into(),
+            timeout_seconds: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(ContractError::ChannelClosed(channel_id.into()), err);
+
+        // a later close keeps the first close time
+        let msg = mock_ibc_channel_close_init(channel_id, IbcOrder::Ordered, v3);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        let acct = CLOSED_ACCOUNTS.load(&deps.storage, channel_id).unwrap();
+        assert_eq!(env.block.time, acct.closed_time);
+    }
+
+    #[test]
+    fn closed_accounts_keep_history() {
+        let mut deps = setup();
+        let channels = ["channel-1", "channel-2", "channel-3"];
+        for (i, channel_id) in channels.iter().enumerate() {
+            connect(deps.as_mut(), channel_id);
+            who_am_i_response(deps.as_mut(), channel_id, format!("account-{}", i + 1));
+        }
+        let latest = LatestQueryResponse {
+            last_update_time: Timestamp::from_seconds(1234),
+            response: StdAck::Result(b"result".into()),
+        };
+        LATEST_QUERIES
+            .save(deps.as_mut().storage, ("channel-1", CREATOR), &latest)
+            .unwrap();
+        let alice = AccountData {
+            last_update_time: Timestamp::from_seconds(1234),
+            remote_addr: Some("account-alice".into()),
+            remote_balance: coins(500, "uatom"),
+        };
+        SENDER_ACCOUNTS
+            .save(deps.as_mut().storage, ("channel-1", "alice"), &alice)
+            .unwrap();
+
+        // we close one, the counterparty another
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(60);
+        let msg = mock_ibc_channel_close_init("channel-1", APP_ORDER, IBC_APP_VERSION);
+        let res = ibc_channel_close(deps.as_mut(), env.clone(), msg).unwrap();
+        assert!(res.attributes.contains(&attr("closed_by", "us")));
+        let msg = mock_ibc_channel_close_confirm("channel-3", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), env.clone(), msg).unwrap();
+
+        // only the open one is left, and only the query results of the closed one are gone
+        let accounts: ListAccountsResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap())
+                .unwrap();
+        assert_eq!(1, accounts.accounts.len());
+        assert_eq!("channel-2", accounts.accounts[0].channel_id);
+        assert!(!LATEST_QUERIES.has(&deps.storage, ("channel-1", CREATOR)));
+        assert!(!SENDER_ACCOUNTS.has(&deps.storage, ("channel-1", CREATOR)));
+        assert!(SENDER_ACCOUNTS.has(&deps.storage, ("channel-2", CREATOR)));
+        let msg = ExecuteMsg::CheckRemoteBalance {
+            channel_id: "channel-1".into(),
+            timeout_seconds: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(ContractError::ChannelClosed("channel-1".into()), err);
+
+        // the closed ones are listed a page at a time
+        let list = |deps: Deps, start_after: Option<&str>| -> ListClosedAccountsResponse {
+            let q = QueryMsg::ListClosedAccounts {
+                start_after: start_after.map(String::from),
+                limit: Some(1),
+            };
+            from_slice(&query(deps, mock_env(), q).unwrap()).unwrap()
+        };
+        let page = list(deps.as_ref(), None);
+        assert_eq!(
+            vec![ClosedAccountInfo {
+                channel_id: "channel-1".into(),
+                last_update_time: Timestamp::from_nanos(0),
+                remote_addr: Some("account-1".into()),
+                remote_balance: vec![],
+                closed_time: env.block.time,
+                closed_by: ClosedBy::Us,
+            }],
+            page.accounts
+        );
+        let page = list(deps.as_ref(), Some("channel-1"));
+        assert_eq!("channel-3", page.accounts[0].channel_id);
+        assert_eq!(Some("account-3".into()), page.accounts[0].remote_addr);
+        assert_eq!(ClosedBy::Counterparty, page.accounts[0].closed_by);
+        let page = list(deps.as_ref(), Some("channel-3"));
+        assert_eq!(0, page.accounts.len());
+
+        // and so are the accounts of their senders
+        let list_senders = |deps: Deps,
+                            channel_id: &str,
+                            start_after: Option<&str>|
+         -> ListClosedSenderAccountsResponse {
+            let q = QueryMsg::ListClosedSenderAccounts {
+                channel_id: channel_id.into(),
+                start_after: start_after.map(String::from),
+                limit: Some(1),
+            };
+            from_slice(&query(deps, mock_env(), q).unwrap()).unwrap()
+        };
+        let page = list_senders(deps.as_ref(), "channel-1", None);
+        assert_eq!(
+            vec![ClosedSenderAccountInfo {
+                sender: "alice".into(),
+                last_update_time: Timestamp::from_seconds(1234),
+                remote_addr: Some("account-alice".into()),
+                remote_balance: coins(500, "uatom"),
+                closed_time: env.block.time,
+                closed_by: ClosedBy::Us,
+            }],
+            page.accounts
+        );
+        let page = list_senders(deps.as_ref(), "channel-1", Some("alice"));
+        assert_eq!(CREATOR, page.accounts[0].sender);
+        assert_eq!(Some("account-1".into()), page.accounts[0].remote_addr);
+        let page = list_senders(deps.as_ref(), "channel-1", Some(CREATOR));
+        assert_eq!(0, page.accounts.len());
+        let page = list_senders(deps.as_ref(), "channel-3", None);
+        assert_eq!(ClosedBy::Counterparty, page.accounts[0].closed_by);
+        let page = list_senders(deps.as_ref(), "channel-2", None);
+        assert_eq!(0, page.accounts.len());
+    }
+
     #[test]
     fn dispatch_message_send_and_ack() {
         let channel_id = "channel-1234";
@@ -384,6 +1016,8 @@ mod tests {
             channel_id: channel_id.into(),
             msgs: msgs_to_dispatch,
             callback_id: None,
+            timeout_seconds: None,
+            mode: None,
         };
         let info = mock_info(CREATOR, &[]);
         let mut res = execute(deps.as_mut(), mock_env(), info, handle_msg).unwrap();
@@ -392,7 +1026,8 @@ mod tests {
             CosmosMsg::Ibc(IbcMsg::SendPacket {
                 channel_id, data, ..
             }) => {
-                let ack = IbcAcknowledgement::new(StdAck::success(&()));
+                #[allow(clippy::needless_borrows_for_generic_args)]
+                let ack = IbcAcknowledgement::new(StdAck::success(&()).unwrap());
                 let mut msg = mock_ibc_packet_ack(&channel_id, &1u32, ack).unwrap();
                 msg.original_packet.data = data;
                 msg
@@ -405,6 +1040,373 @@ mod tests {
         assert_eq!(vec![("action", "acknowledge_dispatch")], res.attributes)
     }
 
+    #[test]
+    fn packet_timeout_per_call() {
+        let channel_id = "channel-1234";
+
+        // init contract and connect
+        let mut deps = setup();
+        connect(deps.as_mut(), channel_id);
+
+        let timeout_for = |deps: DepsMut, timeout_seconds: Option<u64>| {
+            let msg = ExecuteMsg::CheckRemoteBalance {
+                channel_id: channel_id.into(),
+                timeout_seconds,
+            };
+            let info = mock_info(CREATOR, &[]);
+            let res = execute(deps, mock_env(), info, msg)?;
+            match &res.messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket { timeout, .. }) => Ok(timeout.timestamp()),
+                o => panic!("Unexpected message: {:?}", o),
+            }
+        };
+
+        // the default is used if not set
+        let now = mock_env().block.time;
+        let timeout = timeout_for(deps.as_mut(), None).unwrap();
+        assert_eq!(timeout, Some(now.plus_seconds(DEFAULT_PACKET_LIFETIME)));
+
+        // the caller can set their own
+        let timeout = timeout_for(deps.as_mut(), Some(120)).unwrap();
+        assert_eq!(timeout, Some(now.plus_seconds(120)));
+
+        // as long as it is in range
+        let err = timeout_for(deps.as_mut(), Some(MIN_PACKET_LIFETIME - 1)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTimeout { .. }));
+        let err = timeout_for(deps.as_mut(), Some(MAX_PACKET_LIFETIME + 1)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTimeout { .. }));
+    }
+
+    #[test]
+    fn packet_registry_tracks_status() {
+        let channel_id = "channel-1234";
+
+        let mut deps = setup();
+        connect(deps.as_mut(), channel_id);
+        // the WhoAmI packet is recorded as soon as we know the sequence
+        send_packet_reply(deps.as_mut(), 28);
+        let packet = query_packet(deps.as_ref(), channel_id, 28);
+        assert_eq!(packet.kind, PacketKind::WhoAmI);
+        assert_eq!(packet.sender, CREATOR);
+        assert_eq!(packet.status, PacketStatus::Pending);
+
+        // dispatch a message
+        let msg = ExecuteMsg::SendMsgs {
+            channel_id: channel_id.into(),
+            msgs: vec![],
+            callback_id: Some("dispatch".into()),
+            timeout_seconds: None,
+            mode: None,
+        };
+        let info = mock_info(CREATOR, &[]);
+        let mut res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(SEND_PACKET_ID, res.messages[0].id);
+        send_packet_reply(deps.as_mut(), 29);
+
+        let packet = query_packet(deps.as_ref(), channel_id, 29);
+        assert_eq!(
+            packet,
+            PacketResponse {
+                sender: CREATOR.into(),
+                callback_id: Some("dispatch".into()),
+                channel_id: channel_id.into(),
+                kind: PacketKind::Dispatch,
+                status: PacketStatus::Pending,
+                sent_time: mock_env().block.time,
+                timeout_time: mock_env().block.time.plus_seconds(DEFAULT_PACKET_LIFETIME),
+                completed_time: None,
+            }
+        );
+        let pending = query_pending(deps.as_ref());
+        assert_eq!(2, pending.packets.len());
+        assert_eq!(28, pending.packets[0].sequence);
+        assert_eq!(29, pending.packets[1].sequence);
+
+        // paginate
+        let q = QueryMsg::PendingPackets {
+            start_after: Some((channel_id.into(), 28)),
+            limit: Some(1),
+        };
+        let page: PendingPacketsResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q).unwrap()).unwrap();
+        assert_eq!(1, page.packets.len());
+        assert_eq!(29, page.packets[0].sequence);
+
+        // the dispatch is acknowledged (mock packets use sequence 29)
+        let msg = match res.messages.swap_remove(0).msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                let ack = IbcAcknowledgement::new(StdAck::success(()).unwrap());
+                let mut msg = mock_ibc_packet_ack(channel_id, &1u32, ack).unwrap();
+                msg.original_packet.data = data;
+                msg
+            }
+            o => panic!("Unexpected message: {:?}", o),
+        };
+        ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        let packet = query_packet(deps.as_ref(), channel_id, 29);
+        assert_eq!(packet.status, PacketStatus::Acknowledged);
+        assert_eq!(packet.completed_time, Some(mock_env().block.time));
+
+        // and the WhoAmI times out
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(CREATOR.into()),
+        };
+        let mut msg = mock_ibc_packet_timeout(channel_id, &packet).unwrap();
+        msg.packet.sequence = 28;
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        let packet = query_packet(deps.as_ref(), channel_id, 28);
+        assert_eq!(packet.status, PacketStatus::TimedOut);
+
+        // nothing pending anymore
+        let pending = query_pending(deps.as_ref());
+        assert_eq!(0, pending.packets.len());
+    }
+
+    #[test]
+    fn missing_sequence_skips_record() {
+        let channel_id = "channel-1234";
+        let mut deps = setup();
+        connect(deps.as_mut(), channel_id);
+
+        // the packet is out, so the reply must not revert the send
+        let response = Reply {
+            id: SEND_PACKET_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        let res = reply(deps.as_mut(), mock_env(), response).unwrap();
+        assert!(res.attributes.contains(&attr("packet_record", "skipped")));
+        assert_eq!(0, query_pending(deps.as_ref()).packets.len());
+        assert_eq!(None, SENDING_PACKET.may_load(&deps.storage).unwrap());
+    }
+
+    #[test]
+    fn dispatch_timeout_sends_callback() {
+        let mut deps = setup();
+        let channel_id = "channel-1234";
+        let sender = "contract-1";
+
+        // timeout without a callback_id, no messages
+        let packet = PacketMsg::Dispatch {
+            sender: sender.into(),
+            msgs: vec![],
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+        let msg = mock_ibc_packet_timeout(channel_id, &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // with a callback_id, the sender is informed of the timeout
+        let packet = PacketMsg::IbcQuery {
+            sender: sender.into(),
+            msgs: vec![],
+            callback_id: Some("my-query".into()),
+        };
+        let msg = mock_ibc_packet_timeout(channel_id, &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let expected: CosmosMsg = ReceiveIcaResponseMsg {
+            id: "my-query".into(),
+            msg: StdAck::Timeout {},
+        }
+        .into_cosmos_msg(sender)
+        .unwrap();
+        assert_eq!(expected, res.messages[0].msg);
+    }
+
+    #[test]
+    fn failed_callbacks_are_queued() {
+        let mut deps = setup();
+        let channel_id = "channel-1234";
+        let receiver = "contract-1";
+        connect(deps.as_mut(), channel_id);
+
+        // the callback is limited, and always replies to clear the pending callback
+        let packet = PacketMsg::Dispatch {
+            sender: receiver.into(),
+            msgs: vec![],
+            callback_id: Some("send".into()),
+            mode: DispatchMode::Atomic,
+        };
+        let ack = StdAck::success(DispatchResponse::default()).unwrap();
+        let msg =
+            mock_ibc_packet_ack(channel_id, &packet, IbcAcknowledgement::new(ack.clone())).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(CALLBACK_ID, res.messages[0].id);
+        assert_eq!(ReplyOn::Always, res.messages[0].reply_on);
+        assert_eq!(Some(DEFAULT_CALLBACK_GAS_LIMIT), res.messages[0].gas_limit);
+
+        // a callback that goes through is not kept
+        let reply_msg = Reply {
+            id: CALLBACK_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        assert_eq!(None, SENDING_CALLBACK.may_load(&deps.storage).unwrap());
+
+        // the next one fails
+        let msg = mock_ibc_packet_ack(channel_id, &packet, IbcAcknowledgement::new(ack)).unwrap();
+        ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        // it fails
+        let reply_msg = Reply {
+            id: CALLBACK_ID,
+            result: SubMsgResult::Err("out of gas".into()),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let query_msg = QueryMsg::FailedCallbacks {
+            receiver: receiver.into(),
+            start_after: None,
+            limit: None,
+        };
+        let failed: FailedCallbacksResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap()).unwrap();
+        assert_eq!(1, failed.callbacks.len());
+        let callback = &failed.callbacks[0];
+        assert_eq!("send", callback.callback_id);
+        assert_eq!("out of gas", callback.error);
+        assert_eq!(channel_id, callback.channel_id);
+
+        // only the receiver can retry it, without a gas limit this time
+        let retry = ExecuteMsg::RetryCallback {
+            id: callback.id,
+            drop: false,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            retry.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::UnknownCallback(callback.id));
+        let res = execute(deps.as_mut(), mock_env(), mock_info(receiver, &[]), retry).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(None, res.messages[0].gas_limit);
+        let expected: CosmosMsg = ReceiveIcaResponseMsg {
+            id: "send".into(),
+            msg: callback.msg.clone(),
+        }
+        .into_cosmos_msg(receiver)
+        .unwrap();
+        assert_eq!(expected, res.messages[0].msg);
+        let failed: FailedCallbacksResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+        assert_eq!(0, failed.callbacks.len());
+
+        // a timeout callback that fails can be dropped
+        let msg = mock_ibc_packet_timeout(channel_id, &packet).unwrap();
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        let reply_msg = Reply {
+            id: CALLBACK_ID,
+            result: SubMsgResult::Err("unknown variant".into()),
+        };
+        reply(deps.as_mut(), mock_env(), reply_msg).unwrap();
+        let drop = ExecuteMsg::RetryCallback { id: 2, drop: true };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(receiver, &[]), drop).unwrap();
+        assert_eq!(0, res.messages.len());
+        let drop = ExecuteMsg::RetryCallback { id: 2, drop: true };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(receiver, &[]), drop).unwrap_err();
+        assert_eq!(err, ContractError::UnknownCallback(2));
+    }
+
+    #[test]
+    fn error_acks_report_their_code() {
+        let channel_id = "channel-1234";
+
+        let mut deps = setup();
+        connect(deps.as_mut(), channel_id);
+
+        let packet = PacketMsg::Balances {
+            sender: Some("alice".into()),
+        };
+        let err = IcaError::new(
+            ErrorCode::UnregisteredAccount,
+            "No account for sender alice",
+        );
+        let ack = IbcAcknowledgement::new(err.ack());
+        let msg = mock_ibc_packet_ack(channel_id, &packet, ack).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "acknowledge_balances"),
+                attr(
+                    "error",
+                    "[unregistered_account] No account for sender alice"
+                ),
+                attr("error_code", "unregistered_account"),
+            ]
+        );
+
+        // errors without a code, as from older hosts, are still reported
+        let ack = IbcAcknowledgement::new(StdAck::fail("out of gas".to_string()));
+        let msg = mock_ibc_packet_ack(channel_id, &packet, ack).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(res.attributes[2], attr("error_code", "other"));
+    }
+
+    #[test]
+    fn accounts_are_tracked_per_sender() {
+        let channel_id = "channel-1234";
+
+        let mut deps = setup();
+        connect(deps.as_mut(), channel_id);
+        who_am_i_response(deps.as_mut(), channel_id, "account-admin");
+
+        let balances_ack = |deps: DepsMut, sender: &str, account: &str| {
+            let packet = PacketMsg::Balances {
+                sender: Some(sender.into()),
+            };
+            let res = StdAck::success(BalancesResponse {
+                account: account.into(),
+                balances: coins(100, "uatom"),
+            })
+            .unwrap();
+            let msg =
+                mock_ibc_packet_ack(channel_id, &packet, IbcAcknowledgement::new(res)).unwrap();
+            ibc_packet_ack(deps, mock_env(), msg)
+        };
+        let sender_account = |deps: Deps, sender: &str| -> AccountResponse {
+            let q = QueryMsg::SenderAccount {
+                channel_id: channel_id.into(),
+                sender: sender.into(),
+            };
+            from_slice(&query(deps, mock_env(), q).unwrap()).unwrap()
+        };
+
+        // another sender's balances don't touch the channel account
+        balances_ack(deps.as_mut(), "alice", "account-alice").unwrap();
+        let alice = sender_account(deps.as_ref(), "alice");
+        assert_eq!(alice.remote_addr.unwrap(), "account-alice");
+        assert_eq!(alice.remote_balance, coins(100, "uatom"));
+        let q = QueryMsg::Account {
+            channel_id: channel_id.into(),
+        };
+        let acct: AccountResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q.clone()).unwrap()).unwrap();
+        assert_eq!(acct.remote_addr.unwrap(), "account-admin");
+        assert!(acct.remote_balance.is_empty());
+
+        // the admin's balances do
+        balances_ack(deps.as_mut(), CREATOR, "account-admin").unwrap();
+        let acct: AccountResponse =
+            from_slice(&query(deps.as_ref(), mock_env(), q).unwrap()).unwrap();
+        assert_eq!(acct.remote_balance, coins(100, "uatom"));
+        let admin = sender_account(deps.as_ref(), CREATOR);
+        assert_eq!(admin.remote_addr.unwrap(), "account-admin");
+
+        // a sender's account cannot change
+        let err = balances_ack(deps.as_mut(), "alice", "account-other").unwrap_err();
+        assert!(matches!(err, ContractError::RemoteAccountChanged { .. }));
+    }
+
     #[test]
     fn send_remote_funds() {
         let reflect_channel_id = "channel-1234";
@@ -422,6 +1424,7 @@ mod tests {
         let msg = ExecuteMsg::SendFunds {
             ica_channel_id: "random-channel".into(),
             transfer_channel_id: transfer_channel_id.into(),
+            timeout_seconds: None,
         };
         let info = mock_info(CREATOR, &coins(12344, "utrgd"));
         execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
@@ -430,6 +1433,7 @@ mod tests {
         let msg = ExecuteMsg::SendFunds {
             ica_channel_id: reflect_channel_id.into(),
             transfer_channel_id: transfer_channel_id.into(),
+            timeout_seconds: None,
         };
         let info = mock_info(CREATOR, &[]);
         execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
@@ -438,6 +1442,7 @@ mod tests {
         let msg = ExecuteMsg::SendFunds {
             ica_channel_id: reflect_channel_id.into(),
             transfer_channel_id: transfer_channel_id.into(),
+            timeout_seconds: None,
         };
         let info = mock_info(CREATOR, &coins(12344, "utrgd"));
         let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -457,5 +1462,44 @@ mod tests {
             }
             o => panic!("unexpected message: {:?}", o),
         }
+
+        // anyone may send, but only to their own account
+        let msg = ExecuteMsg::UpdateAccessPolicy {
+            access_policy: AccessPolicy::Open,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let send_funds = ExecuteMsg::SendFunds {
+            ica_channel_id: reflect_channel_id.into(),
+            transfer_channel_id: transfer_channel_id.into(),
+            timeout_seconds: None,
+        };
+        let info = mock_info("alice", &coins(500, "utrgd"));
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), send_funds.clone()).unwrap_err();
+        assert_eq!(
+            ContractError::UnknownRemoteAddress {
+                channel_id: reflect_channel_id.into(),
+                sender: "alice".into()
+            },
+            err
+        );
+
+        // once alice's account is known, the funds go there
+        let packet = PacketMsg::WhoAmI {
+            sender: Some("alice".into()),
+        };
+        let res = StdAck::success(WhoAmIResponse {
+            account: "account-alice".into(),
+        })
+        .unwrap();
+        let ack =
+            mock_ibc_packet_ack(reflect_channel_id, &packet, IbcAcknowledgement::new(res)).unwrap();
+        ibc_packet_ack(deps.as_mut(), mock_env(), ack).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), info, send_funds).unwrap();
+        match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::Transfer { to_address, .. }) => {
+                assert_eq!("account-alice", to_address.as_str())
+            }
+            o => panic!("unexpected message: {:?}", o),
+        }
     }
 }
@@ -0,0 +1,139 @@
+use cosmwasm_std::{Addr, Order, StdResult, Storage};
+use cw2::CONTRACT;
+use cw_storage_plus::Item;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use simple_ica::ensure_upgrade;
+
+use crate::contract::{CONTRACT_NAME, CONTRACT_VERSION};
+use crate::error::ContractError;
+use crate::ibc::{DEFAULT_CALLBACK_GAS_LIMIT, DEFAULT_PACKET_LIFETIME};
+use crate::state::{AccessPolicy, AccountData, Config, ACCOUNTS, CONFIG, SENDER_ACCOUNTS};
+
+/// Config of 0.2.0, before it tracked the contract version
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigV0_2 {
+    pub admin: Addr,
+}
+
+pub const CONFIG_V0_2: Item<ConfigV0_2> = Item::new("config");
+
+/// 0.2.0 did not store a contract version, so a missing one means the 0.2.0 layout
+pub const LEGACY_VERSION: Version = Version::new(0, 2, 0);
+
+/// The version we migrate from, failing unless it is an older or equal version of this contract
+pub fn previous_version(storage: &dyn Storage) -> Result<Version, ContractError> {
+    match CONTRACT.may_load(storage)? {
+        Some(previous) => Ok(ensure_upgrade(&previous, CONTRACT_NAME, CONTRACT_VERSION)?),
+        None => Ok(LEGACY_VERSION),
+    }
+}
+
+/// Moves the 0.2.0 layout to the current one. Back then only the admin could send packets,
+/// so the account of each channel becomes the admin's account.
+/// Packets still in flight were not tracked and will not get callbacks.
+pub fn migrate_legacy(storage: &mut dyn Storage) -> StdResult<()> {
+    let ConfigV0_2 { admin } = CONFIG_V0_2.load(storage)?;
+
+    let accounts = ACCOUNTS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(String, AccountData)>>>()?;
+    for (channel_id, account) in accounts {
+        SENDER_ACCOUNTS.save(storage, (&channel_id, admin.as_str()), &account)?;
+    }
+
+    let cfg = Config {
+        admin,
+        default_timeout: DEFAULT_PACKET_LIFETIME,
+        access_policy: AccessPolicy::AdminOnly,
+        callback_gas_limit: DEFAULT_CALLBACK_GAS_LIMIT,
+    };
+    CONFIG.save(storage, &cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{instantiate, migrate};
+    use crate::msg::{InstantiateMsg, MigrateMsg};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coins, Timestamp};
+    use cw2::{get_contract_version, set_contract_version};
+    use simple_ica::SimpleIcaError;
+
+    const ADMIN: &str = "admin";
+
+    #[test]
+    fn migrate_from_legacy_layout() {
+        let mut deps = mock_dependencies();
+        let legacy = ConfigV0_2 {
+            admin: Addr::unchecked(ADMIN),
+        };
+        CONFIG_V0_2.save(deps.as_mut().storage, &legacy).unwrap();
+        let account = AccountData {
+            last_update_time: Timestamp::from_seconds(1234),
+            remote_addr: Some("wasm1remote".to_string()),
+            remote_balance: coins(100, "uatom"),
+        };
+        ACCOUNTS
+            .save(deps.as_mut().storage, "channel-1", &account)
+            .unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        // config gets the defaults of the old behaviour
+        let cfg = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(Addr::unchecked(ADMIN), cfg.admin);
+        assert_eq!(DEFAULT_PACKET_LIFETIME, cfg.default_timeout);
+        assert_eq!(AccessPolicy::AdminOnly, cfg.access_policy);
+        assert_eq!(DEFAULT_CALLBACK_GAS_LIMIT, cfg.callback_gas_limit);
+
+        // the channel account now belongs to the admin, and is still the channel account
+        let sender_account = SENDER_ACCOUNTS
+            .load(&deps.storage, ("channel-1", ADMIN))
+            .unwrap();
+        assert_eq!(account, sender_account);
+        assert_eq!(account, ACCOUNTS.load(&deps.storage, "channel-1").unwrap());
+
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(CONTRACT_NAME, version.contract);
+        assert_eq!(CONTRACT_VERSION, version.version);
+    }
+
+    #[test]
+    fn migrate_checks_version() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            default_timeout: None,
+            access_policy: None,
+            callback_gas_limit: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+
+        // same version is fine, and leaves the state alone
+        let cfg = CONFIG.load(&deps.storage).unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(cfg, CONFIG.load(&deps.storage).unwrap());
+
+        // but not a newer one
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SimpleIca(SimpleIcaError::CannotDowngrade {
+                previous: "99.0.0".to_string(),
+                current: CONTRACT_VERSION.to_string()
+            })
+        );
+
+        // nor another contract
+        set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SimpleIca(SimpleIcaError::WrongContract(
+                "crates.io:cw20-base".to_string()
+            ))
+        );
+    }
+}
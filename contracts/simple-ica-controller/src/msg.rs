@@ -1,21 +1,52 @@
-use cosmwasm_std::{Coin, CosmosMsg, Empty, QueryRequest, Timestamp};
+use cosmwasm_std::{Binary, Coin, CosmosMsg, Empty, IbcOrder, QueryRequest, Timestamp};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use simple_ica::StdAck;
+use simple_ica::{CounterpartyPolicy, DispatchMode, ProtocolVersion, StdAck};
 
-use crate::state::AccountData;
+use crate::state::{
+    AccessPolicy, AccountData, ClosedAccount, ClosedBy, FailedCallback, PacketData, PacketKind,
+    PacketStatus,
+};
 
-/// This needs no info. Owner of the contract is whoever signed the InstantiateMsg.
+/// Owner of the contract is whoever signed the InstantiateMsg.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub struct InstantiateMsg {}
+pub struct InstantiateMsg {
+    /// Lifetime of packets in seconds, if the caller doesn't set one. Defaults to one hour
+    pub default_timeout: Option<u64>,
+    /// Who may send packets. Defaults to only the admin
+    pub access_policy: Option<AccessPolicy>,
+    /// Gas available to each callback. Defaults to one million
+    pub callback_gas_limit: Option<u64>,
+}
+
+/// Contracts deployed before we tracked versions get the defaults of `InstantiateMsg`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrateMsg {}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     /// Changes the admin
-    UpdateAdmin {
-        admin: String,
+    UpdateAdmin { admin: String },
+    /// Changes the packet lifetime used when the caller doesn't set one
+    UpdateDefaultTimeout { default_timeout: u64 },
+    /// Changes who may send packets
+    UpdateAccessPolicy { access_policy: AccessPolicy },
+    /// Changes the gas available to each callback
+    UpdateCallbackGasLimit { callback_gas_limit: u64 },
+    /// Adds and removes addresses on the allowlist used by `AccessPolicy::Allowlist`
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Sets who may open channels to us. Channels that are already open are not affected
+    UpdateCounterpartyPolicy { policy: CounterpartyPolicy },
+    /// Adds and removes addresses that may send `IbcQuery` packets, but nothing else
+    UpdateQueriers {
+        add: Vec<String>,
+        remove: Vec<String>,
     },
     SendMsgs {
         channel_id: String,
@@ -23,15 +54,29 @@ pub enum ExecuteMsg {
         msgs: Vec<CosmosMsg<Empty>>,
         /// If set, the original caller will get a callback with of the result, along with this id
         callback_id: Option<String>,
+        /// Lifetime of the packet in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
+        /// Whether the messages may succeed on their own. Atomic if not set
+        mode: Option<DispatchMode>,
+    },
+    /// Sends a `WhoAmI` for the caller, so the host creates its account and we learn the address
+    RegisterAccount {
+        channel_id: String,
+        /// Lifetime of the packet in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
     },
     CheckRemoteBalance {
         channel_id: String,
+        /// Lifetime of the packet in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
     },
     IbcQuery {
         channel_id: String,
         msgs: Vec<QueryRequest<Empty>>,
         /// If set, the original caller will get a callback with of the result, along with this id
         callback_id: Option<String>,
+        /// Lifetime of the packet in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
     },
     /// If you sent funds to this contract, it will attempt to ibc transfer them
     /// to the account on the remote side of this channel.
@@ -43,7 +88,31 @@ pub enum ExecuteMsg {
         /// port and handled by a different module.
         /// It should connect to the same chain as the ica_channel_id does
         transfer_channel_id: String,
+        /// Lifetime of the transfer in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
+    },
+    /// Asks the host to move the funds it rescued from the caller's account on a closed
+    /// channel into the caller's account on `channel_id`
+    ReclaimFunds {
+        channel_id: String,
+        /// The closed channel, as the host knows it (see the host's `ListRescuedFunds`)
+        closed_channel_id: String,
+        /// Lifetime of the packet in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
+    },
+    /// Asks the host to migrate the caller's account on `channel_id` to another code.
+    /// The host only allows the codes it chose
+    MigrateAccount {
+        channel_id: String,
+        code_id: u64,
+        /// The migrate message of the new code
+        msg: Binary,
+        /// Lifetime of the packet in seconds. Uses the default timeout if not set
+        timeout_seconds: Option<u64>,
     },
+    /// Sends one of the caller's failed callbacks again, without a gas limit.
+    /// If `drop` is set, it is removed from the queue instead.
+    RetryCallback { id: u64, drop: bool },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -51,12 +120,72 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     // Returns current admin
     Admin {},
+    // Returns admin, default timeout and access policy
+    Config {},
+    // Returns who may open channels to us
+    CounterpartyPolicy {},
+    // Lists addresses that may send packets under the allowlist policy
+    Allowlist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Lists addresses that may send query packets
+    Queriers {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
     // Shows all open accounts (incl. remote info)
     ListAccounts {},
     // Get account for one channel
-    Account { channel_id: String },
-    // Get latest query
-    LatestQueryResult { channel_id: String },
+    Account {
+        channel_id: String,
+    },
+    // Lists the accounts of closed channels, as we last knew them
+    ListClosedAccounts {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Get the account of one closed channel
+    ClosedAccount {
+        channel_id: String,
+    },
+    // Lists the remote accounts of the senders on a closed channel, as we last knew them
+    ListClosedSenderAccounts {
+        channel_id: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Get the protocol version and order agreed on this channel
+    ChannelSettings {
+        channel_id: String,
+    },
+    // Get the remote account of one sender on this channel
+    SenderAccount {
+        channel_id: String,
+        sender: String,
+    },
+    // Get the result of the latest query this sender made on the channel
+    LatestQueryResult {
+        channel_id: String,
+        sender: String,
+    },
+    // Get the status of one packet we sent
+    Packet {
+        channel_id: String,
+        sequence: u64,
+    },
+    // Lists all packets that were neither acknowledged nor timed out yet
+    PendingPackets {
+        /// (channel_id, sequence) of the last packet on the previous page
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    // Lists the callbacks to this receiver that failed and can be retried
+    FailedCallbacks {
+        receiver: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -64,6 +193,35 @@ pub struct AdminResponse {
     pub admin: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub admin: String,
+    pub default_timeout: u64,
+    pub access_policy: AccessPolicy,
+    pub callback_gas_limit: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CounterpartyPolicyResponse {
+    pub policy: CounterpartyPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllowlistResponse {
+    pub addresses: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueriersResponse {
+    pub addresses: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelSettingsResponse {
+    pub version: ProtocolVersion,
+    pub order: IbcOrder,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ListAccountsResponse {
     pub accounts: Vec<AccountInfo>,
@@ -117,3 +275,172 @@ impl From<AccountData> for AccountResponse {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClosedAccountResponse {
+    /// last block balance was updated before the channel closed (0 is never)
+    pub last_update_time: Timestamp,
+    pub remote_addr: Option<String>,
+    pub remote_balance: Vec<Coin>,
+    pub closed_time: Timestamp,
+    pub closed_by: ClosedBy,
+}
+
+impl From<ClosedAccount> for ClosedAccountResponse {
+    fn from(input: ClosedAccount) -> Self {
+        ClosedAccountResponse {
+            last_update_time: input.last_update_time,
+            remote_addr: input.remote_addr,
+            remote_balance: input.remote_balance,
+            closed_time: input.closed_time,
+            closed_by: input.closed_by,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClosedAccountInfo {
+    pub channel_id: String,
+    /// last block balance was updated before the channel closed (0 is never)
+    pub last_update_time: Timestamp,
+    pub remote_addr: Option<String>,
+    pub remote_balance: Vec<Coin>,
+    pub closed_time: Timestamp,
+    pub closed_by: ClosedBy,
+}
+
+impl ClosedAccountInfo {
+    pub fn convert(channel_id: String, input: ClosedAccount) -> Self {
+        ClosedAccountInfo {
+            channel_id,
+            last_update_time: input.last_update_time,
+            remote_addr: input.remote_addr,
+            remote_balance: input.remote_balance,
+            closed_time: input.closed_time,
+            closed_by: input.closed_by,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListClosedAccountsResponse {
+    pub accounts: Vec<ClosedAccountInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClosedSenderAccountInfo {
+    pub sender: String,
+    /// last block balance was updated before the channel closed (0 is never)
+    pub last_update_time: Timestamp,
+    pub remote_addr: Option<String>,
+    pub remote_balance: Vec<Coin>,
+    pub closed_time: Timestamp,
+    pub closed_by: ClosedBy,
+}
+
+impl ClosedSenderAccountInfo {
+    pub fn convert(sender: String, input: ClosedAccount) -> Self {
+        ClosedSenderAccountInfo {
+            sender,
+            last_update_time: input.last_update_time,
+            remote_addr: input.remote_addr,
+            remote_balance: input.remote_balance,
+            closed_time: input.closed_time,
+            closed_by: input.closed_by,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListClosedSenderAccountsResponse {
+    pub accounts: Vec<ClosedSenderAccountInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PacketResponse {
+    pub sender: String,
+    pub callback_id: Option<String>,
+    pub channel_id: String,
+    pub kind: PacketKind,
+    pub status: PacketStatus,
+    pub sent_time: Timestamp,
+    pub timeout_time: Timestamp,
+    /// When we got the ack or timeout (None while pending)
+    pub completed_time: Option<Timestamp>,
+}
+
+impl From<PacketData> for PacketResponse {
+    fn from(input: PacketData) -> Self {
+        PacketResponse {
+            sender: input.sender.into(),
+            callback_id: input.callback_id,
+            channel_id: input.channel_id,
+            kind: input.kind,
+            status: input.status,
+            sent_time: input.sent_time,
+            timeout_time: input.timeout_time,
+            completed_time: input.completed_time,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PacketInfo {
+    pub sequence: u64,
+    pub sender: String,
+    pub callback_id: Option<String>,
+    pub channel_id: String,
+    pub kind: PacketKind,
+    pub sent_time: Timestamp,
+    pub timeout_time: Timestamp,
+}
+
+impl PacketInfo {
+    pub fn convert(sequence: u64, input: PacketData) -> Self {
+        PacketInfo {
+            sequence,
+            sender: input.sender.into(),
+            callback_id: input.callback_id,
+            channel_id: input.channel_id,
+            kind: input.kind,
+            sent_time: input.sent_time,
+            timeout_time: input.timeout_time,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingPacketsResponse {
+    pub packets: Vec<PacketInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FailedCallbackInfo {
+    /// Pass this to `RetryCallback`
+    pub id: u64,
+    pub channel_id: String,
+    pub sequence: u64,
+    pub callback_id: String,
+    pub msg: StdAck,
+    pub error: String,
+    pub failed_time: Timestamp,
+}
+
+impl FailedCallbackInfo {
+    pub fn convert(id: u64, input: FailedCallback) -> Self {
+        FailedCallbackInfo {
+            id,
+            channel_id: input.callback.channel_id,
+            sequence: input.callback.sequence,
+            callback_id: input.callback.callback_id,
+            msg: input.callback.msg,
+            error: input.error,
+            failed_time: input.failed_time,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FailedCallbacksResponse {
+    pub callbacks: Vec<FailedCallbackInfo>,
+}
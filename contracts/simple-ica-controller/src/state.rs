@@ -1,12 +1,31 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::msg::LatestQueryResponse;
-use cosmwasm_std::{Addr, Coin, Timestamp};
+use cosmwasm_std::{Addr, Coin, Empty, StdResult, Storage, Timestamp};
 use cw_storage_plus::{Item, Map};
+use simple_ica::{ChannelSettings, CounterpartyPolicy, PacketMsg, StdAck};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Config {
     pub admin: Addr,
+    /// lifetime of packets in seconds, if the caller doesn't set one
+    pub default_timeout: u64,
+    /// who may send packets. The host gives every sender its own account
+    pub access_policy: AccessPolicy,
+    /// gas available to each callback, so a failing receiver cannot block the ack
+    pub callback_gas_limit: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessPolicy {
+    /// Only the admin may send packets
+    AdminOnly,
+    /// The admin and all addresses on the allowlist may send packets
+    Allowlist,
+    /// Anyone may send packets
+    Open,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
@@ -22,6 +41,150 @@ pub struct AccountData {
     pub remote_balance: Vec<Coin>,
 }
 
+/// Who closed a channel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClosedBy {
+    /// We did
+    Us,
+    /// The other end did
+    Counterparty,
+    /// A packet timed out on an ordered channel
+    Timeout,
+}
+
+impl ClosedBy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClosedBy::Us => "us",
+            ClosedBy::Counterparty => "counterparty",
+            ClosedBy::Timeout => "timeout",
+        }
+    }
+}
+
+/// The account of a channel or sender, as we last knew it before the channel closed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClosedAccount {
+    /// last block balance was updated (0 is never)
+    pub last_update_time: Timestamp,
+    pub remote_addr: Option<String>,
+    pub remote_balance: Vec<Coin>,
+    pub closed_time: Timestamp,
+    pub closed_by: ClosedBy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketKind {
+    Dispatch,
+    IbcQuery,
+    WhoAmI,
+    Balances,
+    ReclaimFunds,
+    MigrateAccount,
+}
+
+impl From<&PacketMsg> for PacketKind {
+    fn from(packet: &PacketMsg) -> Self {
+        match packet {
+            PacketMsg::Dispatch { .. } => PacketKind::Dispatch,
+            PacketMsg::IbcQuery { .. } => PacketKind::IbcQuery,
+            PacketMsg::WhoAmI { .. } => PacketKind::WhoAmI,
+            PacketMsg::Balances { .. } => PacketKind::Balances,
+            PacketMsg::ReclaimFunds { .. } => PacketKind::ReclaimFunds,
+            PacketMsg::MigrateAccount { .. } => PacketKind::MigrateAccount,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketStatus {
+    /// Sent, but we got neither an ack nor a timeout yet
+    Pending,
+    /// The host acknowledged it with a success result
+    Acknowledged,
+    /// The host acknowledged it with an error
+    Errored,
+    /// It was never received by the host
+    TimedOut,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PacketData {
+    /// Who triggered the packet (the admin for packets sent on channel connect)
+    pub sender: Addr,
+    pub callback_id: Option<String>,
+    pub channel_id: String,
+    pub kind: PacketKind,
+    pub status: PacketStatus,
+    pub sent_time: Timestamp,
+    pub timeout_time: Timestamp,
+    /// When we got the ack or timeout (None while pending)
+    pub completed_time: Option<Timestamp>,
+}
+
+/// A callback to the sender of a packet, with the ack or timeout of the packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CallbackData {
+    /// The sender of the packet
+    pub receiver: Addr,
+    pub channel_id: String,
+    pub sequence: u64,
+    pub callback_id: String,
+    pub msg: StdAck,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FailedCallback {
+    pub callback: CallbackData,
+    pub error: String,
+    pub failed_time: Timestamp,
+}
+
 pub const CONFIG: Item<Config> = Item::new("config");
+// the account of each open channel. Moved to CLOSED_ACCOUNTS when the channel closes
 pub const ACCOUNTS: Map<&str, AccountData> = Map::new("accounts");
-pub const LATEST_QUERIES: Map<&str, LatestQueryResponse> = Map::new("querys");
+// accounts of closed channels, kept for the record as funds may be left behind
+pub const CLOSED_ACCOUNTS: Map<&str, ClosedAccount> = Map::new("closed_accounts");
+// result of the latest query of each sender, by (channel_id, sender). Results from before they
+// were kept per sender are left under the old "querys" namespace
+pub const LATEST_QUERIES: Map<(&str, &str), LatestQueryResponse> = Map::new("latest_queries");
+// the protocol version and order agreed on each channel. Kept after the channel closes, as
+// packets on it may still time out. Channels opened before we negotiated use the defaults
+pub const CHANNEL_SETTINGS: Map<&str, ChannelSettings> = Map::new("channel_settings");
+// remote account of each sender, by (channel_id, sender). Moved to CLOSED_SENDER_ACCOUNTS when
+// the channel closes
+pub const SENDER_ACCOUNTS: Map<(&str, &str), AccountData> = Map::new("sender_accounts");
+// remote accounts of the senders on closed channels, by (channel_id, sender)
+pub const CLOSED_SENDER_ACCOUNTS: Map<(&str, &str), ClosedAccount> =
+    Map::new("closed_sender_accounts");
+// who may open channels to us. Open to all if not set
+pub const COUNTERPARTY_POLICY: Item<CounterpartyPolicy> = Item::new("counterparty_policy");
+// addresses that may send packets under AccessPolicy::Allowlist
+pub const ALLOWLIST: Map<&Addr, Empty> = Map::new("allowlist");
+// addresses that may send query packets, whatever the access policy
+pub const QUERIERS: Map<&Addr, Empty> = Map::new("queriers");
+
+// this stores the packet we are sending, until the reply tells us its sequence
+pub const SENDING_PACKET: Item<PacketData> = Item::new("sending_packet");
+// all packets we sent, by (channel_id, sequence). Completed packets are kept as a record,
+// nothing prunes them
+pub const PACKETS: Map<(&str, u64), PacketData> = Map::new("packets");
+// the subset of PACKETS that is still pending
+pub const PENDING_PACKETS: Map<(&str, u64), Empty> = Map::new("pending_packets");
+
+// this stores the callback we are sending. It is only read back if the callback fails
+pub const SENDING_CALLBACK: Item<CallbackData> = Item::new("sending_callback");
+// callbacks that failed, until their receiver retries or drops them, by (receiver, id)
+pub const FAILED_CALLBACKS: Map<(&Addr, u64), FailedCallback> = Map::new("failed_callbacks");
+// last id given to a failed callback
+pub const FAILED_CALLBACK_COUNT: Item<u64> = Item::new("failed_callback_count");
+
+/// The protocol version and order of this channel
+pub fn channel_settings(storage: &dyn Storage, channel_id: &str) -> StdResult<ChannelSettings> {
+    Ok(CHANNEL_SETTINGS
+        .may_load(storage, channel_id)?
+        .unwrap_or_default())
+}
@@ -3,7 +3,11 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use simple_ica_host::msg::{InstantiateMsg, QueryMsg};
+use simple_ica_host::msg::{
+    AccountResponse, AllowanceResponse, ConfigResponse, CounterpartyPolicyResponse, ExecuteMsg,
+    InstantiateMsg, ListAccountsResponse, ListFrozenChannelsResponse, ListMsgPoliciesResponse,
+    ListRescuedFundsResponse, MigrateMsg, MsgPolicyResponse, QueryMsg,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -12,5 +16,16 @@ fn main() {
     remove_schemas(&out_dir).unwrap();
 
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(ExecuteMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(CounterpartyPolicyResponse), &out_dir);
+    export_schema(&schema_for!(MsgPolicyResponse), &out_dir);
+    export_schema(&schema_for!(ListMsgPoliciesResponse), &out_dir);
+    export_schema(&schema_for!(AllowanceResponse), &out_dir);
+    export_schema(&schema_for!(ListFrozenChannelsResponse), &out_dir);
+    export_schema(&schema_for!(AccountResponse), &out_dir);
+    export_schema(&schema_for!(ListAccountsResponse), &out_dir);
+    export_schema(&schema_for!(ListRescuedFundsResponse), &out_dir);
 }
@@ -0,0 +1,131 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{to_binary, to_vec, Addr, Binary, CosmosMsg, StdResult, Storage, WasmMsg};
+
+use crate::state::ACCOUNT_BACKENDS;
+
+/// The contract used for remote accounts, and how to talk to it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountBackend {
+    /// cw1-whitelist, with this contract as its only admin
+    Cw1Whitelist,
+    /// cw1-subkeys, with this contract as its only admin.
+    /// It takes the same messages as cw1-whitelist
+    Cw1Subkeys,
+    /// Any contract that runs the messages it is given on behalf of whoever instantiated it
+    Reflect {
+        /// Sent as is when creating an account
+        instantiate_msg: Binary,
+        /// The execute message is `{"<execute_msg>": {"<msgs_field>": [...]}}`
+        execute_msg: String,
+        msgs_field: String,
+    },
+}
+
+// cw1-whitelist is what the host always used
+#[allow(clippy::derivable_impls)]
+impl Default for AccountBackend {
+    fn default() -> Self {
+        AccountBackend::Cw1Whitelist
+    }
+}
+
+impl AccountBackend {
+    /// The message instantiating a new account owned by the host
+    pub fn instantiate_msg(&self, host: &Addr) -> StdResult<Binary> {
+        match self {
+            AccountBackend::Cw1Whitelist | AccountBackend::Cw1Subkeys => {
+                to_binary(&cw1_whitelist::msg::InstantiateMsg {
+                    admins: vec![host.to_string()],
+                    mutable: false,
+                })
+            }
+            AccountBackend::Reflect {
+                instantiate_msg, ..
+            } => Ok(instantiate_msg.clone()),
+        }
+    }
+
+    /// The message having the account execute these messages
+    pub fn execute_msg(&self, msgs: Vec<CosmosMsg>) -> StdResult<Binary> {
+        match self {
+            AccountBackend::Cw1Whitelist | AccountBackend::Cw1Subkeys => {
+                to_binary(&cw1_whitelist::msg::ExecuteMsg::Execute { msgs })
+            }
+            AccountBackend::Reflect {
+                execute_msg,
+                msgs_field,
+                ..
+            } => {
+                // serde-json-wasm cannot write maps, so we put the object together ourselves
+                let mut msg = b"{".to_vec();
+                msg.extend(to_vec(execute_msg)?);
+                msg.extend(b":{");
+                msg.extend(to_vec(msgs_field)?);
+                msg.push(b':');
+                msg.extend(to_vec(&msgs)?);
+                msg.extend(b"}}");
+                Ok(msg.into())
+            }
+        }
+    }
+}
+
+/// Has the account execute these messages, talking to it the way it was created with.
+/// Accounts created before backends were configurable are cw1-whitelist
+pub fn account_execute(
+    storage: &dyn Storage,
+    account: &Addr,
+    msgs: Vec<CosmosMsg>,
+) -> StdResult<WasmMsg> {
+    let backend = ACCOUNT_BACKENDS
+        .may_load(storage, account)?
+        .unwrap_or_default();
+    Ok(WasmMsg::Execute {
+        contract_addr: account.to_string(),
+        msg: backend.execute_msg(msgs)?,
+        funds: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cosmwasm_std::{coins, BankMsg};
+
+    #[test]
+    fn execute_msg_shapes() {
+        let msgs: Vec<CosmosMsg> = vec![BankMsg::Send {
+            to_address: "friend".into(),
+            amount: coins(100, "uatom"),
+        }
+        .into()];
+        let send = r#"[{"bank":{"send":{"to_address":"friend","amount":[{"denom":"uatom","amount":"100"}]}}}]"#;
+
+        let cw1 = AccountBackend::Cw1Subkeys
+            .execute_msg(msgs.clone())
+            .unwrap();
+        assert_eq!(
+            format!(r#"{{"execute":{{"msgs":{}}}}}"#, send),
+            String::from_utf8(cw1.0).unwrap()
+        );
+
+        let reflect = AccountBackend::Reflect {
+            instantiate_msg: b"{}".into(),
+            execute_msg: "reflect_msg".into(),
+            msgs_field: "msgs".into(),
+        };
+        assert_eq!(
+            Binary::from(b"{}"),
+            reflect.instantiate_msg(&Addr::unchecked("host")).unwrap()
+        );
+        let msg = reflect.execute_msg(msgs).unwrap();
+        assert_eq!(
+            format!(r#"{{"reflect_msg":{{"msgs":{}}}}}"#, send),
+            String::from_utf8(msg.0).unwrap()
+        );
+    }
+}
@@ -1,54 +1,553 @@
+use std::slice;
+
 use cosmwasm_std::{
-    entry_point, from_slice, to_binary, to_vec, wasm_execute, BankMsg, Binary, ContractResult,
-    CosmosMsg, Deps, DepsMut, Empty, Env, Event, Ibc3ChannelOpenResponse, IbcBasicResponse,
-    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse,
+    entry_point, to_binary, to_vec, wasm_execute, Addr, BankMsg, Binary, ContractResult, CosmosMsg,
+    Deps, DepsMut, Empty, Env, Event, Ibc3ChannelOpenResponse, IbcBasicResponse,
+    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse, IbcMsg,
     IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo,
     Order, QuerierWrapper, QueryRequest, QueryResponse, Reply, Response, StdError, StdResult,
-    SubMsg, SystemResult, WasmMsg,
+    Storage, SubMsg, SubMsgResult, SystemResult, WasmMsg,
 };
-use cw_utils::parse_reply_instantiate_data;
+use cw2::set_contract_version;
+use cw_utils::{parse_execute_response_data, parse_reply_instantiate_data};
 use simple_ica::{
-    check_order, check_version, BalancesResponse, DispatchResponse, IbcQueryResponse, PacketMsg,
-    StdAck, WhoAmIResponse, IBC_APP_VERSION,
+    connect_settings, open_version, BalancesResponse, CounterpartyPolicy, DispatchMode,
+    DispatchResponse, ErrorCode, IbcQueryResponse, IcaError, MessageOutcome,
+    MigrateAccountResponse, PacketMsg, ProtocolVersion, ReclaimFundsResponse, StdAck,
+    WhoAmIResponse,
 };
 
+use crate::backend::{account_execute, AccountBackend};
 use crate::error::ContractError;
+use crate::limits::{
+    account_limits, allowances, check_spending, outflow, record_spending, SpendingLimits,
+};
+use crate::migrations::{migrate_admin, migrate_legacy, previous_version, LEGACY_VERSION};
 use crate::msg::{
-    AccountInfo, AccountResponse, InstantiateMsg, ListAccountsResponse, QueryMsg, ReflectExecuteMsg,
+    AccountCode, AccountInfo, AccountResponse, AllowanceResponse, ChannelMsgPolicy, ConfigResponse,
+    CounterpartyPolicyResponse, DetachedAccount, ExecuteMsg, InstantiateMsg,
+    ListAccountCodesResponse, ListAccountsResponse, ListDetachedAccountsResponse,
+    ListFrozenChannelsResponse, ListMsgPoliciesResponse, ListRescuedFundsResponse, MigrateMsg,
+    MsgPolicyResponse, QueryMsg, RescuedFundsInfo,
 };
-use crate::state::{Config, ACCOUNTS, CONFIG, PENDING, RESULTS};
+use crate::policy::{msg_policy, MsgPolicy};
+use crate::state::{
+    ChannelRules, Config, Counterparty, Detached, PartialDispatch, PendingAccount,
+    PendingMigration, PendingSpending, RescuedFunds, ACCOUNTS, ACCOUNT_BACKENDS, ACCOUNT_CODES,
+    ACCOUNT_LIMITS, CHANNELS, CHANNEL_LIMITS, CHANNEL_SETTINGS, CONFIG, COUNTERPARTY_POLICY,
+    DEFAULT_MSG_POLICY, DETACHED_ACCOUNTS, DETACHED_RULES, FROZEN_CHANNELS, LEGACY_SENDERS,
+    MANAGED_ACCOUNTS, MSG_POLICIES, PARTIAL_DISPATCH, PENDING, PENDING_ADMIN, PENDING_COUNT,
+    PENDING_MIGRATION, PENDING_SPENDING, RESCUED_FUNDS, RESULTS, SPENDING,
+};
+
+pub const CONTRACT_NAME: &str = "crates.io:simple-ica-host";
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub const RECEIVE_DISPATCH_ID: u64 = 1234;
+pub const PARTIAL_DISPATCH_ID: u64 = 1235;
+pub const ATOMIC_MSG_ID: u64 = 1236;
+pub const MIGRATE_ACCOUNT_ID: u64 = 1237;
+/// Each account instantiation gets its own reply id, starting from this one
 pub const INIT_CALLBACK_ID: u64 = 7890;
 
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
     // we store the reflect_id for creating accounts later
     let cfg = Config {
+        admin: info.sender,
         cw1_code_id: msg.cw1_code_id,
+        account_backend: msg.account_backend.unwrap_or_default(),
     };
     CONFIG.save(deps.storage, &cfg)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new())
 }
 
 #[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let admin = msg
+        .admin
+        .map(|admin| deps.api.addr_validate(&admin))
+        .transpose()?;
+    let previous_version = previous_version(deps.storage)?;
+    migrate_admin(deps.storage, &previous_version, admin)?;
+    // only the 0.2.0 layout needs migrating, later versions just record the new version
+    if previous_version <= LEGACY_VERSION {
+        migrate_legacy(deps.storage, msg.legacy_senders)?;
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("previous_version", previous_version.to_string())
+        .add_attribute("version", CONTRACT_VERSION))
+}
+
+#[entry_point]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::TransferAdmin { admin } => execute_transfer_admin(deps, info, admin),
+        ExecuteMsg::AcceptAdmin {} => execute_accept_admin(deps, info),
+        ExecuteMsg::UpdateAccountBackend {
+            cw1_code_id,
+            account_backend,
+        } => execute_update_account_backend(deps, info, cw1_code_id, account_backend),
+        ExecuteMsg::AllowAccountCode {
+            code_id,
+            account_backend,
+        } => execute_allow_account_code(deps, info, code_id, Some(account_backend)),
+        ExecuteMsg::DisallowAccountCode { code_id } => {
+            execute_allow_account_code(deps, info, code_id, None)
+        }
+        ExecuteMsg::MigrateAccounts {
+            code_id,
+            msg,
+            channel_id,
+        } => execute_migrate_accounts(deps, info, code_id, msg, channel_id),
+        ExecuteMsg::UpdateCounterpartyPolicy { policy } => {
+            execute_update_counterparty_policy(deps, info, policy)
+        }
+        ExecuteMsg::UpdateDefaultMsgPolicy { policy } => {
+            execute_update_default_msg_policy(deps, info, policy)
+        }
+        ExecuteMsg::UpdateChannelMsgPolicy { channel_id, policy } => {
+            execute_update_channel_msg_policy(deps, info, channel_id, policy)
+        }
+        ExecuteMsg::UpdateChannelLimits { channel_id, limits } => {
+            execute_update_channel_limits(deps, info, channel_id, limits)
+        }
+        ExecuteMsg::UpdateAccountLimits {
+            channel_id,
+            sender,
+            limits,
+        } => execute_update_account_limits(deps, info, channel_id, sender, limits),
+        ExecuteMsg::FreezeChannel { channel_id } => {
+            execute_freeze_channel(deps, info, channel_id, true)
+        }
+        ExecuteMsg::UnfreezeChannel { channel_id } => {
+            execute_freeze_channel(deps, info, channel_id, false)
+        }
+        ExecuteMsg::CloseChannel { channel_id } => execute_close_channel(deps, info, channel_id),
+        ExecuteMsg::DispatchAtomic { account, msgs } => {
+            execute_dispatch_atomic(deps, env, info, account, msgs)
+        }
+    }
+}
+
+pub fn execute_transfer_admin(
+    deps: DepsMut,
+    info: MessageInfo,
+    new_admin: String,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may transfer admin").into());
+    }
+    let pending_admin = deps.api.addr_validate(&new_admin)?;
+    PENDING_ADMIN.save(deps.storage, &pending_admin)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_transfer_admin")
+        .add_attribute("pending_admin", pending_admin))
+}
+
+pub fn execute_accept_admin(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let pending_admin = PENDING_ADMIN
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoPendingAdmin)?;
+    if info.sender != pending_admin {
+        return Err(StdError::generic_err("Only the proposed admin may accept").into());
+    }
+    let mut cfg = CONFIG.load(deps.storage)?;
+    cfg.admin = pending_admin;
+    CONFIG.save(deps.storage, &cfg)?;
+    PENDING_ADMIN.remove(deps.storage);
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_accept_admin")
+        .add_attribute("new_admin", cfg.admin))
+}
+
+pub fn execute_update_account_backend(
+    deps: DepsMut,
+    info: MessageInfo,
+    cw1_code_id: u64,
+    account_backend: AccountBackend,
+) -> Result<Response, ContractError> {
+    // auth check
+    let mut cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update account backend").into());
+    }
+    cfg.cw1_code_id = cw1_code_id;
+    cfg.account_backend = account_backend;
+    CONFIG.save(deps.storage, &cfg)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_account_backend")
+        .add_attribute("cw1_code_id", cw1_code_id.to_string()))
+}
+
+pub fn execute_allow_account_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code_id: u64,
+    account_backend: Option<AccountBackend>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update account codes").into());
+    }
+    let action = match account_backend {
+        Some(account_backend) => {
+            ACCOUNT_CODES.save(deps.storage, code_id, &account_backend)?;
+            "handle_allow_account_code"
+        }
+        None => {
+            ACCOUNT_CODES.remove(deps.storage, code_id);
+            "handle_disallow_account_code"
+        }
+    };
+
+    Ok(Response::new()
+        .add_attribute("action", action)
+        .add_attribute("code_id", code_id.to_string()))
+}
+
+pub fn execute_migrate_accounts(
+    deps: DepsMut,
+    info: MessageInfo,
+    code_id: u64,
+    msg: Binary,
+    channel_id: Option<String>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may migrate accounts").into());
+    }
+    let account_backend = ACCOUNT_CODES
+        .may_load(deps.storage, code_id)?
+        .ok_or(ContractError::CodeNotAllowed(code_id))?;
+
+    let accounts: Vec<Addr> = match &channel_id {
+        Some(channel_id) => ACCOUNTS
+            .prefix(channel_id)
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, account)| account))
+            .collect::<StdResult<_>>()?,
+        None => ACCOUNTS
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, account)| account))
+            .chain(
+                DETACHED_ACCOUNTS
+                    .range(deps.storage, None, None, Order::Ascending)
+                    .map(|item| item.map(|(_, detached)| detached))
+                    .collect::<StdResult<Vec<_>>>()?
+                    .into_iter()
+                    .flatten()
+                    .map(|detached| Ok(detached.account)),
+            )
+            .collect::<StdResult<_>>()?,
+    };
+    let mut msgs = vec![];
+    let mut skipped = 0u32;
+    for account in accounts {
+        if !MANAGED_ACCOUNTS.has(deps.storage, &account) {
+            skipped += 1;
+            continue;
+        }
+        // if any migration fails, they are all reverted
+        ACCOUNT_BACKENDS.save(deps.storage, &account, &account_backend)?;
+        msgs.push(WasmMsg::Migrate {
+            contract_addr: account.into(),
+            new_code_id: code_id,
+            msg: msg.clone(),
+        });
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_migrate_accounts")
+        .add_attribute("code_id", code_id.to_string())
+        .add_attribute("migrated", msgs.len().to_string())
+        .add_attribute("skipped", skipped.to_string())
+        .add_messages(msgs))
+}
+
+pub fn execute_update_counterparty_policy(
+    deps: DepsMut,
+    info: MessageInfo,
+    policy: CounterpartyPolicy,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update counterparty policy").into());
+    }
+    COUNTERPARTY_POLICY.save(deps.storage, &policy)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_counterparty_policy")
+        .add_attribute("open", policy.is_open().to_string()))
+}
+
+pub fn execute_update_default_msg_policy(
+    deps: DepsMut,
+    info: MessageInfo,
+    policy: MsgPolicy,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update msg policies").into());
+    }
+    DEFAULT_MSG_POLICY.save(deps.storage, &policy)?;
+
+    Ok(Response::new().add_attribute("action", "handle_update_default_msg_policy"))
+}
+
+pub fn execute_update_channel_msg_policy(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    policy: Option<MsgPolicy>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update msg policies").into());
+    }
+    match policy {
+        Some(policy) => MSG_POLICIES.save(deps.storage, &channel_id, &policy)?,
+        None => MSG_POLICIES.remove(deps.storage, &channel_id),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_channel_msg_policy")
+        .add_attribute("channel_id", channel_id))
+}
+
+pub fn execute_update_channel_limits(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    limits: Option<SpendingLimits>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update spending limits").into());
+    }
+    match limits {
+        Some(limits) => {
+            limits.validate()?;
+            CHANNEL_LIMITS.save(deps.storage, &channel_id, &limits)?;
+        }
+        None => CHANNEL_LIMITS.remove(deps.storage, &channel_id),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_channel_limits")
+        .add_attribute("channel_id", channel_id))
+}
+
+pub fn execute_update_account_limits(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    sender: String,
+    limits: Option<SpendingLimits>,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may update spending limits").into());
+    }
+    let key = (channel_id.as_str(), sender.as_str());
+    match limits {
+        Some(limits) => {
+            limits.validate()?;
+            ACCOUNT_LIMITS.save(deps.storage, key, &limits)?;
+        }
+        None => ACCOUNT_LIMITS.remove(deps.storage, key),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "handle_update_account_limits")
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("sender", sender))
+}
+
+pub fn execute_freeze_channel(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    frozen: bool,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may freeze channels").into());
+    }
+    let action = if frozen {
+        FROZEN_CHANNELS.save(deps.storage, &channel_id, &Empty {})?;
+        "handle_freeze_channel"
+    } else {
+        FROZEN_CHANNELS.remove(deps.storage, &channel_id);
+        "handle_unfreeze_channel"
+    };
+
+    Ok(Response::new()
+        .add_attribute("action", action)
+        .add_attribute("channel_id", channel_id))
+}
+
+pub fn execute_close_channel(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+) -> Result<Response, ContractError> {
+    // auth check
+    let cfg = CONFIG.load(deps.storage)?;
+    if info.sender != cfg.admin {
+        return Err(StdError::generic_err("Only admin may close channels").into());
+    }
+    let msg = IbcMsg::CloseChannel {
+        channel_id: channel_id.clone(),
+    };
+
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attribute("action", "handle_close_channel")
+        .add_attribute("channel_id", channel_id))
+}
+
+pub fn execute_dispatch_atomic(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    account: String,
+    msgs: Vec<CosmosMsg>,
+) -> Result<Response, ContractError> {
+    // auth check
+    if info.sender != env.contract.address {
+        return Err(StdError::generic_err("Only the contract itself may dispatch").into());
+    }
+    let account = deps.api.addr_validate(&account)?;
+
+    // every message goes to the reflect contract on its own, wrapped in a submessage,
+    // so the replies can report the result of each one
+    let msgs = msgs
+        .into_iter()
+        .map(|msg| {
+            let wasm_msg = reflect_execute(deps.storage, &account, msg)?;
+            Ok(SubMsg::reply_on_success(wasm_msg, ATOMIC_MSG_ID))
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_dispatch_atomic"))
+}
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
     match msg {
-        QueryMsg::Account { channel_id } => to_binary(&query_account(deps, channel_id)?),
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::CounterpartyPolicy {} => to_binary(&query_counterparty_policy(deps)?),
+        QueryMsg::DefaultMsgPolicy {} => to_binary(&query_default_msg_policy(deps)?),
+        QueryMsg::MsgPolicy { channel_id } => to_binary(&query_msg_policy(deps, channel_id)?),
+        QueryMsg::ListMsgPolicies {} => to_binary(&query_list_msg_policies(deps)?),
+        QueryMsg::Allowance { channel_id, sender } => {
+            to_binary(&query_allowance(deps, env, channel_id, sender)?)
+        }
+        QueryMsg::ListFrozenChannels {} => to_binary(&query_list_frozen_channels(deps)?),
+        QueryMsg::Account { channel_id, sender } => {
+            to_binary(&query_account(deps, channel_id, sender)?)
+        }
         QueryMsg::ListAccounts {} => to_binary(&query_list_accounts(deps)?),
+        QueryMsg::ListDetachedAccounts {} => to_binary(&query_list_detached_accounts(deps)?),
+        QueryMsg::ListAccountCodes {} => to_binary(&query_list_account_codes(deps)?),
+        QueryMsg::ListRescuedFunds {} => to_binary(&query_list_rescued_funds(deps)?),
     }
 }
 
-pub fn query_account(deps: Deps, channel_id: String) -> StdResult<AccountResponse> {
-    let account = ACCOUNTS.load(deps.storage, &channel_id)?;
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let cfg = CONFIG.load(deps.storage)?;
+    let pending_admin = PENDING_ADMIN.may_load(deps.storage)?;
+    Ok(ConfigResponse {
+        admin: cfg.admin.into(),
+        pending_admin: pending_admin.map(Into::into),
+        cw1_code_id: cfg.cw1_code_id,
+        account_backend: cfg.account_backend,
+    })
+}
+
+pub fn query_counterparty_policy(deps: Deps) -> StdResult<CounterpartyPolicyResponse> {
+    let policy = COUNTERPARTY_POLICY
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    Ok(CounterpartyPolicyResponse { policy })
+}
+
+pub fn query_default_msg_policy(deps: Deps) -> StdResult<MsgPolicyResponse> {
+    let policy = DEFAULT_MSG_POLICY
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    Ok(MsgPolicyResponse { policy })
+}
+
+pub fn query_msg_policy(deps: Deps, channel_id: String) -> StdResult<MsgPolicyResponse> {
+    let policy = msg_policy(deps.storage, &channel_id)?;
+    Ok(MsgPolicyResponse { policy })
+}
+
+pub fn query_list_msg_policies(deps: Deps) -> StdResult<ListMsgPoliciesResponse> {
+    let policies = MSG_POLICIES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (channel_id, policy) = item?;
+            Ok(ChannelMsgPolicy { channel_id, policy })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListMsgPoliciesResponse { policies })
+}
+
+pub fn query_allowance(
+    deps: Deps,
+    env: Env,
+    channel_id: String,
+    sender: String,
+) -> StdResult<AllowanceResponse> {
+    let limits = account_limits(deps.storage, &channel_id, &sender)?;
+    let allowances = allowances(deps.storage, &env, &channel_id, &sender)?;
+    Ok(AllowanceResponse { limits, allowances })
+}
+
+pub fn query_list_frozen_channels(deps: Deps) -> StdResult<ListFrozenChannelsResponse> {
+    let channels = FROZEN_CHANNELS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    Ok(ListFrozenChannelsResponse { channels })
+}
+
+pub fn query_account(deps: Deps, channel_id: String, sender: String) -> StdResult<AccountResponse> {
+    let account = ACCOUNTS.may_load(deps.storage, (&channel_id, &sender))?;
     Ok(AccountResponse {
-        account: Some(account.into()),
+        account: account.map(Into::into),
     })
 }
 
@@ -56,101 +555,266 @@ pub fn query_list_accounts(deps: Deps) -> StdResult<ListAccountsResponse> {
     let accounts = ACCOUNTS
         .range(deps.storage, None, None, Order::Ascending)
         .map(|item| {
-            let (channel_id, account) = item?;
+            let ((channel_id, sender), account) = item?;
             Ok(AccountInfo {
                 account: account.into(),
                 channel_id,
+                sender,
             })
         })
         .collect::<StdResult<_>>()?;
     Ok(ListAccountsResponse { accounts })
 }
 
+pub fn query_list_detached_accounts(deps: Deps) -> StdResult<ListDetachedAccountsResponse> {
+    let mut accounts = vec![];
+    for item in DETACHED_ACCOUNTS.range(deps.storage, None, None, Order::Ascending) {
+        let ((connection_id, port_id, sender), detached) = item?;
+        let counterparty = Counterparty {
+            connection_id,
+            port_id,
+        };
+        accounts.extend(detached.into_iter().map(|detached| DetachedAccount {
+            account: detached.account.into(),
+            counterparty: counterparty.clone(),
+            sender: sender.clone(),
+            channel_id: detached.channel_id,
+        }));
+    }
+    Ok(ListDetachedAccountsResponse { accounts })
+}
+
+pub fn query_list_account_codes(deps: Deps) -> StdResult<ListAccountCodesResponse> {
+    let codes = ACCOUNT_CODES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (code_id, account_backend) = item?;
+            Ok(AccountCode {
+                code_id,
+                account_backend,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListAccountCodesResponse { codes })
+}
+
+pub fn query_list_rescued_funds(deps: Deps) -> StdResult<ListRescuedFundsResponse> {
+    let funds = RESCUED_FUNDS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let ((channel_id, sender), rescued) = item?;
+            Ok(RescuedFundsInfo {
+                channel_id,
+                sender,
+                counterparty: rescued.counterparty,
+                balance: rescued.balance,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListRescuedFundsResponse { funds })
+}
+
 #[entry_point]
-/// enforces ordering and versioing constraints
+/// enforces ordering and versioing constraints, and who may connect
 pub fn ibc_channel_open(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelOpenMsg,
 ) -> Result<IbcChannelOpenResponse, ContractError> {
-    let channel = msg.channel();
-
-    check_order(&channel.order)?;
-    // In ibcv3 we don't check the version string passed in the message
-    // and only check the counterparty version.
-    if let Some(counter_version) = msg.counterparty_version() {
-        check_version(counter_version)?;
-    }
-
-    // We return the version we need (which could be different than the counterparty version)
-    Ok(Some(Ibc3ChannelOpenResponse {
-        version: IBC_APP_VERSION.to_string(),
-    }))
+    let policy = COUNTERPARTY_POLICY
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    policy.check(msg.channel())?;
+    // We return the version we agree to (which could be different than the counterparty version).
+    // Ordered channels are only accepted with a version supporting them
+    let version = open_version(&msg)?;
+    Ok(Some(Ibc3ChannelOpenResponse { version }))
 }
 
 #[entry_point]
-/// once it's established, we create the reflect contract
+/// once it's established, we are ready to receive packets.
+/// Accounts left on a closed channel from the same controller are attached to it again,
+/// other reflect accounts are only created once a sender needs one
 pub fn ibc_channel_connect(
     deps: DepsMut,
-    env: Env,
+    _env: Env,
     msg: IbcChannelConnectMsg,
-) -> StdResult<IbcBasicResponse> {
+) -> Result<IbcBasicResponse, ContractError> {
     let channel = msg.channel();
-    let cfg = CONFIG.load(deps.storage)?;
     let chan_id = &channel.endpoint.channel_id;
+    let settings = connect_settings(&msg)?;
+    CHANNEL_SETTINGS.save(deps.storage, chan_id, &settings)?;
 
-    let init_msg = cw1_whitelist::msg::InstantiateMsg {
-        admins: vec![env.contract.address.into_string()],
-        mutable: false,
-    };
-    let msg = WasmMsg::Instantiate {
-        admin: None,
-        code_id: cfg.cw1_code_id,
-        msg: to_binary(&init_msg)?,
-        funds: vec![],
-        label: format!("ibc-reflect-{}", chan_id),
+    // remember who is on the other end, to recognize them on a later channel
+    let counterparty = Counterparty {
+        connection_id: channel.connection_id.clone(),
+        port_id: channel.counterparty_endpoint.port_id.clone(),
     };
-    let msg = SubMsg::reply_on_success(msg, INIT_CALLBACK_ID);
+    CHANNELS.save(deps.storage, chan_id, &counterparty)?;
+
+    // it takes over what the admin set on an earlier channel from the same controller
+    let key = (
+        counterparty.connection_id.as_str(),
+        counterparty.port_id.as_str(),
+    );
+    let mut detached_rules = DETACHED_RULES
+        .may_load(deps.storage, key)?
+        .unwrap_or_default();
+    let restore_rules = !detached_rules.is_empty();
+    if restore_rules {
+        let rules = detached_rules.remove(0);
+        if let Some(policy) = rules.msg_policy {
+            MSG_POLICIES.save(deps.storage, chan_id, &policy)?;
+        }
+        if let Some(limits) = rules.limits {
+            CHANNEL_LIMITS.save(deps.storage, chan_id, &limits)?;
+        }
+        if detached_rules.is_empty() {
+            DETACHED_RULES.remove(deps.storage, key);
+        } else {
+            DETACHED_RULES.save(deps.storage, key, &detached_rules)?;
+        }
+    }
 
-    // store the channel id for the reply handler
-    PENDING.save(deps.storage, chan_id)?;
+    // and the oldest account each sender left there, with its limits and the funds we took
+    // from it when that channel closed
+    let senders = DETACHED_ACCOUNTS
+        .prefix(key)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut messages: Vec<BankMsg> = vec![];
+    for sender in &senders {
+        let sender_key = (key.0, key.1, sender.as_str());
+        let mut detached = DETACHED_ACCOUNTS.load(deps.storage, sender_key)?;
+        let Detached {
+            account,
+            channel_id: closed_channel,
+            limits,
+            spending,
+        } = detached.remove(0);
+        if detached.is_empty() {
+            DETACHED_ACCOUNTS.remove(deps.storage, sender_key);
+        } else {
+            DETACHED_ACCOUNTS.save(deps.storage, sender_key, &detached)?;
+        }
+        ACCOUNTS.save(deps.storage, (chan_id, sender), &account)?;
+        if let Some(limits) = limits {
+            ACCOUNT_LIMITS.save(deps.storage, (chan_id, sender), &limits)?;
+        }
+        if let Some(spending) = spending {
+            SPENDING.save(deps.storage, (chan_id, sender), &spending)?;
+        }
+        if let Some(rescued) = RESCUED_FUNDS.may_load(deps.storage, (&closed_channel, sender))? {
+            RESCUED_FUNDS.remove(deps.storage, (&closed_channel, sender));
+            messages.push(BankMsg::Send {
+                to_address: account.into(),
+                amount: rescued.balance,
+            });
+        }
+    }
 
     Ok(IbcBasicResponse::new()
-        .add_submessage(msg)
+        .add_messages(messages)
         .add_attribute("action", "ibc_connect")
         .add_attribute("channel_id", chan_id)
+        .add_attribute("restore_rules", restore_rules.to_string())
+        .add_attribute("reattach_accounts", senders.len().to_string())
+        .add_attribute("version", settings.version.as_str())
         .add_event(Event::new("ibc").add_attribute("channel", "connect")))
 }
 
 #[entry_point]
-/// On closed channel, we take all tokens from reflect contract to this contract.
-/// We also delete the channel entry from accounts.
+/// On closed channel, we take all tokens from the reflect contracts to this contract.
+/// We also delete the channel entries from accounts, but keep track of the funds we took,
+/// so they go back to the accounts once a new channel takes them over.
 pub fn ibc_channel_close(
     deps: DepsMut,
     env: Env,
     msg: IbcChannelCloseMsg,
 ) -> StdResult<IbcBasicResponse> {
     let channel = msg.channel();
-    // get contract address and remove lookup
+    // get contract addresses and remove lookup
     let channel_id = channel.endpoint.channel_id.as_str();
-    let reflect_addr = ACCOUNTS.load(deps.storage, channel_id)?;
-    ACCOUNTS.remove(deps.storage, channel_id);
+    CHANNELS.remove(deps.storage, channel_id);
+    CHANNEL_SETTINGS.remove(deps.storage, channel_id);
+    FROZEN_CHANNELS.remove(deps.storage, channel_id);
+    LEGACY_SENDERS.remove(deps.storage, channel_id);
+    let counterparty = Counterparty {
+        connection_id: channel.connection_id.clone(),
+        port_id: channel.counterparty_endpoint.port_id.clone(),
+    };
 
-    // transfer current balance if any to this host contract
-    let amount = deps.querier.query_all_balances(&reflect_addr)?;
-    let messages: Vec<SubMsg<Empty>> = if !amount.is_empty() {
-        let bank_msg = BankMsg::Send {
-            to_address: env.contract.address.into(),
-            amount,
-        };
-        let reflect_msg = ReflectExecuteMsg::ReflectMsg {
-            msgs: vec![bank_msg.into()],
-        };
-        let wasm_msg = wasm_execute(reflect_addr, &reflect_msg, vec![])?;
-        vec![SubMsg::new(wasm_msg)]
-    } else {
-        vec![]
+    // the next channel from this controller gets the same rules
+    let rules = ChannelRules {
+        msg_policy: MSG_POLICIES.may_load(deps.storage, channel_id)?,
+        limits: CHANNEL_LIMITS.may_load(deps.storage, channel_id)?,
     };
+    MSG_POLICIES.remove(deps.storage, channel_id);
+    CHANNEL_LIMITS.remove(deps.storage, channel_id);
+    if rules.msg_policy.is_some() || rules.limits.is_some() {
+        let key = (
+            counterparty.connection_id.as_str(),
+            counterparty.port_id.as_str(),
+        );
+        let mut detached_rules = DETACHED_RULES
+            .may_load(deps.storage, key)?
+            .unwrap_or_default();
+        detached_rules.push(rules);
+        DETACHED_RULES.save(deps.storage, key, &detached_rules)?;
+    }
+
+    let accounts = ACCOUNTS
+        .prefix(channel_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut messages: Vec<SubMsg<Empty>> = vec![];
+    for (sender, reflect_addr) in accounts {
+        ACCOUNTS.remove(deps.storage, (channel_id, &sender));
+        // kept for the next channel from this controller, along with its limits
+        let key = (
+            counterparty.connection_id.as_str(),
+            counterparty.port_id.as_str(),
+            sender.as_str(),
+        );
+        let mut detached = DETACHED_ACCOUNTS
+            .may_load(deps.storage, key)?
+            .unwrap_or_default();
+        detached.push(Detached {
+            account: reflect_addr.clone(),
+            channel_id: channel_id.to_string(),
+            limits: ACCOUNT_LIMITS.may_load(deps.storage, (channel_id, &sender))?,
+            spending: SPENDING.may_load(deps.storage, (channel_id, &sender))?,
+        });
+        DETACHED_ACCOUNTS.save(deps.storage, key, &detached)?;
+        SPENDING.remove(deps.storage, (channel_id, &sender));
+
+        // transfer current balance if any to this host contract
+        let amount = deps.querier.query_all_balances(&reflect_addr)?;
+        if !amount.is_empty() {
+            let rescued = RescuedFunds {
+                counterparty: counterparty.clone(),
+                balance: amount.clone(),
+            };
+            RESCUED_FUNDS.save(deps.storage, (channel_id, &sender), &rescued)?;
+
+            let bank_msg: CosmosMsg = BankMsg::Send {
+                to_address: env.contract.address.to_string(),
+                amount,
+            }
+            .into();
+            let wasm_msg = account_execute(deps.storage, &reflect_addr, vec![bank_msg])?;
+            messages.push(SubMsg::new(wasm_msg));
+        }
+    }
+    // limits may be set for senders without an account, so clear them all
+    let limited = ACCOUNT_LIMITS
+        .prefix(channel_id)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for sender in limited {
+        ACCOUNT_LIMITS.remove(deps.storage, (channel_id, &sender));
+    }
     let rescue_funds = !messages.is_empty();
 
     Ok(IbcBasicResponse::new()
@@ -161,42 +825,229 @@ pub fn ibc_channel_close(
 }
 
 #[entry_point]
-pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, ContractError> {
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
     match reply.id {
-        RECEIVE_DISPATCH_ID => reply_dispatch_callback(deps, reply),
-        INIT_CALLBACK_ID => reply_init_callback(deps, reply),
+        RECEIVE_DISPATCH_ID => reply_dispatch_callback(deps, env, reply),
+        PARTIAL_DISPATCH_ID => reply_partial_dispatch(deps, env, reply),
+        ATOMIC_MSG_ID => reply_atomic_msg(deps, reply),
+        MIGRATE_ACCOUNT_ID => reply_migrate_account(deps, reply),
+        id if id >= INIT_CALLBACK_ID => reply_init_callback(deps, env, reply),
         _ => Err(ContractError::InvalidReplyId),
     }
 }
 
-pub fn reply_dispatch_callback(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+pub fn reply_dispatch_callback(
+    deps: DepsMut,
+    env: Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    let pending = PENDING_SPENDING.may_load(deps.storage)?;
+    PENDING_SPENDING.remove(deps.storage);
+    // the results were collected while dispatching, unless it failed and reverted them all
+    let data = match reply.result {
+        SubMsgResult::Ok(_) => {
+            if let Some(pending) = pending {
+                record_pending(deps.storage, &env, pending)?;
+            }
+            StdAck::success(&RESULTS.load(deps.storage)?)?
+        }
+        SubMsgResult::Err(err) => IcaError::new(ErrorCode::DispatchFailed, err).ack(),
+    };
+    Ok(Response::new().set_data(data))
+}
+
+pub fn reply_atomic_msg(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
     // add the new result to the current tracker
     let mut results = RESULTS.load(deps.storage)?;
-    results.push(reply.result.unwrap().data.unwrap_or_default());
+    record_result(&mut results, reply.result)?;
+    RESULTS.save(deps.storage, &results)?;
+    Ok(Response::new())
+}
+
+pub fn reply_partial_dispatch(
+    deps: DepsMut,
+    env: Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    let mut results = RESULTS.load(deps.storage)?;
+    let mut dispatch = PARTIAL_DISPATCH.load(deps.storage)?;
+    let mut pending = PENDING_SPENDING.load(deps.storage)?;
+    let failed = !record_result(&mut results, reply.result)?;
+    if !failed {
+        record_pending(deps.storage, &env, pending.clone())?;
+    }
+    if failed && dispatch.mode == DispatchMode::StopOnError {
+        for _ in dispatch.msgs.drain(..) {
+            results.results.push(Binary::default());
+            results.events.push(vec![]);
+            results.outcomes.push(MessageOutcome::Skipped {});
+        }
+    }
     RESULTS.save(deps.storage, &results)?;
 
-    // update result data if this is the last
-    let data = StdAck::success(&DispatchResponse { results });
+    // go on with the next message, its reply will update the ack again
+    let mut res = Response::new().set_data(StdAck::success(&results)?);
+    if dispatch.msgs.is_empty() {
+        PARTIAL_DISPATCH.remove(deps.storage);
+        PENDING_SPENDING.remove(deps.storage);
+    } else {
+        let next = dispatch.msgs.remove(0);
+        pending.amount = outflow(slice::from_ref(&next)).into_vec();
+        let msg = reflect_execute(deps.storage, &dispatch.account, next)?;
+        res = res.add_submessage(SubMsg::reply_always(msg, PARTIAL_DISPATCH_ID));
+        PARTIAL_DISPATCH.save(deps.storage, &dispatch)?;
+        PENDING_SPENDING.save(deps.storage, &pending)?;
+    }
+    Ok(res)
+}
+
+// counts what the messages that just succeeded spent
+fn record_pending(storage: &mut dyn Storage, env: &Env, pending: PendingSpending) -> StdResult<()> {
+    record_spending(
+        storage,
+        env,
+        &pending.channel_id,
+        &pending.sender,
+        pending.amount,
+    )
+}
+
+pub fn reply_migrate_account(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    let PendingMigration { account, code_id } = PENDING_MIGRATION.load(deps.storage)?;
+    PENDING_MIGRATION.remove(deps.storage);
+    let data = match reply.result {
+        SubMsgResult::Ok(_) => {
+            // from now on we talk to the account the way the new code wants
+            if let Some(backend) = ACCOUNT_CODES.may_load(deps.storage, code_id)? {
+                ACCOUNT_BACKENDS.save(deps.storage, &account, &backend)?;
+            }
+            let response = MigrateAccountResponse {
+                account: account.into(),
+                code_id,
+            };
+            StdAck::success(&response)?
+        }
+        SubMsgResult::Err(err) => IcaError::new(ErrorCode::DispatchFailed, err).ack(),
+    };
     Ok(Response::new().set_data(data))
 }
 
-pub fn reply_init_callback(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
-    // we use storage to pass info from the caller to the reply
-    let id = PENDING.load(deps.storage)?;
-    PENDING.remove(deps.storage);
+// adds the outcome of one message to the ack, returns whether it succeeded
+fn record_result(
+    results: &mut DispatchResponse,
+    result: SubMsgResult,
+) -> Result<bool, ContractError> {
+    match result {
+        SubMsgResult::Ok(response) => {
+            // the account wraps whatever its message returned in a MsgExecuteContractResponse
+            let data = match response.data {
+                Some(data) => parse_execute_response_data(&data)?.data,
+                None => None,
+            };
+            results.results.push(data.unwrap_or_default());
+            results.events.push(response.events);
+            results.outcomes.push(MessageOutcome::Success {});
+            Ok(true)
+        }
+        SubMsgResult::Err(err) => {
+            results.results.push(Binary::default());
+            results.events.push(vec![]);
+            results.outcomes.push(MessageOutcome::Error(err));
+            Ok(false)
+        }
+    }
+}
+
+pub fn reply_init_callback(
+    deps: DepsMut,
+    env: Env,
+    reply: Reply,
+) -> Result<Response, ContractError> {
+    // we use storage to pass info from the caller to the reply, under the reply id
+    let PendingAccount {
+        channel_id,
+        sender,
+        msgs,
+        mode,
+    } = PENDING
+        .may_load(deps.storage, reply.id)?
+        .ok_or(ContractError::InvalidReplyId)?;
+    PENDING.remove(deps.storage, reply.id);
 
     // parse contract info from data
     let raw_addr = parse_reply_instantiate_data(reply)?.contract_address;
     let contract_addr = deps.api.addr_validate(&raw_addr)?;
 
-    // store id -> contract_addr if it is empty
-    // id comes from: `let chan_id = msg.endpoint.channel_id;` in `ibc_channel_connect`
-    if ACCOUNTS.may_load(deps.storage, &id)?.is_some() {
-        return Err(ContractError::ChannelAlreadyRegistered);
+    // store (channel_id, sender) -> contract_addr if it is empty
+    if ACCOUNTS
+        .may_load(deps.storage, (&channel_id, &sender))?
+        .is_some()
+    {
+        return Err(ContractError::AccountAlreadyRegistered);
+    }
+    ACCOUNTS.save(deps.storage, (&channel_id, &sender), &contract_addr)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    ACCOUNT_BACKENDS.save(deps.storage, &contract_addr, &cfg.account_backend)?;
+    MANAGED_ACCOUNTS.save(deps.storage, &contract_addr, &Empty {})?;
+
+    // now finish processing the packet that needed this account
+    match msgs {
+        Some(msgs) => {
+            let (msgs, acknowledgement) = dispatch_to_account(
+                deps.storage,
+                &env,
+                contract_addr,
+                channel_id,
+                sender,
+                msgs,
+                mode,
+            )?;
+            Ok(Response::new()
+                .set_data(acknowledgement)
+                .add_submessages(msgs))
+        }
+        None => {
+            let response = WhoAmIResponse {
+                account: contract_addr.into(),
+            };
+            Ok(Response::new().set_data(StdAck::success(&response)?))
+        }
     }
-    ACCOUNTS.save(deps.storage, &id, &contract_addr)?;
+}
 
-    Ok(Response::new())
+// instantiates a new reflect account, the reply handler will register it
+fn create_account(
+    deps: DepsMut,
+    env: &Env,
+    channel_id: String,
+    sender: String,
+    msgs: Option<Vec<CosmosMsg>>,
+    mode: DispatchMode,
+) -> StdResult<SubMsg> {
+    let cfg = CONFIG.load(deps.storage)?;
+    // we are its admin, so it can be migrated later
+    let msg = WasmMsg::Instantiate {
+        admin: Some(env.contract.address.to_string()),
+        code_id: cfg.cw1_code_id,
+        msg: cfg.account_backend.instantiate_msg(&env.contract.address)?,
+        funds: vec![],
+        label: format!("ibc-reflect-{}-{}", channel_id, sender),
+    };
+
+    // store the channel id and sender for the reply handler.
+    // a reply id of its own keeps it apart from any other account created in this tx
+    let count = PENDING_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    let reply_id = INIT_CALLBACK_ID + count;
+    let pending = PendingAccount {
+        channel_id,
+        sender,
+        msgs,
+        mode,
+    };
+    PENDING_COUNT.save(deps.storage, &(count + 1))?;
+    PENDING.save(deps.storage, reply_id, &pending)?;
+
+    Ok(SubMsg::reply_on_success(msg, reply_id))
 }
 
 #[entry_point]
@@ -205,18 +1056,72 @@ pub fn reply_init_callback(deps: DepsMut, reply: Reply) -> Result<Response, Cont
 /// of execution. We just return ok if we dispatched, error if we failed to dispatch
 pub fn ibc_packet_receive(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     msg: IbcPacketReceiveMsg,
 ) -> Result<IbcReceiveResponse, ContractError> {
     let packet = msg.packet;
     // which local channel did this packet come on
     let caller = packet.dest.channel_id;
-    let msg: PacketMsg = from_slice(&packet.data)?;
+    // we ack errors ourselves, so they keep their code.
+    // this keeps any state written before the error, so handlers only write once nothing
+    // can fail anymore
+    receive_packet(deps, env, caller, &packet.data).or_else(|err| {
+        Ok(IbcReceiveResponse::new()
+            .set_ack(IcaError::new(err.code(), err.to_string()).ack())
+            .add_attribute("action", "receive_error")
+            .add_attribute("error_code", err.code().as_str()))
+    })
+}
+
+fn receive_packet(
+    deps: DepsMut,
+    env: Env,
+    caller: String,
+    data: &[u8],
+) -> Result<IbcReceiveResponse, ContractError> {
+    if FROZEN_CHANNELS.has(deps.storage, &caller) {
+        return Err(ContractError::ChannelFrozen(caller));
+    }
+    let settings = CHANNEL_SETTINGS
+        .may_load(deps.storage, &caller)?
+        .unwrap_or_default();
+    let msg = PacketMsg::decode(data, settings.version)?;
     match msg {
-        PacketMsg::Dispatch { msgs, .. } => receive_dispatch(deps, caller, msgs),
+        PacketMsg::Dispatch {
+            sender, msgs, mode, ..
+        } => receive_dispatch(deps, env, caller, settings.version, sender, msgs, mode),
         PacketMsg::IbcQuery { msgs, .. } => receive_query(deps.as_ref(), msgs),
-        PacketMsg::WhoAmI {} => receive_who_am_i(deps, caller),
-        PacketMsg::Balances {} => receive_balances(deps, caller),
+        PacketMsg::WhoAmI { sender } => {
+            let sender = packet_sender(deps.storage, &caller, sender)?;
+            receive_who_am_i(deps, env, caller, sender)
+        }
+        PacketMsg::Balances { sender } => {
+            let sender = packet_sender(deps.storage, &caller, sender)?;
+            receive_balances(deps, caller, sender)
+        }
+        PacketMsg::ReclaimFunds { sender, channel_id } => {
+            receive_reclaim_funds(deps, caller, sender, channel_id)
+        }
+        PacketMsg::MigrateAccount {
+            sender,
+            code_id,
+            msg,
+        } => receive_migrate_account(deps, caller, sender, code_id, msg),
+    }
+}
+
+// the sender of a WhoAmI or Balances. Controllers before 0.3.0 send none, and mean the
+// account of the channel
+fn packet_sender(
+    storage: &dyn Storage,
+    channel_id: &str,
+    sender: Option<String>,
+) -> Result<String, ContractError> {
+    match sender {
+        Some(sender) => Ok(sender),
+        None => LEGACY_SENDERS
+            .may_load(storage, channel_id)?
+            .ok_or_else(|| ContractError::NoLegacyAccount(channel_id.to_string())),
     }
 }
 
@@ -226,12 +1131,13 @@ fn unparsed_query(
 ) -> Result<Binary, ContractError> {
     let raw = to_vec(request)?;
     match querier.raw_query(&raw) {
-        SystemResult::Err(system_err) => {
-            Err(StdError::generic_err(format!("Querier system error: {}", system_err)).into())
-        }
-        SystemResult::Ok(ContractResult::Err(contract_err)) => {
-            Err(StdError::generic_err(format!("Querier contract error: {}", contract_err)).into())
-        }
+        SystemResult::Err(system_err) => Err(ContractError::QueryFailed(format!(
+            "Querier system error: {}",
+            system_err
+        ))),
+        SystemResult::Ok(ContractResult::Err(contract_err)) => Err(ContractError::QueryFailed(
+            format!("Querier contract error: {}", contract_err),
+        )),
         SystemResult::Ok(ContractResult::Ok(value)) => Ok(value),
     }
 }
@@ -249,19 +1155,33 @@ fn receive_query(
     }
     let response = IbcQueryResponse { results };
 
-    let acknowledgement = StdAck::success(&response);
+    let acknowledgement = StdAck::success(&response)?;
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
         .add_attribute("action", "receive_ibc_query"))
 }
 
 // processes PacketMsg::WhoAmI variant
-fn receive_who_am_i(deps: DepsMut, caller: String) -> Result<IbcReceiveResponse, ContractError> {
-    let account = ACCOUNTS.load(deps.storage, &caller)?;
+fn receive_who_am_i(
+    deps: DepsMut,
+    env: Env,
+    caller: String,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let account = match ACCOUNTS.may_load(deps.storage, (&caller, &sender))? {
+        Some(account) => account,
+        None => {
+            // the ack is set in the reply, once we know the address
+            let msg = create_account(deps, &env, caller, sender, None, DispatchMode::Atomic)?;
+            return Ok(IbcReceiveResponse::new()
+                .add_submessage(msg)
+                .add_attribute("action", "receive_who_am_i"));
+        }
+    };
     let response = WhoAmIResponse {
         account: account.into(),
     };
-    let acknowledgement = StdAck::success(&response);
+    let acknowledgement = StdAck::success(&response)?;
     // and we are golden
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
@@ -269,48 +1189,219 @@ fn receive_who_am_i(deps: DepsMut, caller: String) -> Result<IbcReceiveResponse,
 }
 
 // processes PacketMsg::Balances variant
-fn receive_balances(deps: DepsMut, caller: String) -> Result<IbcReceiveResponse, ContractError> {
-    let account = ACCOUNTS.load(deps.storage, &caller)?;
+fn receive_balances(
+    deps: DepsMut,
+    caller: String,
+    sender: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let account = ACCOUNTS
+        .may_load(deps.storage, (&caller, &sender))?
+        .ok_or(ContractError::UnregisteredAccount { sender })?;
     let balances = deps.querier.query_all_balances(&account)?;
     let response = BalancesResponse {
         account: account.into(),
         balances,
     };
-    let acknowledgement = StdAck::success(&response);
+    let acknowledgement = StdAck::success(&response)?;
     // and we are golden
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
         .add_attribute("action", "receive_balances"))
 }
 
+// processes PacketMsg::ReclaimFunds variant
+fn receive_reclaim_funds(
+    deps: DepsMut,
+    caller: String,
+    sender: String,
+    channel_id: String,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let rescued = RESCUED_FUNDS
+        .may_load(deps.storage, (&channel_id, &sender))?
+        .ok_or_else(|| ContractError::NoRescuedFunds {
+            channel_id: channel_id.clone(),
+            sender: sender.clone(),
+        })?;
+    // only the same controller, over the same connection, may take them
+    if CHANNELS.may_load(deps.storage, &caller)? != Some(rescued.counterparty) {
+        return Err(ContractError::WrongCounterparty(channel_id));
+    }
+    let account = ACCOUNTS
+        .may_load(deps.storage, (&caller, &sender))?
+        .ok_or_else(|| ContractError::UnregisteredAccount {
+            sender: sender.clone(),
+        })?;
+
+    let msg = BankMsg::Send {
+        to_address: account.to_string(),
+        amount: rescued.balance.clone(),
+    };
+    let response = ReclaimFundsResponse {
+        account: account.into(),
+        amount: rescued.balance,
+    };
+    let acknowledgement = StdAck::success(&response)?;
+    RESCUED_FUNDS.remove(deps.storage, (&channel_id, &sender));
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_message(msg)
+        .add_attribute("action", "receive_reclaim_funds")
+        .add_attribute("channel_id", channel_id))
+}
+
+// processes PacketMsg::MigrateAccount variant
+fn receive_migrate_account(
+    deps: DepsMut,
+    caller: String,
+    sender: String,
+    code_id: u64,
+    msg: Binary,
+) -> Result<IbcReceiveResponse, ContractError> {
+    if !ACCOUNT_CODES.has(deps.storage, code_id) {
+        return Err(ContractError::CodeNotAllowed(code_id));
+    }
+    let account = ACCOUNTS
+        .may_load(deps.storage, (&caller, &sender))?
+        .ok_or(ContractError::UnregisteredAccount { sender })?;
+    if !MANAGED_ACCOUNTS.has(deps.storage, &account) {
+        return Err(ContractError::AccountNotMigratable(account.into()));
+    }
+
+    let migrate = WasmMsg::Migrate {
+        contract_addr: account.to_string(),
+        new_code_id: code_id,
+        msg,
+    };
+    let response = MigrateAccountResponse {
+        account: account.to_string(),
+        code_id,
+    };
+    let acknowledgement = StdAck::success(&response)?;
+
+    // the reply sets the ack, once we know whether it worked
+    let pending = PendingMigration { account, code_id };
+    PENDING_MIGRATION.save(deps.storage, &pending)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessage(SubMsg::reply_always(migrate, MIGRATE_ACCOUNT_ID))
+        .add_attribute("action", "receive_migrate_account")
+        .add_attribute("code_id", code_id.to_string()))
+}
+
 // processes PacketMsg::Dispatch variant
 fn receive_dispatch(
-    deps: DepsMut,
+    mut deps: DepsMut,
+    env: Env,
     caller: String,
+    version: ProtocolVersion,
+    sender: String,
     msgs: Vec<CosmosMsg>,
+    mode: DispatchMode,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    // what is the reflect contract here
-    let reflect_addr = ACCOUNTS.load(deps.storage, &caller)?;
-
-    // let them know we're fine
-    let response = DispatchResponse { results: vec![] };
-    let acknowledgement = StdAck::success(&response);
-    // create the message to re-dispatch to the reflect contract
-    let reflect_msg = cw1_whitelist::msg::ExecuteMsg::Execute { msgs };
-    let wasm_msg = wasm_execute(reflect_addr, &reflect_msg, vec![])?;
+    // refuse the whole packet before anything runs.
+    // spending is only counted once the messages succeed
+    msg_policy(deps.storage, &caller)?.check(&msgs)?;
+    check_spending(deps.storage, &env, &caller, &sender, &msgs)?;
 
-    // we wrap it in a submessage to properly report results
-    let msg = SubMsg::reply_on_success(wasm_msg, RECEIVE_DISPATCH_ID);
+    // the first sender on a v2 channel may be a controller before 0.3.0, whose
+    // WhoAmI and Balances come without a sender
+    let legacy_sender = (version == ProtocolVersion::V2
+        && !LEGACY_SENDERS.has(deps.storage, &caller))
+    .then(|| (caller.clone(), sender.clone()));
 
-    // reset the data field
-    RESULTS.save(deps.storage, &vec![])?;
+    // what is the reflect contract here
+    let (msgs, acknowledgement) = match ACCOUNTS.may_load(deps.storage, (&caller, &sender))? {
+        Some(reflect_addr) => {
+            dispatch_to_account(deps.storage, &env, reflect_addr, caller, sender, msgs, mode)?
+        }
+        None => {
+            // first dispatch of this sender, the messages are sent once the account exists
+            let acknowledgement = StdAck::success(DispatchResponse::default())?;
+            let msg = create_account(deps.branch(), &env, caller, sender, Some(msgs), mode)?;
+            (vec![msg], acknowledgement)
+        }
+    };
+    if let Some((caller, sender)) = legacy_sender {
+        LEGACY_SENDERS.save(deps.storage, &caller, &sender)?;
+    }
 
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
-        .add_submessage(msg)
+        .add_submessages(msgs)
         .add_attribute("action", "receive_dispatch"))
 }
 
+// creates the submessages to re-dispatch to the reflect contract, along with the initial ack
+fn dispatch_to_account(
+    storage: &mut dyn Storage,
+    env: &Env,
+    reflect_addr: Addr,
+    channel_id: String,
+    sender: String,
+    msgs: Vec<CosmosMsg>,
+    mode: DispatchMode,
+) -> StdResult<(Vec<SubMsg>, Binary)> {
+    // let them know we're fine
+    let response = DispatchResponse::default();
+    let acknowledgement = StdAck::success(&response)?;
+
+    let msgs = match mode {
+        DispatchMode::Atomic if msgs.is_empty() => vec![],
+        // we run them all in a call to ourselves, which fails or succeeds as a whole
+        DispatchMode::Atomic => {
+            let pending = PendingSpending {
+                channel_id,
+                sender,
+                amount: outflow(&msgs).into_vec(),
+            };
+            let msg = ExecuteMsg::DispatchAtomic {
+                account: reflect_addr.into(),
+                msgs,
+            };
+            let wasm_msg = wasm_execute(&env.contract.address, &msg, vec![])?;
+            PENDING_SPENDING.save(storage, &pending)?;
+            vec![SubMsg::reply_always(wasm_msg, RECEIVE_DISPATCH_ID)]
+        }
+        // only the first one goes now, the reply decides whether to go on
+        DispatchMode::StopOnError | DispatchMode::BestEffort => {
+            let mut msgs = msgs.into_iter();
+            match msgs.next() {
+                Some(first) => {
+                    let pending = PendingSpending {
+                        channel_id,
+                        sender,
+                        amount: outflow(slice::from_ref(&first)).into_vec(),
+                    };
+                    let wasm_msg = reflect_execute(storage, &reflect_addr, first)?;
+                    let dispatch = PartialDispatch {
+                        account: reflect_addr,
+                        mode,
+                        msgs: msgs.collect(),
+                    };
+                    PENDING_SPENDING.save(storage, &pending)?;
+                    PARTIAL_DISPATCH.save(storage, &dispatch)?;
+                    vec![SubMsg::reply_always(wasm_msg, PARTIAL_DISPATCH_ID)]
+                }
+                None => vec![],
+            }
+        }
+    };
+
+    // reset the data field
+    RESULTS.save(storage, &response)?;
+
+    Ok((msgs, acknowledgement))
+}
+
+// has the reflect contract execute a single message
+fn reflect_execute(
+    storage: &dyn Storage,
+    reflect_addr: &Addr,
+    msg: CosmosMsg,
+) -> StdResult<WasmMsg> {
+    account_execute(storage, reflect_addr, vec![msg])
+}
+
 #[entry_point]
 /// never should be called as we do not send packets
 pub fn ibc_packet_ack(
@@ -334,27 +1425,36 @@ pub fn ibc_packet_timeout(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::limits::DenomLimit;
+    use crate::policy::MsgKind;
     use cosmwasm_std::testing::{
-        mock_dependencies, mock_env, mock_ibc_channel_close_init, mock_ibc_channel_connect_ack,
-        mock_ibc_channel_open_init, mock_ibc_channel_open_try, mock_ibc_packet_recv, mock_info,
-        mock_wasmd_attr, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+        mock_dependencies, mock_env, mock_ibc_channel, mock_ibc_channel_close_init,
+        mock_ibc_channel_connect_ack, mock_ibc_channel_open_init, mock_ibc_channel_open_try,
+        mock_ibc_packet_recv, mock_info, mock_wasmd_attr, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
     };
     use cosmwasm_std::{
-        attr, coin, coins, from_slice, BankMsg, Binary, OwnedDeps, SubMsgResponse, SubMsgResult,
-        WasmMsg,
+        attr, coin, coins, from_slice, BankMsg, Binary, IbcOrder, OwnedDeps, ReplyOn,
+        SubMsgResponse, SubMsgResult, Uint128, WasmMsg,
+    };
+    use simple_ica::{
+        ChannelSettings, PortFilter, ProtocolVersion, SimpleIcaError, APP_ORDER, BAD_APP_ORDER,
+        IBC_APP_VERSION,
     };
-    use simple_ica::{APP_ORDER, BAD_APP_ORDER};
 
     const CREATOR: &str = "creator";
     // code id of the reflect contract
     const REFLECT_ID: u64 = 101;
     // address of first reflect contract instance that we created
     const REFLECT_ADDR: &str = "reflect-acct-1";
+    // sender of the packets on the controller chain
+    const SENDER: &str = "controller-user";
 
     fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
         let mut deps = mock_dependencies();
         let msg = InstantiateMsg {
             cw1_code_id: REFLECT_ID,
+            account_backend: None,
         };
         let info = mock_info(CREATOR, &[]);
         let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -378,11 +1478,21 @@ mod tests {
         vec![event]
     }
 
-    // connect will run through the entire handshake to set up a proper connect and
-    // save the account (tested in detail in `proper_handshake_flow`)
-    fn connect(mut deps: DepsMut, channel_id: &str, account: impl Into<String>) {
-        let account: String = account.into();
+    // fake a successful instantiation of the reflect contract
+    fn init_reply(deps: DepsMut, id: u64, account: &str) -> Response {
+        let response = Reply {
+            id,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: fake_events(account),
+                data: Some(fake_data(account)),
+            }),
+        };
+        reply(deps, mock_env(), response).unwrap()
+    }
 
+    // connect will run through the entire handshake to set up a proper connect
+    // (tested in detail in `proper_handshake_flow`)
+    fn connect(mut deps: DepsMut, channel_id: &str) {
         let handshake_open = mock_ibc_channel_open_init(channel_id, APP_ORDER, IBC_APP_VERSION);
         // first we try to open with a valid handshake
         ibc_channel_open(deps.branch(), mock_env(), handshake_open).unwrap();
@@ -391,183 +1501,1399 @@ mod tests {
         let handshake_connect =
             mock_ibc_channel_connect_ack(channel_id, APP_ORDER, IBC_APP_VERSION);
         let res = ibc_channel_connect(deps.branch(), mock_env(), handshake_connect).unwrap();
-        assert_eq!(1, res.messages.len());
+        assert_eq!(0, res.messages.len());
         assert_eq!(1, res.events.len());
         assert_eq!(
             Event::new("ibc").add_attribute("channel", "connect"),
             res.events[0]
         );
-        let id = res.messages[0].id;
+    }
 
-        // fake a reply and ensure this works
-        let response = Reply {
-            id,
-            result: SubMsgResult::Ok(SubMsgResponse {
-                events: fake_events(&account),
-                data: Some(fake_data(&account)),
-            }),
+    // receives a packet that must fail, and returns the error in its ack
+    fn receive_err(deps: DepsMut, msg: IbcPacketReceiveMsg) -> IcaError {
+        let res = ibc_packet_receive(deps, mock_env(), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        ack.ica_error().unwrap()
+    }
+
+    fn ica_error(err: ContractError) -> IcaError {
+        IcaError::new(err.code(), err.to_string())
+    }
+
+    // who_am_i creates the account for this sender if needed and returns the ack
+    fn who_am_i(mut deps: DepsMut, channel_id: &str, sender: &str, account: &str) -> StdAck {
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(sender.into()),
         };
-        reply(deps.branch(), mock_env(), response).unwrap();
+        let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+        let res = ibc_packet_receive(deps.branch(), mock_env(), msg).unwrap();
+        if res.messages.is_empty() {
+            return from_slice(&res.acknowledgement).unwrap();
+        }
+        // the reply sets the ack once the account exists
+        assert_eq!(1, res.messages.len());
+        let res = init_reply(deps, res.messages[0].id, account);
+        from_slice(&res.data.unwrap()).unwrap()
     }
 
     #[test]
     fn instantiate_works() {
         let mut deps = mock_dependencies();
 
-        let msg = InstantiateMsg { cw1_code_id: 17 };
+        let msg = InstantiateMsg {
+            cw1_code_id: 17,
+            account_backend: None,
+        };
         let info = mock_info("creator", &[]);
         let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len())
+        assert_eq!(0, res.messages.len());
+
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(
+            cfg,
+            ConfigResponse {
+                admin: "creator".to_string(),
+                pending_admin: None,
+                cw1_code_id: 17,
+                account_backend: AccountBackend::Cw1Whitelist,
+            }
+        );
     }
 
     #[test]
-    fn enforce_version_in_handshake() {
+    fn admin_transfer_takes_two_steps() {
         let mut deps = setup();
+        let transfer = |admin: &str| ExecuteMsg::TransferAdmin {
+            admin: admin.to_string(),
+        };
 
-        let wrong_order = mock_ibc_channel_open_try("channel-12", BAD_APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_open(deps.as_mut(), mock_env(), wrong_order).unwrap_err();
+        // only the admin may propose, and nothing to accept before
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            transfer("gov"),
+        )
+        .unwrap_err();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::AcceptAdmin {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoPendingAdmin);
 
-        let wrong_version = mock_ibc_channel_open_try("channel-12", APP_ORDER, "reflect");
-        ibc_channel_open(deps.as_mut(), mock_env(), wrong_version).unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            transfer("gov"),
+        )
+        .unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(CREATOR, cfg.admin);
+        assert_eq!(Some("gov".to_string()), cfg.pending_admin);
 
-        let valid_handshake = mock_ibc_channel_open_try("channel-12", APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
+        // only the proposed admin may accept
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("intruder", &[]),
+            ExecuteMsg::AcceptAdmin {},
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::AcceptAdmin {},
+        )
+        .unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!("gov", cfg.admin);
+        assert_eq!(None, cfg.pending_admin);
+
+        // the old admin has no more rights
+        let msg = ExecuteMsg::UpdateAccountBackend {
+            cw1_code_id: 202,
+            account_backend: AccountBackend::Cw1Whitelist,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info("gov", &[]), msg).unwrap();
+        assert_eq!(202, query_config(deps.as_ref()).unwrap().cw1_code_id);
     }
 
     #[test]
-    fn proper_handshake_flow() {
+    fn accounts_keep_their_backend() {
         let mut deps = setup();
-        let channel_id = "channel-1234";
-
-        // first we try to open with a valid handshake
-        let handshake_open = mock_ibc_channel_open_init(channel_id, APP_ORDER, IBC_APP_VERSION);
-        ibc_channel_open(deps.as_mut(), mock_env(), handshake_open).unwrap();
+        let channel_id = "channel-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, REFLECT_ADDR);
+
+        // only the admin switches
+        let reflect = AccountBackend::Reflect {
+            instantiate_msg: b"{}".into(),
+            execute_msg: "reflect_msg".into(),
+            msgs_field: "msgs".into(),
+        };
+        let msg = ExecuteMsg::UpdateAccountBackend {
+            cw1_code_id: 202,
+            account_backend: reflect.clone(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SENDER, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let cfg = query_config(deps.as_ref()).unwrap();
+        assert_eq!(202, cfg.cw1_code_id);
+        assert_eq!(reflect, cfg.account_backend);
+
+        // new accounts are created from the new code
+        let packet = PacketMsg::WhoAmI {
+            sender: Some("other-user".into()),
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { code_id, msg, .. }) => {
+                assert_eq!(202, *code_id);
+                assert_eq!(Binary::from(b"{}"), *msg);
+            }
+            o => panic!("Unexpected message: {:?}", o),
+        }
+        init_reply(deps.as_mut(), res.messages[0].id, "reflect-acct-2");
 
-        // then we connect (with counter-party version set)
-        let handshake_connect =
-            mock_ibc_channel_connect_ack(channel_id, APP_ORDER, IBC_APP_VERSION);
-        let res = ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
-        // and set up a reflect account
-        assert_eq!(1, res.messages.len());
-        let id = res.messages[0].id;
-        if let CosmosMsg::Wasm(WasmMsg::Instantiate {
-            admin,
-            code_id,
-            msg: _,
-            funds,
-            label,
-        }) = &res.messages[0].msg
-        {
-            assert_eq!(*admin, None);
-            assert_eq!(*code_id, REFLECT_ID);
-            assert_eq!(funds.len(), 0);
-            assert!(label.contains(channel_id));
-        } else {
-            panic!("invalid return message: {:?}", res.messages[0]);
+        // and each account is talked to in its own way
+        let send: CosmosMsg = BankMsg::Send {
+            to_address: "my-friend".into(),
+            amount: coins(1000, "uatom"),
         }
+        .into();
+        let old = Addr::unchecked(REFLECT_ADDR);
+        let new = Addr::unchecked("reflect-acct-2");
+        let old_msg = reflect_execute(&deps.storage, &old, send.clone()).unwrap();
+        let new_msg = reflect_execute(&deps.storage, &new, send.clone()).unwrap();
+        let cw1 = AccountBackend::Cw1Whitelist;
+        assert_eq!(
+            cw1.execute_msg(vec![send.clone()]).unwrap(),
+            msg_of(old_msg)
+        );
+        assert_eq!(reflect.execute_msg(vec![send]).unwrap(), msg_of(new_msg));
+    }
 
-        // no accounts set yet
-        let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap();
-        let res: ListAccountsResponse = from_slice(&raw).unwrap();
-        assert_eq!(0, res.accounts.len());
+    #[test]
+    fn accounts_can_be_migrated() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, REFLECT_ADDR);
+        // an account from before we were its admin
+        let legacy = Addr::unchecked("reflect-legacy");
+        ACCOUNTS
+            .save(&mut deps.storage, (channel_id, "legacy-user"), &legacy)
+            .unwrap();
+
+        let packet = |sender: &str| PacketMsg::MigrateAccount {
+            sender: sender.into(),
+            code_id: 202,
+            msg: b"{}".into(),
+        };
+        let recv = |deps: DepsMut, sender: &str| {
+            let msg = mock_ibc_packet_recv(channel_id, &packet(sender)).unwrap();
+            ibc_packet_receive(deps, mock_env(), msg).unwrap()
+        };
 
-        // fake a reply and ensure this works
-        let response = Reply {
-            id,
+        // only to allowed codes
+        let msg = mock_ibc_packet_recv(channel_id, &packet(SENDER)).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(ica_error(ContractError::CodeNotAllowed(202)), err);
+        assert_eq!(ErrorCode::Unauthorized, err.code);
+
+        let reflect = AccountBackend::Reflect {
+            instantiate_msg: b"{}".into(),
+            execute_msg: "reflect_msg".into(),
+            msgs_field: "msgs".into(),
+        };
+        let allow = ExecuteMsg::AllowAccountCode {
+            code_id: 202,
+            account_backend: reflect.clone(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SENDER, &[]),
+            allow.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), allow).unwrap();
+        let res = query_list_account_codes(deps.as_ref()).unwrap();
+        assert_eq!(
+            vec![AccountCode {
+                code_id: 202,
+                account_backend: reflect.clone()
+            }],
+            res.codes
+        );
+
+        // accounts without us as admin cannot be
+        let msg = mock_ibc_packet_recv(channel_id, &packet("legacy-user")).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(
+            ica_error(ContractError::AccountNotMigratable(legacy.to_string())),
+            err
+        );
+
+        // the reply sets the ack
+        let res = recv(deps.as_mut(), SENDER);
+        assert_eq!(1, res.messages.len());
+        assert_eq!(MIGRATE_ACCOUNT_ID, res.messages[0].id);
+        assert_eq!(ReplyOn::Always, res.messages[0].reply_on);
+        assert_eq!(
+            CosmosMsg::Wasm(WasmMsg::Migrate {
+                contract_addr: REFLECT_ADDR.into(),
+                new_code_id: 202,
+                msg: b"{}".into(),
+            }),
+            res.messages[0].msg
+        );
+        let failed = Reply {
+            id: MIGRATE_ACCOUNT_ID,
+            result: SubMsgResult::Err("no migrate entry point".into()),
+        };
+        let res = reply(deps.as_mut(), mock_env(), failed).unwrap();
+        let ack: StdAck = from_slice(&res.data.unwrap()).unwrap();
+        assert_eq!(ErrorCode::DispatchFailed, ack.ica_error().unwrap().code);
+        // and the account is still talked to as before
+        let account = Addr::unchecked(REFLECT_ADDR);
+        assert!(ACCOUNT_BACKENDS
+            .load(&deps.storage, &account)
+            .map(|backend| backend == AccountBackend::Cw1Whitelist)
+            .unwrap());
+
+        recv(deps.as_mut(), SENDER);
+        let migrated = Reply {
+            id: MIGRATE_ACCOUNT_ID,
             result: SubMsgResult::Ok(SubMsgResponse {
-                events: fake_events(REFLECT_ADDR),
-                data: Some(fake_data(REFLECT_ADDR)),
+                events: vec![],
+                data: None,
             }),
         };
-        reply(deps.as_mut(), mock_env(), response).unwrap();
-
-        // ensure this is now registered
-        let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap();
-        let res: ListAccountsResponse = from_slice(&raw).unwrap();
-        assert_eq!(1, res.accounts.len());
+        let res = reply(deps.as_mut(), mock_env(), migrated).unwrap();
+        let ack: StdAck = from_slice(&res.data.unwrap()).unwrap();
+        let res: MigrateAccountResponse = from_slice(&ack.unwrap()).unwrap();
+        assert_eq!(REFLECT_ADDR, res.account);
         assert_eq!(
-            &res.accounts[0],
-            &AccountInfo {
-                account: REFLECT_ADDR.into(),
-                channel_id: channel_id.to_string(),
-            }
+            reflect,
+            ACCOUNT_BACKENDS.load(&deps.storage, &account).unwrap()
         );
 
-        // and the account query also works
-        let raw = query(
-            deps.as_ref(),
+        // the admin migrates them in bulk, skipping those it cannot
+        let migrate = ExecuteMsg::MigrateAccounts {
+            code_id: 203,
+            msg: b"{}".into(),
+            channel_id: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), migrate).unwrap_err();
+        assert_eq!(ContractError::CodeNotAllowed(203), err);
+        let migrate = ExecuteMsg::MigrateAccounts {
+            code_id: 202,
+            msg: b"{}".into(),
+            channel_id: Some(channel_id.into()),
+        };
+        execute(
+            deps.as_mut(),
             mock_env(),
-            QueryMsg::Account {
-                channel_id: channel_id.to_string(),
-            },
+            mock_info(SENDER, &[]),
+            migrate.clone(),
         )
-        .unwrap();
-        let res: AccountResponse = from_slice(&raw).unwrap();
-        assert_eq!(res.account.unwrap(), REFLECT_ADDR);
+        .unwrap_err();
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), migrate).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert!(res.attributes.contains(&attr("skipped", "1")));
+    }
+
+    fn msg_of(wasm_msg: WasmMsg) -> Binary {
+        match wasm_msg {
+            WasmMsg::Execute { msg, .. } => msg,
+            o => panic!("Unexpected message: {:?}", o),
+        }
     }
 
     #[test]
-    fn handle_dispatch_packet() {
+    fn frozen_channel_rejects_packets() {
         let mut deps = setup();
-
         let channel_id = "channel-123";
-        let account = "acct-123";
-
-        // receive a packet for an unregistered channel returns app-level error (not Result::Err)
-        let msgs_to_dispatch = vec![BankMsg::Send {
-            to_address: "my-friend".into(),
-            amount: coins(123456789, "uatom"),
-        }
-        .into()];
-        let ibc_msg = PacketMsg::Dispatch {
-            msgs: msgs_to_dispatch.clone(),
-            sender: account.to_string(),
-            callback_id: None,
+        connect(deps.as_mut(), channel_id);
+        let freeze = ExecuteMsg::FreezeChannel {
+            channel_id: channel_id.into(),
         };
-        let msg = mock_ibc_packet_recv(channel_id, &ibc_msg).unwrap();
-        // this returns an error
-        ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap_err();
-
-        // register the channel
-        connect(deps.as_mut(), channel_id, account);
 
-        // receive a packet for an unregistered channel returns app-level error (not Result::Err)
-        let msg = mock_ibc_packet_recv(channel_id, &ibc_msg).unwrap();
-        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        // only the admin may freeze
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SENDER, &[]),
+            freeze.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), freeze).unwrap();
+        assert_eq!(
+            vec![channel_id.to_string()],
+            query_list_frozen_channels(deps.as_ref()).unwrap().channels
+        );
 
-        // assert app-level success
-        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
-        ack.unwrap();
+        // packets get an error ack, and create no account
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(SENDER.into()),
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+        let err = receive_err(deps.as_mut(), msg.clone());
+        assert_eq!(ErrorCode::Frozen, err.code);
+        assert_eq!(
+            err,
+            ica_error(ContractError::ChannelFrozen(channel_id.into()))
+        );
 
-        // and we dispatch the BankMsg via submessage
+        // until unfrozen
+        let unfreeze = ExecuteMsg::UnfreezeChannel {
+            channel_id: channel_id.into(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), unfreeze).unwrap();
+        assert_eq!(
+            0,
+            query_list_frozen_channels(deps.as_ref())
+                .unwrap()
+                .channels
+                .len()
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
         assert_eq!(1, res.messages.len());
-        assert_eq!(RECEIVE_DISPATCH_ID, res.messages[0].id);
+    }
 
-        // parse the output, ensuring it matches
-        if let CosmosMsg::Wasm(WasmMsg::Execute {
+    #[test]
+    fn admin_closes_channel() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        connect(deps.as_mut(), channel_id);
+        let close = ExecuteMsg::CloseChannel {
+            channel_id: channel_id.into(),
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SENDER, &[]),
+            close.clone(),
+        )
+        .unwrap_err();
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), close).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(
+            res.messages[0].msg,
+            IbcMsg::CloseChannel {
+                channel_id: channel_id.into()
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn enforce_version_in_handshake() {
+        let mut deps = setup();
+
+        let wrong_order = mock_ibc_channel_open_try("channel-12", BAD_APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), wrong_order).unwrap_err();
+
+        let wrong_version = mock_ibc_channel_open_try("channel-12", APP_ORDER, "reflect");
+        ibc_channel_open(deps.as_mut(), mock_env(), wrong_version).unwrap_err();
+
+        let valid_handshake = mock_ibc_channel_open_try("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
+    }
+
+    #[test]
+    fn counterparty_policy_restricts_handshake() {
+        let mut deps = setup();
+        let open_try = |deps: DepsMut| {
+            let msg = mock_ibc_channel_open_try("channel-12", APP_ORDER, IBC_APP_VERSION);
+            ibc_channel_open(deps, mock_env(), msg)
+        };
+        let update = |deps: DepsMut, sender: &str, policy: CounterpartyPolicy| {
+            let msg = ExecuteMsg::UpdateCounterpartyPolicy { policy };
+            execute(deps, mock_env(), mock_info(sender, &[]), msg)
+        };
+
+        // open to all by default
+        let res = query_counterparty_policy(deps.as_ref()).unwrap();
+        assert_eq!(CounterpartyPolicy::default(), res.policy);
+        open_try(deps.as_mut()).unwrap();
+
+        // only the admin may restrict it
+        let policy = CounterpartyPolicy {
+            connections: Some(vec!["connection-1".into()]),
+            ports: None,
+        };
+        update(deps.as_mut(), "intruder", policy.clone()).unwrap_err();
+        update(deps.as_mut(), CREATOR, policy.clone()).unwrap();
+        assert_eq!(
+            policy,
+            query_counterparty_policy(deps.as_ref()).unwrap().policy
+        );
+        let err = open_try(deps.as_mut()).unwrap_err();
+        assert_eq!(
+            ContractError::SimpleIca(SimpleIcaError::ConnectionNotAllowed("connection-2".into())),
+            err
+        );
+
+        // ports match exactly or by prefix
+        let policy = |port: PortFilter| CounterpartyPolicy {
+            connections: Some(vec!["connection-1".into(), "connection-2".into()]),
+            ports: Some(vec![port]),
+        };
+        update(
+            deps.as_mut(),
+            CREATOR,
+            policy(PortFilter::Exact("their".into())),
+        )
+        .unwrap();
+        let err = open_try(deps.as_mut()).unwrap_err();
+        assert_eq!(
+            ContractError::SimpleIca(SimpleIcaError::PortNotAllowed("their_port".into())),
+            err
+        );
+        update(
+            deps.as_mut(),
+            CREATOR,
+            policy(PortFilter::Prefix("their".into())),
+        )
+        .unwrap();
+        open_try(deps.as_mut()).unwrap();
+        update(
+            deps.as_mut(),
+            CREATOR,
+            policy(PortFilter::Exact("their_port".into())),
+        )
+        .unwrap();
+        open_try(deps.as_mut()).unwrap();
+
+        // and it applies when we open the channel as well
+        let msg = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap();
+        update(
+            deps.as_mut(),
+            CREATOR,
+            policy(PortFilter::Prefix("wasm.".into())),
+        )
+        .unwrap();
+        let msg = mock_ibc_channel_open_init("channel-12", APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap_err();
+    }
+
+    #[test]
+    fn negotiate_version_in_handshake() {
+        let mut deps = setup();
+        let open_try = |deps: DepsMut, proposal: &str| {
+            let msg = mock_ibc_channel_open_try("channel-12", APP_ORDER, proposal);
+            ibc_channel_open(deps, mock_env(), msg).map(|res| res.unwrap().version)
+        };
+
+        // we pick the newest version we share
+        let v2 = ProtocolVersion::V2.as_str();
+        let v3 = ProtocolVersion::V3.as_str();
+        assert_eq!(
+            v3,
+            open_try(deps.as_mut(), "simple-ica-v2,simple-ica-v3").unwrap()
+        );
+        assert_eq!(v2, open_try(deps.as_mut(), "simple-ica-v2").unwrap());
+        assert_eq!(
+            v2,
+            open_try(deps.as_mut(), "simple-ica-v9,simple-ica-v2").unwrap()
+        );
+        open_try(deps.as_mut(), "simple-ica-v9").unwrap_err();
+
+        // and propose all we know if opening without a version
+        let msg = mock_ibc_channel_open_init("channel-12", APP_ORDER, "");
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!("simple-ica-v3,simple-ica-v2", res.unwrap().version);
+
+        // packets are read in the version of their channel
+        let channel_id = "channel-12";
+        let msg = mock_ibc_channel_connect_ack(channel_id, APP_ORDER, v3);
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            ProtocolVersion::V3,
+            CHANNEL_SETTINGS
+                .load(&deps.storage, channel_id)
+                .unwrap()
+                .version
+        );
+        let packet = PacketMsg::Balances {
+            sender: Some(SENDER.into()),
+        };
+        let mut msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+        assert_eq!(
+            ErrorCode::InvalidPacket,
+            receive_err(deps.as_mut(), msg.clone()).code
+        );
+        msg.packet.data = packet.encode(ProtocolVersion::V3).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(ErrorCode::UnregisteredAccount, err.code);
+    }
+
+    #[test]
+    fn ordered_channels_need_v3() {
+        let mut deps = setup();
+        let v3 = ProtocolVersion::V3.as_str();
+
+        // simple-ica-v2 is unordered only
+        let msg = mock_ibc_channel_open_try("channel-12", IbcOrder::Ordered, "simple-ica-v2");
+        let err = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(
+            ContractError::SimpleIca(SimpleIcaError::InvalidChannelOrder(v3.to_string())),
+            err
+        );
+        let msg = mock_ibc_channel_open_try(
+            "channel-12",
+            IbcOrder::Ordered,
+            "simple-ica-v2,simple-ica-v3",
+        );
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(v3, res.unwrap().version);
+
+        // so we only propose the versions supporting the order
+        let msg = mock_ibc_channel_open_init("channel-12", IbcOrder::Ordered, "");
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(v3, res.unwrap().version);
+
+        // and the order is recorded with the version
+        let msg = mock_ibc_channel_connect_ack("channel-12", IbcOrder::Ordered, v3);
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        let settings = CHANNEL_SETTINGS.load(&deps.storage, "channel-12").unwrap();
+        assert_eq!(
+            ChannelSettings {
+                version: ProtocolVersion::V3,
+                order: IbcOrder::Ordered,
+            },
+            settings
+        );
+        let msg = mock_ibc_channel_connect_ack("channel-13", IbcOrder::Ordered, "simple-ica-v2");
+        ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap_err();
+    }
+
+    #[test]
+    fn proper_handshake_flow() {
+        let mut deps = setup();
+        let channel_id = "channel-1234";
+
+        // first we try to open with a valid handshake
+        let handshake_open = mock_ibc_channel_open_init(channel_id, APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), handshake_open).unwrap();
+
+        // then we connect (with counter-party version set), no accounts yet
+        let handshake_connect =
+            mock_ibc_channel_connect_ack(channel_id, APP_ORDER, IBC_APP_VERSION);
+        let res = ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // the first WhoAmI of a sender sets up a reflect account
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(SENDER.into()),
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let id = res.messages[0].id;
+        if let CosmosMsg::Wasm(WasmMsg::Instantiate {
+            admin,
+            code_id,
+            msg: _,
+            funds,
+            label,
+        }) = &res.messages[0].msg
+        {
+            assert_eq!(*admin, Some(MOCK_CONTRACT_ADDR.to_string()));
+            assert_eq!(*code_id, REFLECT_ID);
+            assert_eq!(funds.len(), 0);
+            assert!(label.contains(channel_id));
+            assert!(label.contains(SENDER));
+        } else {
+            panic!("invalid return message: {:?}", res.messages[0]);
+        }
+
+        // no accounts set yet
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap();
+        let res: ListAccountsResponse = from_slice(&raw).unwrap();
+        assert_eq!(0, res.accounts.len());
+
+        // fake a reply and ensure this works, reporting the new address
+        let res = init_reply(deps.as_mut(), id, REFLECT_ADDR);
+        let ack: StdAck = from_slice(&res.data.unwrap()).unwrap();
+        let who: WhoAmIResponse = ack.unwrap_into();
+        assert_eq!(who.account, REFLECT_ADDR);
+
+        // ensure this is now registered
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap();
+        let res: ListAccountsResponse = from_slice(&raw).unwrap();
+        assert_eq!(1, res.accounts.len());
+        assert_eq!(
+            &res.accounts[0],
+            &AccountInfo {
+                account: REFLECT_ADDR.into(),
+                channel_id: channel_id.to_string(),
+                sender: SENDER.to_string(),
+            }
+        );
+
+        // and the account query also works
+        let raw = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Account {
+                channel_id: channel_id.to_string(),
+                sender: SENDER.to_string(),
+            },
+        )
+        .unwrap();
+        let res: AccountResponse = from_slice(&raw).unwrap();
+        assert_eq!(res.account.unwrap(), REFLECT_ADDR);
+
+        // a second WhoAmI just returns the address
+        let ack = who_am_i(deps.as_mut(), channel_id, SENDER, "not-used");
+        let who: WhoAmIResponse = ack.unwrap_into();
+        assert_eq!(who.account, REFLECT_ADDR);
+    }
+
+    #[test]
+    fn concurrent_account_creation() {
+        let mut deps = setup();
+        let channels = ["channel-1", "channel-2", "channel-3"];
+
+        // several channels open, and each creates an account before any reply runs
+        let mut reply_ids = vec![];
+        for channel_id in channels {
+            connect(deps.as_mut(), channel_id);
+            let packet = PacketMsg::WhoAmI {
+                sender: Some(SENDER.into()),
+            };
+            let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+            let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+            assert_eq!(1, res.messages.len());
+            reply_ids.push(res.messages[0].id);
+        }
+        assert_eq!(
+            vec![INIT_CALLBACK_ID, INIT_CALLBACK_ID + 1, INIT_CALLBACK_ID + 2],
+            reply_ids
+        );
+
+        // the replies arrive in another order, yet every account lands on its own channel
+        for i in [2, 0, 1] {
+            let account = format!("acct-{}", i);
+            let res = init_reply(deps.as_mut(), reply_ids[i], &account);
+            let ack: StdAck = from_slice(&res.data.unwrap()).unwrap();
+            let res: WhoAmIResponse = ack.unwrap_into();
+            assert_eq!(account, res.account);
+        }
+        for (i, channel_id) in channels.iter().enumerate() {
+            let res = query_account(deps.as_ref(), channel_id.to_string(), SENDER.into()).unwrap();
+            assert_eq!(Some(format!("acct-{}", i)), res.account);
+        }
+
+        // a reply can only be used once
+        let response = Reply {
+            id: reply_ids[0],
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: fake_events("acct-9"),
+                data: Some(fake_data("acct-9")),
+            }),
+        };
+        let err = reply(deps.as_mut(), mock_env(), response).unwrap_err();
+        assert_eq!(err, ContractError::InvalidReplyId);
+    }
+
+    #[test]
+    fn msg_policy_rejects_dispatch() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, REFLECT_ADDR);
+
+        let send: CosmosMsg = BankMsg::Send {
+            to_address: "my-friend".into(),
+            amount: coins(1000, "uatom"),
+        }
+        .into();
+        let burn: CosmosMsg = BankMsg::Burn {
+            amount: coins(1000, "uatom"),
+        }
+        .into();
+        let dispatch = |deps: DepsMut, msgs: Vec<CosmosMsg>| {
+            let packet = PacketMsg::Dispatch {
+                msgs,
+                sender: SENDER.to_string(),
+                callback_id: None,
+                mode: DispatchMode::Atomic,
+            };
+            let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+            ibc_packet_receive(deps, mock_env(), msg).unwrap()
+        };
+        let update = |deps: DepsMut, sender: &str, msg: ExecuteMsg| {
+            execute(deps, mock_env(), mock_info(sender, &[]), msg)
+        };
+
+        // anything goes by default
+        assert_eq!(
+            MsgPolicy::AllowAll,
+            query_msg_policy(deps.as_ref(), channel_id.into())
+                .unwrap()
+                .policy
+        );
+        let res = dispatch(deps.as_mut(), vec![send.clone(), burn.clone()]);
+        assert_eq!(1, res.messages.len());
+
+        // only the admin sets the default
+        let bank_send = MsgPolicy::AllowOnly {
+            kinds: vec![MsgKind::BankSend],
+            wasm_contracts: None,
+        };
+        let msg = ExecuteMsg::UpdateDefaultMsgPolicy {
+            policy: bank_send.clone(),
+        };
+        update(deps.as_mut(), SENDER, msg.clone()).unwrap_err();
+        update(deps.as_mut(), CREATOR, msg).unwrap();
+        let res = query_default_msg_policy(deps.as_ref()).unwrap();
+        assert_eq!(bank_send, res.policy);
+
+        // and the whole packet is refused before it reaches the account
+        let msg = mock_ibc_packet_recv(
+            channel_id,
+            &PacketMsg::Dispatch {
+                msgs: vec![send.clone(), burn.clone()],
+                sender: SENDER.to_string(),
+                callback_id: None,
+                mode: DispatchMode::BestEffort,
+            },
+        )
+        .unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(
+            ica_error(ContractError::MsgNotAllowed {
+                index: 1,
+                kind: "bank_burn".into()
+            }),
+            err
+        );
+        assert_eq!(ErrorCode::MsgNotAllowed, err.code);
+        let res = dispatch(deps.as_mut(), vec![send.clone()]);
+        assert_eq!(1, res.messages.len());
+
+        // a channel policy overrides the default, until it is removed
+        let msg = ExecuteMsg::UpdateChannelMsgPolicy {
+            channel_id: channel_id.into(),
+            policy: Some(MsgPolicy::AllowAll),
+        };
+        update(deps.as_mut(), CREATOR, msg).unwrap();
+        let res = dispatch(deps.as_mut(), vec![burn.clone()]);
+        assert_eq!(1, res.messages.len());
+        let res = query_list_msg_policies(deps.as_ref()).unwrap();
+        assert_eq!(
+            vec![ChannelMsgPolicy {
+                channel_id: channel_id.into(),
+                policy: MsgPolicy::AllowAll
+            }],
+            res.policies
+        );
+
+        let msg = ExecuteMsg::UpdateChannelMsgPolicy {
+            channel_id: channel_id.into(),
+            policy: None,
+        };
+        update(deps.as_mut(), CREATOR, msg).unwrap();
+        let res = query_msg_policy(deps.as_ref(), channel_id.into()).unwrap();
+        assert_eq!(bank_send, res.policy);
+        let res = dispatch(deps.as_mut(), vec![burn]);
+        assert_eq!(0, res.messages.len());
+    }
+
+    #[test]
+    fn spending_limit_rejects_dispatch() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, REFLECT_ADDR);
+
+        let packet = |amount: u128| PacketMsg::Dispatch {
+            msgs: vec![BankMsg::Send {
+                to_address: "my-friend".into(),
+                amount: coins(amount, "uatom"),
+            }
+            .into()],
+            sender: SENDER.to_string(),
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+
+        // only the admin sets limits
+        let limits = SpendingLimits {
+            window: 3600,
+            limits: vec![DenomLimit {
+                denom: "uatom".into(),
+                per_packet: None,
+                per_window: Some(Uint128::new(1000)),
+            }],
+        };
+        let msg = ExecuteMsg::UpdateChannelLimits {
+            channel_id: channel_id.into(),
+            limits: Some(limits.clone()),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SENDER, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        // the spending counts once the dispatch succeeds
+        let ok = || {
+            SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            })
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &packet(700)).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), channel_id.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(Uint128::new(1000)), res.allowances[0].remaining);
+        let result = ok();
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: RECEIVE_DISPATCH_ID,
+                result,
+            },
+        )
+        .unwrap();
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), channel_id.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(limits), res.limits);
+        assert_eq!(Some(Uint128::new(300)), res.allowances[0].remaining);
+
+        // a failed dispatch spends nothing
+        let msg = mock_ibc_packet_recv(channel_id, &packet(300)).unwrap();
+        ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let result = SubMsgResult::Err("insufficient funds".into());
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: RECEIVE_DISPATCH_ID,
+                result,
+            },
+        )
+        .unwrap();
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), channel_id.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(Uint128::new(300)), res.allowances[0].remaining);
+
+        // and in the partial modes, only the messages that succeed count
+        let send = |amount: u128| -> CosmosMsg {
+            BankMsg::Send {
+                to_address: "my-friend".into(),
+                amount: coins(amount, "uatom"),
+            }
+            .into()
+        };
+        let best_effort = PacketMsg::Dispatch {
+            msgs: vec![send(100), send(50)],
+            sender: SENDER.to_string(),
+            callback_id: None,
+            mode: DispatchMode::BestEffort,
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &best_effort).unwrap();
+        ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let result = SubMsgResult::Err("insufficient funds".into());
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: PARTIAL_DISPATCH_ID,
+                result,
+            },
+        )
+        .unwrap();
+        let result = ok();
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: PARTIAL_DISPATCH_ID,
+                result,
+            },
+        )
+        .unwrap();
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), channel_id.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(Uint128::new(250)), res.allowances[0].remaining);
+        assert!(PENDING_SPENDING.may_load(&deps.storage).unwrap().is_none());
+
+        // the next packet would go over, so it is refused and nothing is spent
+        let msg = mock_ibc_packet_recv(channel_id, &packet(251)).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(
+            ica_error(ContractError::SpendingLimitExceeded {
+                wanted: coin(251, "uatom"),
+                remaining: Uint128::new(250),
+            }),
+            err
+        );
+        assert_eq!(ErrorCode::LimitExceeded, err.code);
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), channel_id.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(Uint128::new(250)), res.allowances[0].remaining);
+
+        // limits go with the channel
+        let msg = mock_ibc_channel_close_init(channel_id, APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), msg).unwrap();
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), channel_id.into(), SENDER.into()).unwrap();
+        assert_eq!(None, res.limits);
+        assert!(SPENDING
+            .may_load(&deps.storage, (channel_id, SENDER))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn packets_without_sender_use_the_legacy_account() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        let account = "acct-123";
+        connect(deps.as_mut(), channel_id);
+
+        // a controller before 0.3.0 asks before it dispatched, no account answers yet
+        let legacy_who_am_i =
+            mock_ibc_packet_recv(channel_id, &PacketMsg::WhoAmI { sender: None }).unwrap();
+        let err = receive_err(deps.as_mut(), legacy_who_am_i.clone());
+        assert_eq!(
+            ica_error(ContractError::NoLegacyAccount(channel_id.into())),
+            err
+        );
+
+        // only its admin may dispatch, so the first sender's account answers from then on
+        let dispatch = PacketMsg::Dispatch {
+            sender: SENDER.into(),
+            msgs: vec![],
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &dispatch).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        init_reply(deps.as_mut(), res.messages[0].id, account);
+        who_am_i(deps.as_mut(), channel_id, "other-user", "acct-other");
+
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), legacy_who_am_i).unwrap();
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        assert_eq!(account, ack.try_into_who_am_i().unwrap().account);
+        let balances = PacketMsg::Balances { sender: None };
+        let msg = mock_ibc_packet_recv(channel_id, &balances).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        assert_eq!(account, ack.try_into_balances().unwrap().account);
+    }
+
+    #[test]
+    fn rejected_packets_leave_no_trace() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, REFLECT_ADDR);
+        let limits = SpendingLimits {
+            window: 3600,
+            limits: vec![DenomLimit {
+                denom: "uatom".into(),
+                per_packet: Some(Uint128::new(100)),
+                per_window: None,
+            }],
+        };
+        let msg = ExecuteMsg::UpdateChannelLimits {
+            channel_id: channel_id.into(),
+            limits: Some(limits),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+
+        let dispatch = |sender: &str| PacketMsg::Dispatch {
+            sender: sender.into(),
+            msgs: vec![BankMsg::Send {
+                to_address: "my-friend".into(),
+                amount: coins(500, "uatom"),
+            }
+            .into()],
+            callback_id: None,
+            mode: DispatchMode::StopOnError,
+        };
+        let packets = vec![
+            // the first dispatch on a v2 channel would pick the legacy sender
+            dispatch("other-user"),
+            dispatch(SENDER),
+            PacketMsg::Balances {
+                sender: Some("other-user".into()),
+            },
+            PacketMsg::Balances { sender: None },
+            PacketMsg::ReclaimFunds {
+                sender: SENDER.into(),
+                channel_id: "channel-7".into(),
+            },
+            PacketMsg::MigrateAccount {
+                sender: SENDER.into(),
+                code_id: 202,
+                msg: b"{}".into(),
+            },
+        ];
+
+        // the error ack is all that is left of them
+        let before = deps
+            .storage
+            .range(None, None, Order::Ascending)
+            .collect::<Vec<_>>();
+        for packet in packets {
+            let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+            receive_err(deps.as_mut(), msg);
+        }
+        let after = deps
+            .storage
+            .range(None, None, Order::Ascending)
+            .collect::<Vec<_>>();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn handle_dispatch_packet() {
+        let mut deps = setup();
+
+        let channel_id = "channel-123";
+        let account = "acct-123";
+
+        // register the channel
+        connect(deps.as_mut(), channel_id);
+
+        let msgs_to_dispatch = vec![BankMsg::Send {
+            to_address: "my-friend".into(),
+            amount: coins(123456789, "uatom"),
+        }
+        .into()];
+        let ibc_msg = PacketMsg::Dispatch {
+            msgs: msgs_to_dispatch.clone(),
+            sender: SENDER.to_string(),
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+
+        // the first dispatch of a sender creates the account
+        let msg = mock_ibc_packet_recv(channel_id, &ibc_msg).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(INIT_CALLBACK_ID, res.messages[0].id);
+        // and once it exists, dispatches the messages
+        let res = init_reply(deps.as_mut(), res.messages[0].id, account);
+        assert_eq!(1, res.messages.len());
+        assert_eq!(RECEIVE_DISPATCH_ID, res.messages[0].id);
+        let ack: StdAck = from_slice(&res.data.unwrap()).unwrap();
+        ack.unwrap();
+
+        // the next packet goes directly to the account
+        let msg = mock_ibc_packet_recv(channel_id, &ibc_msg).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // assert app-level success
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        ack.unwrap();
+
+        // and we dispatch the BankMsg in a call to ourselves
+        assert_eq!(1, res.messages.len());
+        assert_eq!(RECEIVE_DISPATCH_ID, res.messages[0].id);
+        assert_eq!(ReplyOn::Always, res.messages[0].reply_on);
+
+        // parse the output, ensuring it matches
+        if let CosmosMsg::Wasm(WasmMsg::Execute {
             contract_addr,
             msg,
             funds,
         }) = &res.messages[0].msg
         {
-            assert_eq!(account, contract_addr.as_str());
+            assert_eq!(MOCK_CONTRACT_ADDR, contract_addr.as_str());
             assert_eq!(0, funds.len());
-            // parse the message - should callback with proper channel_id
-            let rmsg: cw1_whitelist::msg::ExecuteMsg = from_slice(msg).unwrap();
+            let rmsg: ExecuteMsg = from_slice(msg).unwrap();
             assert_eq!(
                 rmsg,
-                cw1_whitelist::msg::ExecuteMsg::Execute {
-                    msgs: msgs_to_dispatch
+                ExecuteMsg::DispatchAtomic {
+                    account: account.into(),
+                    msgs: msgs_to_dispatch.clone()
                 }
             );
         } else {
             panic!("invalid return message: {:?}", res.messages[0]);
         }
 
+        // which only we may make
+        let dispatch = ExecuteMsg::DispatchAtomic {
+            account: account.into(),
+            msgs: msgs_to_dispatch.clone(),
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SENDER, &[]),
+            dispatch.clone(),
+        )
+        .unwrap_err();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MOCK_CONTRACT_ADDR, &[]),
+            dispatch,
+        )
+        .unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(ATOMIC_MSG_ID, res.messages[0].id);
+        let expected = wasm_execute(
+            account,
+            &cw1_whitelist::msg::ExecuteMsg::Execute {
+                msgs: msgs_to_dispatch,
+            },
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(CosmosMsg::Wasm(expected), res.messages[0].msg);
+
+        // another sender on the same channel gets its own account
+        let ack = who_am_i(deps.as_mut(), channel_id, "other-user", "acct-456");
+        let who: WhoAmIResponse = ack.unwrap_into();
+        assert_eq!(who.account, "acct-456");
+
+        // balances are only returned for known senders
+        let ibc_msg = PacketMsg::Balances {
+            sender: Some("unknown".into()),
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &ibc_msg).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(ErrorCode::UnregisteredAccount, err.code);
+        assert_eq!(
+            err,
+            ica_error(ContractError::UnregisteredAccount {
+                sender: "unknown".into()
+            })
+        );
+
         // invalid packet format on registered channel also returns error
-        let bad_data = InstantiateMsg { cw1_code_id: 12345 };
+        let bad_data = InstantiateMsg {
+            cw1_code_id: 12345,
+            account_backend: None,
+        };
         let msg = mock_ibc_packet_recv(channel_id, &bad_data).unwrap();
-        ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(
+            ErrorCode::InvalidPacket,
+            receive_err(deps.as_mut(), msg).code
+        );
+    }
+
+    #[test]
+    fn dispatch_reports_each_message() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        let account = "acct-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, account);
+
+        let msgs: Vec<CosmosMsg> = vec![
+            BankMsg::Burn {
+                amount: coins(1, "uatom"),
+            }
+            .into(),
+            BankMsg::Burn {
+                amount: coins(2, "uatom"),
+            }
+            .into(),
+        ];
+        let ibc_msg = PacketMsg::Dispatch {
+            msgs: msgs.clone(),
+            sender: SENDER.to_string(),
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+        let msg = mock_ibc_packet_recv(channel_id, &ibc_msg).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+
+        // every message is sent to the account on its own
+        assert_eq!(1, res.messages.len());
+        let dispatch = ExecuteMsg::DispatchAtomic {
+            account: account.into(),
+            msgs: msgs.clone(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(MOCK_CONTRACT_ADDR, &[]),
+            dispatch,
+        )
+        .unwrap();
+        assert_eq!(2, res.messages.len());
+        for (sub, msg) in res.messages.iter().zip(msgs) {
+            assert_eq!(ATOMIC_MSG_ID, sub.id);
+            let expected = wasm_execute(
+                account,
+                &cw1_whitelist::msg::ExecuteMsg::Execute { msgs: vec![msg] },
+                vec![],
+            )
+            .unwrap();
+            assert_eq!(CosmosMsg::Wasm(expected), sub.msg);
+        }
+
+        // and every reply adds its data and events to the ack
+        let mut wrapped = vec![0x0a, 5];
+        wrapped.extend(b"first");
+        let replies = [
+            (Some(Binary::from(wrapped)), Event::new("burn-1")),
+            (None, Event::new("burn-2")),
+        ];
+        for (data, event) in replies {
+            let response = Reply {
+                id: ATOMIC_MSG_ID,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![event],
+                    data,
+                }),
+            };
+            reply(deps.as_mut(), mock_env(), response).unwrap();
+        }
+        let response = Reply {
+            id: RECEIVE_DISPATCH_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        let ack = reply(deps.as_mut(), mock_env(), response).unwrap().data;
+        let ack: StdAck = from_slice(&ack.unwrap()).unwrap();
+        let res: DispatchResponse = ack.unwrap_into();
+        assert_eq!(
+            res,
+            DispatchResponse {
+                results: vec![Binary::from(b"first"), Binary::default()],
+                events: vec![vec![Event::new("burn-1")], vec![Event::new("burn-2")]],
+                outcomes: vec![MessageOutcome::Success {}, MessageOutcome::Success {}],
+            }
+        );
+
+        // if any of them fails, they are all reverted and the ack says why
+        let response = Reply {
+            id: RECEIVE_DISPATCH_ID,
+            result: SubMsgResult::Err("insufficient funds".into()),
+        };
+        let ack = reply(deps.as_mut(), mock_env(), response).unwrap().data;
+        let ack: StdAck = from_slice(&ack.unwrap()).unwrap();
+        assert_eq!(
+            ack.ica_error().unwrap(),
+            IcaError::new(ErrorCode::DispatchFailed, "insufficient funds")
+        );
+    }
+
+    #[test]
+    fn partial_dispatch_modes() {
+        let mut deps = setup();
+        let channel_id = "channel-123";
+        let account = "acct-123";
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, account);
+
+        let burn = |amount| -> CosmosMsg {
+            BankMsg::Burn {
+                amount: coins(amount, "uatom"),
+            }
+            .into()
+        };
+        let msgs = vec![burn(1), burn(2), burn(3)];
+        let ok = || {
+            SubMsgResult::Ok(SubMsgResponse {
+                events: vec![Event::new("burn")],
+                data: None,
+            })
+        };
+        let failed = || SubMsgResult::Err("insufficient funds".to_string());
+        // runs the dispatch with the given outcomes, checking each message is sent on its own
+        let mut run = |mode: DispatchMode, results: Vec<SubMsgResult>| -> DispatchResponse {
+            let packet = PacketMsg::Dispatch {
+                sender: SENDER.into(),
+                msgs: msgs.clone(),
+                callback_id: None,
+                mode,
+            };
+            let msg = mock_ibc_packet_recv(channel_id, &packet).unwrap();
+            let mut res: Response = ibc_packet_receive(deps.as_mut(), mock_env(), msg)
+                .unwrap()
+                .messages
+                .into_iter()
+                .fold(Response::new(), |res, msg| res.add_submessage(msg));
+            for (i, result) in results.into_iter().enumerate() {
+                assert_eq!(1, res.messages.len());
+                let sub = res.messages.remove(0);
+                assert_eq!(PARTIAL_DISPATCH_ID, sub.id);
+                assert_eq!(ReplyOn::Always, sub.reply_on);
+                let expected =
+                    reflect_execute(&deps.storage, &Addr::unchecked(account), msgs[i].clone());
+                assert_eq!(CosmosMsg::Wasm(expected.unwrap()), sub.msg);
+                let response = Reply { id: sub.id, result };
+                res = reply(deps.as_mut(), mock_env(), response).unwrap();
+            }
+            // nothing more to run
+            assert_eq!(0, res.messages.len());
+            let ack: StdAck = from_slice(&res.data.unwrap()).unwrap();
+            ack.unwrap_into()
+        };
+
+        // best effort goes on after the failure
+        let res = run(DispatchMode::BestEffort, vec![ok(), failed(), ok()]);
+        assert_eq!(
+            res.outcomes,
+            vec![
+                MessageOutcome::Success {},
+                MessageOutcome::Error("insufficient funds".to_string()),
+                MessageOutcome::Success {},
+            ]
+        );
+        assert_eq!(vec![Binary::default(); 3], res.results);
+        assert_eq!(
+            vec![vec![Event::new("burn")], vec![], vec![Event::new("burn")]],
+            res.events
+        );
+
+        // stop on error skips the rest
+        let res = run(DispatchMode::StopOnError, vec![ok(), failed()]);
+        assert_eq!(
+            res.outcomes,
+            vec![
+                MessageOutcome::Success {},
+                MessageOutcome::Error("insufficient funds".to_string()),
+                MessageOutcome::Skipped {},
+            ]
+        );
+        assert_eq!(3, res.results.len());
+        assert_eq!(3, res.events.len());
     }
 
     #[test]
@@ -576,17 +2902,20 @@ mod tests {
 
         let channel_id = "channel-123";
         let account = "acct-123";
+        let other_account = "acct-456";
 
-        // register the channel
-        connect(deps.as_mut(), channel_id, account);
-        // assign it some funds
+        // register the channel and two accounts
+        connect(deps.as_mut(), channel_id);
+        who_am_i(deps.as_mut(), channel_id, SENDER, account);
+        who_am_i(deps.as_mut(), channel_id, "other-user", other_account);
+        // assign one of them some funds
         let funds = vec![coin(123456, "uatom"), coin(7654321, "tgrd")];
         deps.querier.update_balance(account, funds.clone());
 
-        // channel should be listed and have balance
+        // accounts should be listed and have balance
         let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap();
         let res: ListAccountsResponse = from_slice(&raw).unwrap();
-        assert_eq!(1, res.accounts.len());
+        assert_eq!(2, res.accounts.len());
         let balance = deps.as_ref().querier.query_all_balances(account).unwrap();
         assert_eq!(funds, balance);
 
@@ -601,27 +2930,374 @@ mod tests {
         }) = &res.messages[0].msg
         {
             assert_eq!(contract_addr.as_str(), account);
-            let reflect: ReflectExecuteMsg = from_slice(msg).unwrap();
+            let reflect: cw1_whitelist::msg::ExecuteMsg = from_slice(msg).unwrap();
             match reflect {
-                ReflectExecuteMsg::ReflectMsg { msgs } => {
+                cw1_whitelist::msg::ExecuteMsg::Execute { msgs } => {
                     assert_eq!(1, msgs.len());
                     assert_eq!(
                         &msgs[0],
                         &BankMsg::Send {
                             to_address: MOCK_CONTRACT_ADDR.into(),
-                            amount: funds
+                            amount: funds.clone()
                         }
                         .into()
                     )
                 }
+                o => panic!("Unexpected message: {:?}", o),
             }
         } else {
             panic!("Unexpected message: {:?}", &res.messages[0]);
         }
 
-        // and removes the account lookup
+        // and removes the account lookups
         let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListAccounts {}).unwrap();
         let res: ListAccountsResponse = from_slice(&raw).unwrap();
         assert_eq!(0, res.accounts.len());
+
+        // but remembers the funds it took, and from whom
+        let raw = query(deps.as_ref(), mock_env(), QueryMsg::ListRescuedFunds {}).unwrap();
+        let res: ListRescuedFundsResponse = from_slice(&raw).unwrap();
+        let channel = mock_ibc_channel(channel_id, APP_ORDER, IBC_APP_VERSION);
+        assert_eq!(
+            res.funds,
+            vec![RescuedFundsInfo {
+                channel_id: channel_id.to_string(),
+                sender: SENDER.to_string(),
+                counterparty: Counterparty {
+                    connection_id: channel.connection_id,
+                    port_id: channel.counterparty_endpoint.port_id,
+                },
+                balance: funds,
+            }]
+        );
+    }
+
+    #[test]
+    fn reclaim_rescued_funds() {
+        let mut deps = setup();
+
+        let first_channel = "channel-111";
+        let closed_channel = "channel-123";
+        let new_channel = "channel-234";
+        let account = "acct-111";
+        let funds = coins(123456, "uatom");
+
+        // the sender had accounts on two channels from the same controller
+        connect(deps.as_mut(), first_channel);
+        who_am_i(deps.as_mut(), first_channel, SENDER, account);
+        deps.querier.update_balance(account, coins(100, "uatom"));
+        connect(deps.as_mut(), closed_channel);
+        who_am_i(deps.as_mut(), closed_channel, SENDER, "acct-123");
+        deps.querier.update_balance("acct-123", funds.clone());
+        for channel_id in [first_channel, closed_channel] {
+            let channel = mock_ibc_channel_close_init(channel_id, APP_ORDER, IBC_APP_VERSION);
+            ibc_channel_close(deps.as_mut(), mock_env(), channel).unwrap();
+        }
+
+        // a new channel takes over the oldest account, with the funds taken from it
+        let msg = mock_ibc_channel_connect_ack(new_channel, APP_ORDER, IBC_APP_VERSION);
+        let res = ibc_channel_connect(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            BankMsg::Send {
+                to_address: account.into(),
+                amount: coins(100, "uatom")
+            }
+            .into()
+        );
+        assert_eq!(
+            1,
+            query_list_rescued_funds(deps.as_ref()).unwrap().funds.len()
+        );
+
+        // the funds of the other one can only be claimed by the same sender
+        let packet = PacketMsg::ReclaimFunds {
+            sender: "other-user".into(),
+            channel_id: closed_channel.into(),
+        };
+        let msg = mock_ibc_packet_recv(new_channel, &packet).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(
+            err,
+            ica_error(ContractError::NoRescuedFunds {
+                channel_id: closed_channel.into(),
+                sender: "other-user".into()
+            })
+        );
+
+        // and are sent to the account the new channel took over
+        let packet = PacketMsg::ReclaimFunds {
+            sender: SENDER.into(),
+            channel_id: closed_channel.into(),
+        };
+        let msg = mock_ibc_packet_recv(new_channel, &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg.clone()).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            BankMsg::Send {
+                to_address: account.into(),
+                amount: funds.clone()
+            }
+            .into()
+        );
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        let res: ReclaimFundsResponse = from_slice(&ack.unwrap()).unwrap();
+        assert_eq!(account, res.account);
+        assert_eq!(funds, res.amount);
+        assert_eq!(
+            0,
+            query_list_rescued_funds(deps.as_ref()).unwrap().funds.len()
+        );
+
+        // only once
+        receive_err(deps.as_mut(), msg);
+    }
+
+    #[test]
+    fn reopened_channel_reattaches_accounts() {
+        let mut deps = setup();
+        let closed_channel = "channel-123";
+        let new_channel = "channel-234";
+        connect(deps.as_mut(), closed_channel);
+        who_am_i(deps.as_mut(), closed_channel, SENDER, REFLECT_ADDR);
+        let channel = mock_ibc_channel_close_init(closed_channel, APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), channel).unwrap();
+
+        // the account waits for its controller
+        let channel = mock_ibc_channel(closed_channel, APP_ORDER, IBC_APP_VERSION);
+        let res = query_list_detached_accounts(deps.as_ref()).unwrap();
+        assert_eq!(
+            vec![DetachedAccount {
+                account: REFLECT_ADDR.into(),
+                counterparty: Counterparty {
+                    connection_id: channel.connection_id,
+                    port_id: channel.counterparty_endpoint.port_id,
+                },
+                sender: SENDER.into(),
+                channel_id: closed_channel.into(),
+            }],
+            res.accounts
+        );
+
+        // a new channel from it gets the same account back as soon as it connects
+        connect(deps.as_mut(), new_channel);
+        let res = query_account(deps.as_ref(), new_channel.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(REFLECT_ADDR.into()), res.account);
+
+        // so nothing is instantiated when the sender asks for it
+        let packet = PacketMsg::WhoAmI {
+            sender: Some(SENDER.into()),
+        };
+        let msg = mock_ibc_packet_recv(new_channel, &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        let res: WhoAmIResponse = from_slice(&ack.unwrap()).unwrap();
+        assert_eq!(REFLECT_ADDR, res.account);
+        let res = query_account(deps.as_ref(), new_channel.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(REFLECT_ADDR.into()), res.account);
+        assert_eq!(
+            0,
+            query_list_detached_accounts(deps.as_ref())
+                .unwrap()
+                .accounts
+                .len()
+        );
+    }
+
+    #[test]
+    fn reopened_channel_keeps_limits_and_policy() {
+        let mut deps = setup();
+        let closed_channel = "channel-123";
+        let new_channel = "channel-234";
+        connect(deps.as_mut(), closed_channel);
+        who_am_i(deps.as_mut(), closed_channel, SENDER, REFLECT_ADDR);
+
+        let limits = |per_window: u128| SpendingLimits {
+            window: 3600,
+            limits: vec![DenomLimit {
+                denom: "uatom".into(),
+                per_packet: None,
+                per_window: Some(Uint128::new(per_window)),
+            }],
+        };
+        let policy = MsgPolicy::AllowOnly {
+            kinds: vec![MsgKind::BankSend],
+            wasm_contracts: None,
+        };
+        let msgs = vec![
+            ExecuteMsg::UpdateChannelMsgPolicy {
+                channel_id: closed_channel.into(),
+                policy: Some(policy.clone()),
+            },
+            ExecuteMsg::UpdateChannelLimits {
+                channel_id: closed_channel.into(),
+                limits: Some(limits(1000)),
+            },
+            ExecuteMsg::UpdateAccountLimits {
+                channel_id: closed_channel.into(),
+                sender: SENDER.into(),
+                limits: Some(limits(500)),
+            },
+        ];
+        for msg in msgs {
+            execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        }
+        let send = PacketMsg::Dispatch {
+            msgs: vec![BankMsg::Send {
+                to_address: "my-friend".into(),
+                amount: coins(200, "uatom"),
+            }
+            .into()],
+            sender: SENDER.to_string(),
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+        let msg = mock_ibc_packet_recv(closed_channel, &send).unwrap();
+        ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let result = SubMsgResult::Ok(SubMsgResponse {
+            events: vec![],
+            data: None,
+        });
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: RECEIVE_DISPATCH_ID,
+                result,
+            },
+        )
+        .unwrap();
+
+        let channel = mock_ibc_channel_close_init(closed_channel, APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), channel).unwrap();
+
+        // the next channel from the same controller has the same rules right away
+        connect(deps.as_mut(), new_channel);
+        let res = query_msg_policy(deps.as_ref(), new_channel.into()).unwrap();
+        assert_eq!(policy, res.policy);
+        let res = query_allowance(
+            deps.as_ref(),
+            mock_env(),
+            new_channel.into(),
+            "other-user".into(),
+        )
+        .unwrap();
+        assert_eq!(Some(limits(1000)), res.limits);
+
+        // and the account gets its own limits back, with what it spent
+        who_am_i(deps.as_mut(), new_channel, SENDER, "unused");
+        let res =
+            query_allowance(deps.as_ref(), mock_env(), new_channel.into(), SENDER.into()).unwrap();
+        assert_eq!(Some(limits(500)), res.limits);
+        assert_eq!(Some(Uint128::new(300)), res.allowances[0].remaining);
+        let burn = PacketMsg::Dispatch {
+            msgs: vec![BankMsg::Burn {
+                amount: coins(1, "uatom"),
+            }
+            .into()],
+            sender: SENDER.to_string(),
+            callback_id: None,
+            mode: DispatchMode::Atomic,
+        };
+        let msg = mock_ibc_packet_recv(new_channel, &burn).unwrap();
+        assert_eq!(
+            ErrorCode::MsgNotAllowed,
+            receive_err(deps.as_mut(), msg).code
+        );
+
+        // a later channel starts without rules
+        let later_channel = "channel-345";
+        connect(deps.as_mut(), later_channel);
+        let res = query_msg_policy(deps.as_ref(), later_channel.into()).unwrap();
+        assert_eq!(MsgPolicy::AllowAll, res.policy);
+    }
+
+    #[test]
+    fn accounts_of_parallel_channels_are_all_kept() {
+        let mut deps = setup();
+        // both from the same controller
+        for (channel_id, account) in [("channel-1", "acct-1"), ("channel-2", "acct-2")] {
+            connect(deps.as_mut(), channel_id);
+            who_am_i(deps.as_mut(), channel_id, SENDER, account);
+        }
+        for channel_id in ["channel-1", "channel-2"] {
+            let channel = mock_ibc_channel_close_init(channel_id, APP_ORDER, IBC_APP_VERSION);
+            ibc_channel_close(deps.as_mut(), mock_env(), channel).unwrap();
+        }
+        let detached = |deps: Deps| -> Vec<(String, String)> {
+            query_list_detached_accounts(deps)
+                .unwrap()
+                .accounts
+                .into_iter()
+                .map(|a| (a.channel_id, a.account))
+                .collect()
+        };
+        assert_eq!(
+            vec![
+                ("channel-1".to_string(), "acct-1".to_string()),
+                ("channel-2".to_string(), "acct-2".to_string())
+            ],
+            detached(deps.as_ref())
+        );
+
+        // new channels take them over, oldest first
+        connect(deps.as_mut(), "channel-3");
+        let who: WhoAmIResponse = who_am_i(deps.as_mut(), "channel-3", SENDER, "new").unwrap_into();
+        assert_eq!("acct-1", who.account);
+        assert_eq!(
+            vec![("channel-2".to_string(), "acct-2".to_string())],
+            detached(deps.as_ref())
+        );
+        connect(deps.as_mut(), "channel-4");
+        let who: WhoAmIResponse = who_am_i(deps.as_mut(), "channel-4", SENDER, "new").unwrap_into();
+        assert_eq!("acct-2", who.account);
+        assert_eq!(0, detached(deps.as_ref()).len());
+    }
+
+    #[test]
+    fn reclaim_needs_same_counterparty() {
+        let mut deps = setup();
+
+        let closed_channel = "channel-123";
+        let new_channel = "channel-234";
+        connect(deps.as_mut(), closed_channel);
+        who_am_i(deps.as_mut(), closed_channel, SENDER, "acct-123");
+        deps.querier.update_balance("acct-123", coins(500, "uatom"));
+        let channel = mock_ibc_channel_close_init(closed_channel, APP_ORDER, IBC_APP_VERSION);
+        ibc_channel_close(deps.as_mut(), mock_env(), channel).unwrap();
+
+        // a new channel from another port
+        let mut channel = mock_ibc_channel(new_channel, APP_ORDER, IBC_APP_VERSION);
+        channel.counterparty_endpoint.port_id = "wasm.impostor".into();
+        ibc_channel_open(
+            deps.as_mut(),
+            mock_env(),
+            IbcChannelOpenMsg::new_init(channel.clone()),
+        )
+        .unwrap();
+        ibc_channel_connect(
+            deps.as_mut(),
+            mock_env(),
+            IbcChannelConnectMsg::new_ack(channel, IBC_APP_VERSION),
+        )
+        .unwrap();
+        who_am_i(deps.as_mut(), new_channel, SENDER, "acct-234");
+
+        let packet = PacketMsg::ReclaimFunds {
+            sender: SENDER.into(),
+            channel_id: closed_channel.into(),
+        };
+        let msg = mock_ibc_packet_recv(new_channel, &packet).unwrap();
+        let err = receive_err(deps.as_mut(), msg);
+        assert_eq!(ErrorCode::Unauthorized, err.code);
+        assert_eq!(
+            err,
+            ica_error(ContractError::WrongCounterparty(closed_channel.into()))
+        );
+        assert_eq!(
+            1,
+            query_list_rescued_funds(deps.as_ref()).unwrap().funds.len()
+        );
     }
 }
@@ -1,9 +1,9 @@
 use thiserror::Error;
 
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, StdError, Uint128};
 use cw_utils::ParseReplyError;
 
-use simple_ica::SimpleIcaError;
+use simple_ica::{ErrorCode, SimpleIcaError};
 
 #[derive(Error, Debug, PartialEq)]
 pub enum ContractError {
@@ -16,9 +16,88 @@ pub enum ContractError {
     #[error("{0}")]
     SimpleIca(#[from] SimpleIcaError),
 
-    #[error("Cannot register over an existing channel")]
-    ChannelAlreadyRegistered,
+    #[error("Cannot register over an existing account")]
+    AccountAlreadyRegistered,
+
+    #[error("No account for sender {sender}")]
+    UnregisteredAccount { sender: String },
 
     #[error("Invalid reply id")]
     InvalidReplyId,
+
+    #[error("No funds rescued for sender {sender} on channel {channel_id}")]
+    NoRescuedFunds { channel_id: String, sender: String },
+
+    #[error("Funds on channel {0} were rescued from another controller")]
+    WrongCounterparty(String),
+
+    #[error("Channel {0} is frozen")]
+    ChannelFrozen(String),
+
+    #[error("Query failed: {0}")]
+    QueryFailed(String),
+
+    #[error("Message {index} ({kind}) is not allowed on this channel")]
+    MsgNotAllowed { index: usize, kind: String },
+
+    #[error("Message {index} executes contract {contract}, which is not allowed on this channel")]
+    ContractNotAllowed { index: usize, contract: String },
+
+    #[error("Cannot spend {wanted}, only {remaining} left within the spending limits")]
+    SpendingLimitExceeded { wanted: Coin, remaining: Uint128 },
+
+    #[error("Invalid spending limits: {0}")]
+    InvalidSpendingLimits(String),
+
+    #[error("Accounts may not be migrated to code {0}")]
+    CodeNotAllowed(u64),
+
+    #[error("Account {0} was created without an admin and cannot be migrated")]
+    AccountNotMigratable(String),
+
+    #[error("No admin transfer in progress")]
+    NoPendingAdmin,
+
+    #[error("An admin must be given when migrating from a version without one")]
+    MissingAdmin,
+
+    #[error("No sender given for the account on channel {0}")]
+    MissingLegacySender(String),
+
+    #[error("No account on channel {0} for packets without a sender")]
+    NoLegacyAccount(String),
+}
+
+impl ContractError {
+    /// The code the controller gets in the error ack
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ContractError::Std(StdError::ParseErr { .. })
+            | ContractError::Std(StdError::InvalidBase64 { .. })
+            | ContractError::Std(StdError::InvalidUtf8 { .. }) => ErrorCode::InvalidPacket,
+            ContractError::UnregisteredAccount { .. } | ContractError::NoLegacyAccount(_) => {
+                ErrorCode::UnregisteredAccount
+            }
+            ContractError::WrongCounterparty(_) | ContractError::CodeNotAllowed(_) => {
+                ErrorCode::Unauthorized
+            }
+            ContractError::ChannelFrozen(_) => ErrorCode::Frozen,
+            ContractError::QueryFailed(_) => ErrorCode::QueryFailed,
+            ContractError::MsgNotAllowed { .. } | ContractError::ContractNotAllowed { .. } => {
+                ErrorCode::MsgNotAllowed
+            }
+            ContractError::SpendingLimitExceeded { .. } => ErrorCode::LimitExceeded,
+            ContractError::Std(_)
+            | ContractError::ParseReply(_)
+            | ContractError::SimpleIca(_)
+            | ContractError::AccountAlreadyRegistered
+            | ContractError::InvalidReplyId
+            | ContractError::NoRescuedFunds { .. }
+            | ContractError::InvalidSpendingLimits(_)
+            | ContractError::AccountNotMigratable(_)
+            | ContractError::NoPendingAdmin
+            | ContractError::MissingAdmin
+            | ContractError::MissingLegacySender(_) => ErrorCode::Other,
+        }
+    }
 }
@@ -1,4 +1,8 @@
+pub mod backend;
 pub mod contract;
 pub mod error;
+pub mod limits;
+pub mod migrations;
 pub mod msg;
+pub mod policy;
 pub mod state;
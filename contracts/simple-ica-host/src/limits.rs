@@ -0,0 +1,440 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{
+    BankMsg, Coin, CosmosMsg, Env, IbcMsg, StdResult, Storage, Timestamp, Uint128, WasmMsg,
+};
+use cw_utils::NativeBalance;
+
+use crate::error::ContractError;
+use crate::policy::MsgKind;
+use crate::state::{ACCOUNT_LIMITS, CHANNEL_LIMITS, SPENDING};
+
+/// How much each account may send away. Denoms that are not listed are not limited
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SpendingLimits {
+    /// Length of the window for `per_window`, in seconds
+    pub window: u64,
+    pub limits: Vec<DenomLimit>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DenomLimit {
+    pub denom: String,
+    /// Most one packet may spend
+    pub per_packet: Option<Uint128>,
+    /// Most all packets in one window may spend
+    pub per_window: Option<Uint128>,
+}
+
+/// What an account spent in the current window
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Spending {
+    /// The window starts with the first packet spending anything after the last one ended
+    pub window_start: Timestamp,
+    pub spent: Vec<Coin>,
+}
+
+/// What an account may still spend of one denom
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Allowance {
+    pub denom: String,
+    /// Most the next packet may spend, None if not limited
+    pub per_packet: Option<Uint128>,
+    /// What is left in the current window, None if not limited
+    pub remaining: Option<Uint128>,
+}
+
+impl SpendingLimits {
+    /// Fails unless the limits can be enforced
+    pub fn validate(&self) -> Result<(), ContractError> {
+        let windowed = self.limits.iter().any(|l| l.per_window.is_some());
+        if windowed && self.window == 0 {
+            return Err(ContractError::InvalidSpendingLimits(
+                "window must be longer than zero".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The funds these messages send out of the account.
+/// Only bank sends and burns, ibc transfers and funds sent to contracts count
+pub fn outflow(msgs: &[CosmosMsg]) -> NativeBalance {
+    let mut total = NativeBalance::default();
+    for msg in msgs {
+        match msg {
+            CosmosMsg::Bank(BankMsg::Send { amount, .. })
+            | CosmosMsg::Bank(BankMsg::Burn { amount })
+            | CosmosMsg::Wasm(WasmMsg::Execute { funds: amount, .. })
+            | CosmosMsg::Wasm(WasmMsg::Instantiate { funds: amount, .. }) => {
+                for coin in amount {
+                    total += coin.clone();
+                }
+            }
+            CosmosMsg::Ibc(IbcMsg::Transfer { amount, .. }) => total += amount.clone(),
+            _ => {}
+        }
+    }
+    total.normalize();
+    total
+}
+
+/// The limits of this account, or of its channel if it has none
+pub fn account_limits(
+    storage: &dyn Storage,
+    channel_id: &str,
+    sender: &str,
+) -> StdResult<Option<SpendingLimits>> {
+    match ACCOUNT_LIMITS.may_load(storage, (channel_id, sender))? {
+        Some(limits) => Ok(Some(limits)),
+        None => CHANNEL_LIMITS.may_load(storage, channel_id),
+    }
+}
+
+// the spending of the window that is open now, or an empty one
+fn current_spending(
+    storage: &dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    sender: &str,
+    window: u64,
+) -> StdResult<Spending> {
+    let spending = SPENDING
+        .may_load(storage, (channel_id, sender))?
+        .filter(|s| env.block.time < s.window_start.plus_seconds(window));
+    Ok(spending.unwrap_or(Spending {
+        window_start: env.block.time,
+        spent: vec![],
+    }))
+}
+
+fn spent_of(spending: &Spending, denom: &str) -> Uint128 {
+    spending
+        .spent
+        .iter()
+        .find(|c| c.denom == denom)
+        .map_or_else(Uint128::zero, |c| c.amount)
+}
+
+/// What this account may still spend, for every limited denom
+pub fn allowances(
+    storage: &dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    sender: &str,
+) -> StdResult<Vec<Allowance>> {
+    let limits = match account_limits(storage, channel_id, sender)? {
+        Some(limits) => limits,
+        None => return Ok(vec![]),
+    };
+    let spending = current_spending(storage, env, channel_id, sender, limits.window)?;
+    let allowances = limits
+        .limits
+        .into_iter()
+        .map(|limit| {
+            let spent = spent_of(&spending, &limit.denom);
+            Allowance {
+                remaining: limit.per_window.map(|max| max.saturating_sub(spent)),
+                per_packet: limit.per_packet,
+                denom: limit.denom,
+            }
+        })
+        .collect();
+    Ok(allowances)
+}
+
+/// Fails if these messages would spend more than the account may.
+/// Nothing is written, the spending is recorded once the messages succeed
+pub fn check_spending(
+    storage: &dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    sender: &str,
+    msgs: &[CosmosMsg],
+) -> Result<(), ContractError> {
+    let limits = match account_limits(storage, channel_id, sender)? {
+        Some(limits) => limits,
+        None => return Ok(()),
+    };
+    // we cannot tell what these spend, so a limited account may not use them
+    for (index, msg) in msgs.iter().enumerate() {
+        if let Some(kind @ (MsgKind::Stargate | MsgKind::Custom)) = MsgKind::of(msg) {
+            return Err(ContractError::MsgNotAllowed {
+                index,
+                kind: kind.as_str().to_string(),
+            });
+        }
+    }
+    let amount = outflow(msgs);
+    if amount.is_empty() {
+        return Ok(());
+    }
+
+    let spending = current_spending(storage, env, channel_id, sender, limits.window)?;
+    for limit in &limits.limits {
+        let wanted = amount
+            .0
+            .iter()
+            .find(|c| c.denom == limit.denom)
+            .map_or_else(Uint128::zero, |c| c.amount);
+        if wanted.is_zero() {
+            continue;
+        }
+        let spent = spent_of(&spending, &limit.denom);
+        let remaining = [
+            limit.per_packet,
+            limit.per_window.map(|max| max.saturating_sub(spent)),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied();
+        if let Some(remaining) = remaining {
+            if wanted > remaining {
+                return Err(ContractError::SpendingLimitExceeded {
+                    wanted: Coin::new(wanted.u128(), &limit.denom),
+                    remaining,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Counts funds that left the account against its limits. Accounts without limits
+/// keep no record
+pub fn record_spending(
+    storage: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    sender: &str,
+    amount: Vec<Coin>,
+) -> StdResult<()> {
+    let limits = match account_limits(storage, channel_id, sender)? {
+        Some(limits) => limits,
+        None => return Ok(()),
+    };
+    if amount.is_empty() {
+        return Ok(());
+    }
+    let mut spending = current_spending(storage, env, channel_id, sender, limits.window)?;
+    let mut spent = NativeBalance(spending.spent);
+    spent += NativeBalance(amount);
+    spending.spent = spent.into_vec();
+    SPENDING.save(storage, (channel_id, sender), &spending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cosmwasm_std::testing::{mock_env, MockStorage};
+    use cosmwasm_std::{coin, coins, to_binary, Binary, Empty, IbcTimeout, StakingMsg};
+
+    // checks and records at once, as if every message succeeded
+    fn spend(
+        storage: &mut dyn Storage,
+        env: &Env,
+        channel_id: &str,
+        sender: &str,
+        msgs: &[CosmosMsg],
+    ) -> Result<(), ContractError> {
+        check_spending(storage, env, channel_id, sender, msgs)?;
+        record_spending(storage, env, channel_id, sender, outflow(msgs).into_vec())?;
+        Ok(())
+    }
+
+    fn send(amount: u128, denom: &str) -> CosmosMsg {
+        BankMsg::Send {
+            to_address: "friend".into(),
+            amount: coins(amount, denom),
+        }
+        .into()
+    }
+
+    #[test]
+    fn outflow_counts_funds_leaving() {
+        let msgs: Vec<CosmosMsg> = vec![
+            send(100, "uatom"),
+            IbcMsg::Transfer {
+                channel_id: "channel-7".into(),
+                to_address: "friend".into(),
+                amount: coin(20, "uatom"),
+                timeout: IbcTimeout::with_timestamp(Timestamp::from_seconds(1000)),
+            }
+            .into(),
+            WasmMsg::Execute {
+                contract_addr: "contract".into(),
+                msg: to_binary(&"go").unwrap(),
+                funds: vec![coin(3, "uatom"), coin(5, "ujuno")],
+            }
+            .into(),
+            BankMsg::Burn {
+                amount: coins(7, "ujuno"),
+            }
+            .into(),
+            // delegating keeps the funds in the account
+            StakingMsg::Delegate {
+                validator: "val".into(),
+                amount: coin(1000, "uatom"),
+            }
+            .into(),
+        ];
+        assert_eq!(
+            vec![coin(123, "uatom"), coin(12, "ujuno")],
+            outflow(&msgs).into_vec()
+        );
+    }
+
+    #[test]
+    fn limited_accounts_only_send_known_msgs() {
+        let mut storage = MockStorage::new();
+        let env = mock_env();
+        let (channel_id, sender) = ("channel-1", "alice");
+        let stargate = CosmosMsg::Stargate {
+            type_url: "/cosmos.bank.v1beta1.MsgSend".into(),
+            value: Binary::from(b"send it all".to_vec()),
+        };
+        let custom = CosmosMsg::Custom(Empty {});
+        let msgs = [send(1, "uatom"), stargate, custom];
+
+        // without limits anything goes
+        check_spending(&storage, &env, channel_id, sender, &msgs).unwrap();
+
+        let limits = SpendingLimits {
+            window: 100,
+            limits: vec![DenomLimit {
+                denom: "uatom".into(),
+                per_packet: Some(Uint128::new(60)),
+                per_window: None,
+            }],
+        };
+        CHANNEL_LIMITS
+            .save(&mut storage, channel_id, &limits)
+            .unwrap();
+        let err = check_spending(&storage, &env, channel_id, sender, &msgs).unwrap_err();
+        assert_eq!(
+            ContractError::MsgNotAllowed {
+                index: 1,
+                kind: "stargate".to_string()
+            },
+            err
+        );
+        let err = check_spending(&storage, &env, channel_id, sender, &msgs[2..]).unwrap_err();
+        assert_eq!(
+            ContractError::MsgNotAllowed {
+                index: 0,
+                kind: "custom".to_string()
+            },
+            err
+        );
+
+        // burning counts as spending
+        let burn: CosmosMsg = BankMsg::Burn {
+            amount: coins(61, "uatom"),
+        }
+        .into();
+        let err = check_spending(&storage, &env, channel_id, sender, &[burn]).unwrap_err();
+        assert_eq!(
+            ContractError::SpendingLimitExceeded {
+                wanted: coin(61, "uatom"),
+                remaining: Uint128::new(60)
+            },
+            err
+        );
+    }
+
+    #[test]
+    fn spend_within_limits() {
+        let mut storage = MockStorage::new();
+        let mut env = mock_env();
+        let (channel_id, sender) = ("channel-1", "alice");
+        let limits = SpendingLimits {
+            window: 100,
+            limits: vec![DenomLimit {
+                denom: "uatom".into(),
+                per_packet: Some(Uint128::new(60)),
+                per_window: Some(Uint128::new(100)),
+            }],
+        };
+        CHANNEL_LIMITS
+            .save(&mut storage, channel_id, &limits)
+            .unwrap();
+
+        // too much for one packet
+        let err = spend(&mut storage, &env, channel_id, sender, &[send(61, "uatom")]).unwrap_err();
+        assert_eq!(
+            ContractError::SpendingLimitExceeded {
+                wanted: coin(61, "uatom"),
+                remaining: Uint128::new(60)
+            },
+            err
+        );
+        // other denoms are not limited
+        spend(
+            &mut storage,
+            &env,
+            channel_id,
+            sender,
+            &[send(1000, "ujuno")],
+        )
+        .unwrap();
+        spend(&mut storage, &env, channel_id, sender, &[send(60, "uatom")]).unwrap();
+
+        // the window only has 40 left
+        let err = spend(&mut storage, &env, channel_id, sender, &[send(41, "uatom")]).unwrap_err();
+        assert_eq!(
+            ContractError::SpendingLimitExceeded {
+                wanted: coin(41, "uatom"),
+                remaining: Uint128::new(40)
+            },
+            err
+        );
+        let allowed = allowances(&storage, &env, channel_id, sender).unwrap();
+        assert_eq!(Some(Uint128::new(40)), allowed[0].remaining);
+        // and other accounts on the channel have their own
+        spend(&mut storage, &env, channel_id, "bob", &[send(60, "uatom")]).unwrap();
+
+        // the window ends
+        env.block.time = env.block.time.plus_seconds(100);
+        let allowed = allowances(&storage, &env, channel_id, sender).unwrap();
+        assert_eq!(Some(Uint128::new(100)), allowed[0].remaining);
+        spend(&mut storage, &env, channel_id, sender, &[send(60, "uatom")]).unwrap();
+
+        // the account has its own limits
+        let unlimited = SpendingLimits {
+            window: 0,
+            limits: vec![],
+        };
+        unlimited.validate().unwrap();
+        ACCOUNT_LIMITS
+            .save(&mut storage, (channel_id, sender), &unlimited)
+            .unwrap();
+        spend(
+            &mut storage,
+            &env,
+            channel_id,
+            sender,
+            &[send(1000, "uatom")],
+        )
+        .unwrap();
+        assert_eq!(
+            vec![] as Vec<Allowance>,
+            allowances(&storage, &env, channel_id, sender).unwrap()
+        );
+    }
+
+    #[test]
+    fn window_must_not_be_empty() {
+        let limits = SpendingLimits {
+            window: 0,
+            limits: vec![DenomLimit {
+                denom: "uatom".into(),
+                per_packet: None,
+                per_window: Some(Uint128::new(100)),
+            }],
+        };
+        limits.validate().unwrap_err();
+    }
+}
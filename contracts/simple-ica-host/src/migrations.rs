@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_std::{Addr, Order, Storage};
+use cw2::CONTRACT;
+use cw_storage_plus::{Item, Map};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use simple_ica::ensure_upgrade;
+
+use crate::backend::AccountBackend;
+use crate::contract::{CONTRACT_NAME, CONTRACT_VERSION};
+use crate::error::ContractError;
+use crate::msg::LegacySender;
+use crate::state::{Config, ACCOUNTS, CONFIG, LEGACY_SENDERS, PENDING_ADMIN};
+
+/// Accounts of 0.2.0, one per channel. Shares the namespace with `ACCOUNTS`
+pub const ACCOUNTS_V0_2: Map<&str, Addr> = Map::new("accounts");
+
+/// Config from before the host had an admin. Shares the namespace with `CONFIG`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConfigV0_2 {
+    pub cw1_code_id: u64,
+}
+
+pub const CONFIG_V0_2: Item<ConfigV0_2> = Item::new("config");
+
+/// 0.2.0 did not store a contract version, so a missing one means the 0.2.0 layout
+pub const LEGACY_VERSION: Version = Version::new(0, 2, 0);
+
+/// The version we migrate from, failing unless it is an older or equal version of this contract
+pub fn previous_version(storage: &dyn Storage) -> Result<Version, ContractError> {
+    match CONTRACT.may_load(storage)? {
+        Some(previous) => Ok(ensure_upgrade(&previous, CONTRACT_NAME, CONTRACT_VERSION)?),
+        None => Ok(LEGACY_VERSION),
+    }
+}
+
+/// Replaces the admin if one is given, cancelling any transfer in progress.
+/// Fails if the previous version had no admin yet and none is given.
+pub fn migrate_admin(
+    storage: &mut dyn Storage,
+    previous_version: &Version,
+    admin: Option<Addr>,
+) -> Result<(), ContractError> {
+    // only the 0.2.0 config lacks the admin
+    let (cw1_code_id, account_backend) = if *previous_version <= LEGACY_VERSION {
+        let cw1_code_id = CONFIG_V0_2.load(storage)?.cw1_code_id;
+        (cw1_code_id, AccountBackend::default())
+    } else if admin.is_none() {
+        return Ok(());
+    } else {
+        let cfg = CONFIG.load(storage)?;
+        (cfg.cw1_code_id, cfg.account_backend)
+    };
+    let admin = admin.ok_or(ContractError::MissingAdmin)?;
+    PENDING_ADMIN.remove(storage);
+    let cfg = Config {
+        admin,
+        cw1_code_id,
+        account_backend,
+    };
+    CONFIG.save(storage, &cfg)?;
+    Ok(())
+}
+
+/// Moves the 0.2.0 layout to the current one, keying each account by the sender it is given.
+/// Fails if any account is left without a sender, as its funds would be lost.
+pub fn migrate_legacy(
+    storage: &mut dyn Storage,
+    legacy_senders: Vec<LegacySender>,
+) -> Result<(), ContractError> {
+    let senders: BTreeMap<String, String> = legacy_senders
+        .into_iter()
+        .map(|s| (s.channel_id, s.sender))
+        .collect();
+
+    // read everything first, the old keys cannot be parsed once the new ones are written
+    let accounts = ACCOUNTS_V0_2
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (channel_id, account) = item?;
+            match senders.get(&channel_id) {
+                Some(sender) => Ok((channel_id, sender.clone(), account)),
+                None => Err(ContractError::MissingLegacySender(channel_id)),
+            }
+        })
+        .collect::<Result<Vec<_>, ContractError>>()?;
+
+    for (channel_id, _, _) in &accounts {
+        ACCOUNTS_V0_2.remove(storage, channel_id);
+    }
+    for (channel_id, sender, account) in accounts {
+        ACCOUNTS.save(storage, (&channel_id, &sender), &account)?;
+        // the controller on the channel may still send packets without a sender
+        LEGACY_SENDERS.save(storage, &channel_id, &sender)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{instantiate, migrate, query_list_accounts};
+    use crate::msg::{AccountInfo, InstantiateMsg, MigrateMsg};
+    use crate::state::{Config, CONFIG};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::StdError;
+    use cw2::{get_contract_version, set_contract_version};
+    use simple_ica::SimpleIcaError;
+
+    fn legacy_sender(channel_id: &str, sender: &str) -> LegacySender {
+        LegacySender {
+            channel_id: channel_id.to_string(),
+            sender: sender.to_string(),
+        }
+    }
+
+    #[test]
+    fn migrate_from_legacy_layout() {
+        let mut deps = mock_dependencies();
+        let legacy = ConfigV0_2 { cw1_code_id: 17 };
+        CONFIG_V0_2.save(deps.as_mut().storage, &legacy).unwrap();
+        for (channel_id, account) in [("channel-1", "account-1"), ("channel-7", "account-7")] {
+            ACCOUNTS_V0_2
+                .save(deps.as_mut().storage, channel_id, &Addr::unchecked(account))
+                .unwrap();
+        }
+
+        // the contract needs an admin
+        let msg = MigrateMsg {
+            admin: None,
+            legacy_senders: vec![],
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, ContractError::MissingAdmin);
+
+        // and every account a sender
+        let msg = MigrateMsg {
+            admin: Some("gov".to_string()),
+            legacy_senders: vec![legacy_sender("channel-1", "admin-1")],
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::MissingLegacySender("channel-7".to_string())
+        );
+
+        let msg = MigrateMsg {
+            admin: Some("gov".to_string()),
+            legacy_senders: vec![
+                legacy_sender("channel-1", "admin-1"),
+                legacy_sender("channel-7", "admin-7"),
+            ],
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+
+        let accounts = query_list_accounts(deps.as_ref()).unwrap().accounts;
+        assert_eq!(
+            accounts,
+            vec![
+                AccountInfo {
+                    account: "account-1".to_string(),
+                    channel_id: "channel-1".to_string(),
+                    sender: "admin-1".to_string(),
+                },
+                AccountInfo {
+                    account: "account-7".to_string(),
+                    channel_id: "channel-7".to_string(),
+                    sender: "admin-7".to_string(),
+                },
+            ]
+        );
+        // packets without a sender keep reaching the same account
+        let sender = LEGACY_SENDERS.load(&deps.storage, "channel-7").unwrap();
+        assert_eq!("admin-7", sender);
+        let cfg = Config {
+            admin: Addr::unchecked("gov"),
+            cw1_code_id: 17,
+            account_backend: AccountBackend::Cw1Whitelist,
+        };
+        assert_eq!(cfg, CONFIG.load(&deps.storage).unwrap());
+
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(CONTRACT_NAME, version.contract);
+        assert_eq!(CONTRACT_VERSION, version.version);
+    }
+
+    #[test]
+    fn migrate_admin_follows_version() {
+        let mut deps = mock_dependencies();
+        let legacy = ConfigV0_2 { cw1_code_id: 17 };
+        CONFIG_V0_2.save(deps.as_mut().storage, &legacy).unwrap();
+
+        // a config without admin is only read as the old one when migrating from 0.2.0
+        let current: Version = CONTRACT_VERSION.parse().unwrap();
+        let gov = Some(Addr::unchecked("gov"));
+        let err = migrate_admin(deps.as_mut().storage, &current, gov.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::ParseErr { .. })));
+
+        migrate_admin(deps.as_mut().storage, &LEGACY_VERSION, gov).unwrap();
+        let cfg = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(Addr::unchecked("gov"), cfg.admin);
+        assert_eq!(17, cfg.cw1_code_id);
+    }
+
+    #[test]
+    fn migrate_checks_version() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            cw1_code_id: 17,
+            account_backend: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        ACCOUNTS
+            .save(
+                deps.as_mut().storage,
+                ("channel-1", "admin-1"),
+                &Addr::unchecked("account-1"),
+            )
+            .unwrap();
+        let no_senders = || MigrateMsg {
+            admin: None,
+            legacy_senders: vec![],
+        };
+
+        // same version is fine, and leaves the accounts alone
+        migrate(deps.as_mut(), mock_env(), no_senders()).unwrap();
+        assert_eq!(
+            1,
+            query_list_accounts(deps.as_ref()).unwrap().accounts.len()
+        );
+
+        // a new admin takes over directly
+        let msg = MigrateMsg {
+            admin: Some("gov".to_string()),
+            legacy_senders: vec![],
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let cfg = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(Addr::unchecked("gov"), cfg.admin);
+        assert_eq!(17, cfg.cw1_code_id);
+
+        // but not a newer one
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), no_senders()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SimpleIca(SimpleIcaError::CannotDowngrade {
+                previous: "99.0.0".to_string(),
+                current: CONTRACT_VERSION.to_string()
+            })
+        );
+
+        // nor another contract
+        set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), no_senders()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SimpleIca(SimpleIcaError::WrongContract(
+                "crates.io:cw20-base".to_string()
+            ))
+        );
+    }
+}
@@ -1,22 +1,198 @@
-use cosmwasm_std::CosmosMsg;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-/// Just needs to know the code_id of a reflect contract to spawn sub-accounts
+use cosmwasm_std::{Binary, Coin, CosmosMsg};
+
+use simple_ica::CounterpartyPolicy;
+
+use crate::backend::AccountBackend;
+use crate::limits::{Allowance, SpendingLimits};
+use crate::policy::MsgPolicy;
+use crate::state::Counterparty;
+
+/// Just needs to know the code_id of a reflect contract to spawn sub-accounts.
+/// The sender becomes the admin.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub cw1_code_id: u64,
+    /// The contract behind `cw1_code_id`, cw1-whitelist if not given
+    #[serde(default)]
+    pub account_backend: Option<AccountBackend>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    /// Replaces the admin, cancelling any transfer in progress.
+    /// Required when migrating from a version without an admin.
+    #[serde(default)]
+    pub admin: Option<String>,
+    /// Only needed when migrating from 0.2.0, which had one account per channel.
+    /// Every such account must be given the sender it now belongs to,
+    /// which is the admin of the controller on the other end.
+    #[serde(default)]
+    pub legacy_senders: Vec<LegacySender>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacySender {
+    pub channel_id: String,
+    pub sender: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecuteMsg {
+    /// Proposes a new admin, who must accept before taking over.
+    /// Proposing another address replaces the proposal.
+    TransferAdmin {
+        admin: String,
+    },
+    /// Makes the proposed admin the admin. Only they may call it
+    AcceptAdmin {},
+    /// Switches to another account contract for accounts created from now on.
+    /// Existing accounts keep the one they were created with
+    UpdateAccountBackend {
+        cw1_code_id: u64,
+        account_backend: AccountBackend,
+    },
+    /// Lets accounts be migrated to this code, which runs the given backend
+    AllowAccountCode {
+        code_id: u64,
+        account_backend: AccountBackend,
+    },
+    DisallowAccountCode {
+        code_id: u64,
+    },
+    /// Migrates all accounts, or those on one channel, to an allowed code.
+    /// Accounts created without an admin are skipped
+    MigrateAccounts {
+        code_id: u64,
+        msg: Binary,
+        channel_id: Option<String>,
+    },
+    /// Sets who may open channels to us. Channels that are already open are not affected
+    UpdateCounterpartyPolicy {
+        policy: CounterpartyPolicy,
+    },
+    /// Sets which messages may be dispatched on channels without a policy of their own
+    UpdateDefaultMsgPolicy {
+        policy: MsgPolicy,
+    },
+    /// Sets which messages may be dispatched on this channel.
+    /// Without a policy, the channel uses the default one
+    UpdateChannelMsgPolicy {
+        channel_id: String,
+        policy: Option<MsgPolicy>,
+    },
+    /// Limits what each account on this channel may spend. None removes the limits
+    UpdateChannelLimits {
+        channel_id: String,
+        limits: Option<SpendingLimits>,
+    },
+    /// Limits what the account of this sender may spend, instead of the limits of its channel.
+    /// None removes them, so the account falls back to those of the channel
+    UpdateAccountLimits {
+        channel_id: String,
+        sender: String,
+        limits: Option<SpendingLimits>,
+    },
+    /// Rejects all packets on this channel with an error ack, until unfrozen
+    FreezeChannel {
+        channel_id: String,
+    },
+    UnfreezeChannel {
+        channel_id: String,
+    },
+    /// Starts closing the channel. The funds of its accounts are rescued once it closes
+    CloseChannel {
+        channel_id: String,
+    },
+    /// Only the contract itself may call this. It runs the messages of an atomic dispatch,
+    /// so that if one fails they are all reverted, and the failure can still be acked
+    DispatchAtomic {
+        account: String,
+        msgs: Vec<CosmosMsg>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    /// Returns (reflect) account that is attached to this channel,
+    /// Returns the admin, the proposed admin and the reflect code id
+    Config {},
+    /// Returns who may open channels to us
+    CounterpartyPolicy {},
+    /// Returns the message policy of channels without one of their own
+    DefaultMsgPolicy {},
+    /// Returns the message policy that applies to this channel
+    MsgPolicy { channel_id: String },
+    /// Returns the channels with a message policy of their own.
+    /// No pagination - this is a test contract
+    ListMsgPolicies {},
+    /// Returns the spending limits of the account of this sender,
+    /// and what it may still spend
+    Allowance { channel_id: String, sender: String },
+    /// Returns all frozen channels.
+    /// No pagination - this is a test contract
+    ListFrozenChannels {},
+    /// Returns (reflect) account of the sender on this channel,
     /// or none.
-    Account { channel_id: String },
-    /// Returns all (channel, reflect_account) pairs.
+    Account { channel_id: String, sender: String },
+    /// Returns all (channel, sender, reflect_account) triples.
     /// No pagination - this is a test contract
     ListAccounts {},
+    /// Returns the accounts of closed channels, waiting for their controller to come back.
+    /// No pagination - this is a test contract
+    ListDetachedAccounts {},
+    /// Returns the codes accounts may be migrated to.
+    /// No pagination - this is a test contract
+    ListAccountCodes {},
+    /// Returns the funds rescued from closed channels that were not reclaimed yet.
+    /// No pagination - this is a test contract
+    ListRescuedFunds {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub admin: String,
+    /// The admin proposed by the current one, if they did not accept yet
+    pub pending_admin: Option<String>,
+    pub cw1_code_id: u64,
+    pub account_backend: AccountBackend,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CounterpartyPolicyResponse {
+    pub policy: CounterpartyPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MsgPolicyResponse {
+    pub policy: MsgPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListMsgPoliciesResponse {
+    pub policies: Vec<ChannelMsgPolicy>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelMsgPolicy {
+    pub channel_id: String,
+    pub policy: MsgPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllowanceResponse {
+    /// The limits of the account, or of its channel. None if it may spend anything
+    pub limits: Option<SpendingLimits>,
+    /// What is left of each limited denom
+    pub allowances: Vec<Allowance>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListFrozenChannelsResponse {
+    pub channels: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -24,6 +200,32 @@ pub struct AccountResponse {
     pub account: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListDetachedAccountsResponse {
+    pub accounts: Vec<DetachedAccount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DetachedAccount {
+    pub account: String,
+    /// The controller it belongs to
+    pub counterparty: Counterparty,
+    pub sender: String,
+    /// The closed channel it was on
+    pub channel_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListAccountCodesResponse {
+    pub codes: Vec<AccountCode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AccountCode {
+    pub code_id: u64,
+    pub account_backend: AccountBackend,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ListAccountsResponse {
     pub accounts: Vec<AccountInfo>,
@@ -33,10 +235,21 @@ pub struct ListAccountsResponse {
 pub struct AccountInfo {
     pub account: String,
     pub channel_id: String,
+    /// address of the sender on the controller chain
+    pub sender: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
-pub enum ReflectExecuteMsg {
-    ReflectMsg { msgs: Vec<CosmosMsg> },
+pub struct ListRescuedFundsResponse {
+    pub funds: Vec<RescuedFundsInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RescuedFundsInfo {
+    /// the closed channel
+    pub channel_id: String,
+    /// address of the sender on the controller chain
+    pub sender: String,
+    pub counterparty: Counterparty,
+    pub balance: Vec<Coin>,
 }
@@ -0,0 +1,376 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{
+    BankMsg, CosmosMsg, DistributionMsg, GovMsg, IbcMsg, StakingMsg, StdResult, Storage, WasmMsg,
+};
+
+use crate::error::ContractError;
+use crate::state::{DEFAULT_MSG_POLICY, MSG_POLICIES};
+
+/// The kinds of messages a dispatch may contain
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MsgKind {
+    BankSend,
+    BankBurn,
+    StakingDelegate,
+    StakingUndelegate,
+    StakingRedelegate,
+    DistributionSetWithdrawAddress,
+    DistributionWithdrawDelegatorReward,
+    Stargate,
+    IbcTransfer,
+    IbcSendPacket,
+    IbcCloseChannel,
+    WasmExecute,
+    WasmInstantiate,
+    WasmMigrate,
+    WasmUpdateAdmin,
+    WasmClearAdmin,
+    GovVote,
+    Custom,
+}
+
+impl MsgKind {
+    /// The kind of this message. None for messages added to cosmwasm after this contract
+    pub fn of(msg: &CosmosMsg) -> Option<MsgKind> {
+        let kind = match msg {
+            CosmosMsg::Bank(BankMsg::Send { .. }) => MsgKind::BankSend,
+            CosmosMsg::Bank(BankMsg::Burn { .. }) => MsgKind::BankBurn,
+            CosmosMsg::Staking(StakingMsg::Delegate { .. }) => MsgKind::StakingDelegate,
+            CosmosMsg::Staking(StakingMsg::Undelegate { .. }) => MsgKind::StakingUndelegate,
+            CosmosMsg::Staking(StakingMsg::Redelegate { .. }) => MsgKind::StakingRedelegate,
+            CosmosMsg::Distribution(DistributionMsg::SetWithdrawAddress { .. }) => {
+                MsgKind::DistributionSetWithdrawAddress
+            }
+            CosmosMsg::Distribution(DistributionMsg::WithdrawDelegatorReward { .. }) => {
+                MsgKind::DistributionWithdrawDelegatorReward
+            }
+            CosmosMsg::Stargate { .. } => MsgKind::Stargate,
+            CosmosMsg::Ibc(IbcMsg::Transfer { .. }) => MsgKind::IbcTransfer,
+            CosmosMsg::Ibc(IbcMsg::SendPacket { .. }) => MsgKind::IbcSendPacket,
+            CosmosMsg::Ibc(IbcMsg::CloseChannel { .. }) => MsgKind::IbcCloseChannel,
+            CosmosMsg::Wasm(WasmMsg::Execute { .. }) => MsgKind::WasmExecute,
+            CosmosMsg::Wasm(WasmMsg::Instantiate { .. }) => MsgKind::WasmInstantiate,
+            CosmosMsg::Wasm(WasmMsg::Migrate { .. }) => MsgKind::WasmMigrate,
+            CosmosMsg::Wasm(WasmMsg::UpdateAdmin { .. }) => MsgKind::WasmUpdateAdmin,
+            CosmosMsg::Wasm(WasmMsg::ClearAdmin { .. }) => MsgKind::WasmClearAdmin,
+            CosmosMsg::Gov(GovMsg::Vote { .. }) => MsgKind::GovVote,
+            CosmosMsg::Custom(_) => MsgKind::Custom,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MsgKind::BankSend => "bank_send",
+            MsgKind::BankBurn => "bank_burn",
+            MsgKind::StakingDelegate => "staking_delegate",
+            MsgKind::StakingUndelegate => "staking_undelegate",
+            MsgKind::StakingRedelegate => "staking_redelegate",
+            MsgKind::DistributionSetWithdrawAddress => "distribution_set_withdraw_address",
+            MsgKind::DistributionWithdrawDelegatorReward => {
+                "distribution_withdraw_delegator_reward"
+            }
+            MsgKind::Stargate => "stargate",
+            MsgKind::IbcTransfer => "ibc_transfer",
+            MsgKind::IbcSendPacket => "ibc_send_packet",
+            MsgKind::IbcCloseChannel => "ibc_close_channel",
+            MsgKind::WasmExecute => "wasm_execute",
+            MsgKind::WasmInstantiate => "wasm_instantiate",
+            MsgKind::WasmMigrate => "wasm_migrate",
+            MsgKind::WasmUpdateAdmin => "wasm_update_admin",
+            MsgKind::WasmClearAdmin => "wasm_clear_admin",
+            MsgKind::GovVote => "gov_vote",
+            MsgKind::Custom => "custom",
+        }
+    }
+}
+
+/// Which messages the accounts on a channel may dispatch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MsgPolicy {
+    /// Any message
+    AllowAll,
+    /// Only messages of these kinds
+    AllowOnly {
+        kinds: Vec<MsgKind>,
+        /// The contracts `wasm_execute` may call. Any contract if not set
+        wasm_contracts: Option<Vec<String>>,
+    },
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for MsgPolicy {
+    fn default() -> Self {
+        MsgPolicy::AllowAll
+    }
+}
+
+impl MsgPolicy {
+    /// Fails unless every message is allowed, naming the first one that is not
+    pub fn check(&self, msgs: &[CosmosMsg]) -> Result<(), ContractError> {
+        let (kinds, wasm_contracts) = match self {
+            MsgPolicy::AllowAll => return Ok(()),
+            MsgPolicy::AllowOnly {
+                kinds,
+                wasm_contracts,
+            } => (kinds, wasm_contracts),
+        };
+        for (index, msg) in msgs.iter().enumerate() {
+            let kind = MsgKind::of(msg);
+            if !matches!(kind, Some(kind) if kinds.contains(&kind)) {
+                let kind = kind.map_or("unknown", |kind| kind.as_str());
+                return Err(ContractError::MsgNotAllowed {
+                    index,
+                    kind: kind.to_string(),
+                });
+            }
+            if let (CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }), Some(contracts)) =
+                (msg, wasm_contracts)
+            {
+                if !contracts.contains(contract_addr) {
+                    return Err(ContractError::ContractNotAllowed {
+                        index,
+                        contract: contract_addr.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The policy of this channel, or the default if it has none
+pub fn msg_policy(storage: &dyn Storage, channel_id: &str) -> StdResult<MsgPolicy> {
+    match MSG_POLICIES.may_load(storage, channel_id)? {
+        Some(policy) => Ok(policy),
+        None => Ok(DEFAULT_MSG_POLICY.may_load(storage)?.unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cosmwasm_std::{coin, coins, to_binary, Binary, Empty, IbcTimeout, Timestamp, VoteOption};
+
+    // one message of every kind
+    fn all_msgs() -> Vec<(MsgKind, CosmosMsg)> {
+        let timeout = IbcTimeout::with_timestamp(Timestamp::from_seconds(1000));
+        vec![
+            (
+                MsgKind::BankSend,
+                BankMsg::Send {
+                    to_address: "friend".into(),
+                    amount: coins(100, "uatom"),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::BankBurn,
+                BankMsg::Burn {
+                    amount: coins(100, "uatom"),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::StakingDelegate,
+                StakingMsg::Delegate {
+                    validator: "val".into(),
+                    amount: coin(100, "uatom"),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::StakingUndelegate,
+                StakingMsg::Undelegate {
+                    validator: "val".into(),
+                    amount: coin(100, "uatom"),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::StakingRedelegate,
+                StakingMsg::Redelegate {
+                    src_validator: "val".into(),
+                    dst_validator: "other-val".into(),
+                    amount: coin(100, "uatom"),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::DistributionSetWithdrawAddress,
+                DistributionMsg::SetWithdrawAddress {
+                    address: "friend".into(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::DistributionWithdrawDelegatorReward,
+                DistributionMsg::WithdrawDelegatorReward {
+                    validator: "val".into(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::Stargate,
+                CosmosMsg::Stargate {
+                    type_url: "/cosmos.bank.v1beta1.MsgSend".into(),
+                    value: Binary::default(),
+                },
+            ),
+            (
+                MsgKind::IbcTransfer,
+                IbcMsg::Transfer {
+                    channel_id: "channel-1".into(),
+                    to_address: "friend".into(),
+                    amount: coin(100, "uatom"),
+                    timeout: timeout.clone(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::IbcSendPacket,
+                IbcMsg::SendPacket {
+                    channel_id: "channel-1".into(),
+                    data: Binary::default(),
+                    timeout,
+                }
+                .into(),
+            ),
+            (
+                MsgKind::IbcCloseChannel,
+                IbcMsg::CloseChannel {
+                    channel_id: "channel-1".into(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::WasmExecute,
+                WasmMsg::Execute {
+                    contract_addr: "dex".into(),
+                    msg: to_binary(&Empty {}).unwrap(),
+                    funds: vec![],
+                }
+                .into(),
+            ),
+            (
+                MsgKind::WasmInstantiate,
+                WasmMsg::Instantiate {
+                    admin: None,
+                    code_id: 12,
+                    msg: to_binary(&Empty {}).unwrap(),
+                    funds: vec![],
+                    label: "dex".into(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::WasmMigrate,
+                WasmMsg::Migrate {
+                    contract_addr: "dex".into(),
+                    new_code_id: 13,
+                    msg: to_binary(&Empty {}).unwrap(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::WasmUpdateAdmin,
+                WasmMsg::UpdateAdmin {
+                    contract_addr: "dex".into(),
+                    admin: "friend".into(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::WasmClearAdmin,
+                WasmMsg::ClearAdmin {
+                    contract_addr: "dex".into(),
+                }
+                .into(),
+            ),
+            (
+                MsgKind::GovVote,
+                GovMsg::Vote {
+                    proposal_id: 1,
+                    vote: VoteOption::Yes,
+                }
+                .into(),
+            ),
+            (MsgKind::Custom, CosmosMsg::Custom(Empty {})),
+        ]
+    }
+
+    #[test]
+    fn each_kind_is_allowed_on_its_own() {
+        let msgs = all_msgs();
+        MsgPolicy::AllowAll
+            .check(&msgs.iter().map(|(_, msg)| msg.clone()).collect::<Vec<_>>())
+            .unwrap();
+
+        for (kind, msg) in &msgs {
+            assert_eq!(Some(*kind), MsgKind::of(msg));
+            let policy = MsgPolicy::AllowOnly {
+                kinds: vec![*kind],
+                wasm_contracts: None,
+            };
+            for (index, (other_kind, other)) in msgs.iter().enumerate() {
+                let res = policy.check(&[msg.clone(), other.clone()]);
+                if other_kind == kind {
+                    res.unwrap();
+                } else {
+                    let err = res.unwrap_err();
+                    assert_eq!(
+                        ContractError::MsgNotAllowed {
+                            index: 1,
+                            kind: other_kind.as_str().to_string(),
+                        },
+                        err,
+                        "{} allowed message {}",
+                        kind.as_str(),
+                        index
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wasm_execute_limited_to_contracts() {
+        let execute = |contract: &str| -> CosmosMsg {
+            WasmMsg::Execute {
+                contract_addr: contract.into(),
+                msg: to_binary(&Empty {}).unwrap(),
+                funds: vec![],
+            }
+            .into()
+        };
+        let policy = MsgPolicy::AllowOnly {
+            kinds: vec![MsgKind::WasmExecute, MsgKind::WasmMigrate],
+            wasm_contracts: Some(vec!["dex".into(), "vault".into()]),
+        };
+        policy.check(&[execute("dex"), execute("vault")]).unwrap();
+        let err = policy
+            .check(&[execute("dex"), execute("thief")])
+            .unwrap_err();
+        assert_eq!(
+            ContractError::ContractNotAllowed {
+                index: 1,
+                contract: "thief".into()
+            },
+            err
+        );
+
+        // only execute is limited
+        let migrate = WasmMsg::Migrate {
+            contract_addr: "thief".into(),
+            new_code_id: 13,
+            msg: to_binary(&Empty {}).unwrap(),
+        };
+        policy.check(&[migrate.into()]).unwrap();
+    }
+}
@@ -1,17 +1,145 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Binary};
+use cosmwasm_std::{Addr, Coin, CosmosMsg, Empty};
 use cw_storage_plus::{Item, Map};
+use simple_ica::{ChannelSettings, CounterpartyPolicy, DispatchMode, DispatchResponse};
+
+use crate::backend::AccountBackend;
+use crate::limits::{Spending, SpendingLimits};
+use crate::policy::MsgPolicy;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
+    pub admin: Addr,
     pub cw1_code_id: u64,
+    /// The contract behind `cw1_code_id`. Configs from before it was configurable are cw1-whitelist
+    #[serde(default)]
+    pub account_backend: AccountBackend,
+}
+
+/// The account we are creating in a submessage, and what to do with it once it exists
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingAccount {
+    pub channel_id: String,
+    pub sender: String,
+    /// Messages to dispatch once it is created. None if we only report the address (WhoAmI)
+    pub msgs: Option<Vec<CosmosMsg>>,
+    #[serde(default)]
+    pub mode: DispatchMode,
+}
+
+/// The controller on the other end of a channel
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Counterparty {
+    pub connection_id: String,
+    pub port_id: String,
+}
+
+/// An account of a closed channel, with the limits it had there
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Detached {
+    pub account: Addr,
+    /// The closed channel
+    pub channel_id: String,
+    pub limits: Option<SpendingLimits>,
+    pub spending: Option<Spending>,
+}
+
+/// What the admin set on a closed channel, for the next channel from the same controller
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelRules {
+    pub msg_policy: Option<MsgPolicy>,
+    pub limits: Option<SpendingLimits>,
+}
+
+/// Funds taken from an account when its channel closed, until its sender reclaims them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RescuedFunds {
+    /// Only a channel from the same controller may reclaim them
+    pub counterparty: Counterparty,
+    pub balance: Vec<Coin>,
+}
+
+/// An account being migrated on request of its sender, until the reply
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingMigration {
+    pub account: Addr,
+    pub code_id: u64,
+}
+
+/// A dispatch in one of the partial modes. Its messages run one at a time,
+/// each sent from the reply of the one before
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PartialDispatch {
+    pub account: Addr,
+    pub mode: DispatchMode,
+    /// The messages still to run
+    pub msgs: Vec<CosmosMsg>,
+}
+
+/// What the messages running now would spend, counted once they succeed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingSpending {
+    pub channel_id: String,
+    pub sender: String,
+    pub amount: Vec<Coin>,
 }
 
 pub const CONFIG: Item<Config> = Item::new("config");
-pub const PENDING: Item<String> = Item::new("pending");
-pub const ACCOUNTS: Map<&str, Addr> = Map::new("accounts");
+// the admin proposed by the current one, until they accept
+pub const PENDING_ADMIN: Item<Addr> = Item::new("pending_admin");
+// accounts being created, by the reply id of their instantiation
+pub const PENDING: Map<u64, PendingAccount> = Map::new("pending_accounts");
+// reply ids are handed out from INIT_CALLBACK_ID upwards, this counts how many we used
+pub const PENDING_COUNT: Item<u64> = Item::new("pending_count");
+// one reflect account per (channel_id, sender)
+pub const ACCOUNTS: Map<(&str, &str), Addr> = Map::new("accounts");
+// the backend each account was created with. Older accounts have none, and are cw1-whitelist
+pub const ACCOUNT_BACKENDS: Map<&Addr, AccountBackend> = Map::new("account_backends");
+// accounts we are the wasm admin of. Older ones were created without an admin,
+// and cannot be migrated
+pub const MANAGED_ACCOUNTS: Map<&Addr, Empty> = Map::new("managed_accounts");
+// the codes accounts may be migrated to, with the backend they become
+pub const ACCOUNT_CODES: Map<u64, AccountBackend> = Map::new("account_codes");
+// accounts of closed channels, by the (connection_id, port_id) of their controller and sender,
+// oldest first. A later channel from the same controller takes them over one by one
+pub const DETACHED_ACCOUNTS: Map<(&str, &str, &str), Vec<Detached>> = Map::new("detached_accounts");
+// the rules of closed channels, by the (connection_id, port_id) of their controller, oldest
+// first. Each new channel from that controller takes over the oldest
+pub const DETACHED_RULES: Map<(&str, &str), Vec<ChannelRules>> = Map::new("detached_rules");
+// the sender whose account answers a WhoAmI or Balances without a sender, as controllers
+// before 0.3.0 send them. Set when migrating from 0.2.0, or by the first dispatch on a
+// `simple-ica-v2` channel, as those controllers only let their admin dispatch
+pub const LEGACY_SENDERS: Map<&str, String> = Map::new("legacy_senders");
+// the controller of each open channel
+pub const CHANNELS: Map<&str, Counterparty> = Map::new("channels");
+// the protocol version and order agreed on each open channel. Channels opened before we
+// negotiated have none, and use the defaults
+pub const CHANNEL_SETTINGS: Map<&str, ChannelSettings> = Map::new("channel_settings");
+// who may open channels to us. Open to all if not set
+pub const COUNTERPARTY_POLICY: Item<CounterpartyPolicy> = Item::new("counterparty_policy");
+// which messages may be dispatched on channels without a policy of their own.
+// Allows all if not set
+pub const DEFAULT_MSG_POLICY: Item<MsgPolicy> = Item::new("default_msg_policy");
+// which messages may be dispatched on each channel, if not the default
+pub const MSG_POLICIES: Map<&str, MsgPolicy> = Map::new("msg_policies");
+// how much each account on a channel may spend, if limited
+pub const CHANNEL_LIMITS: Map<&str, SpendingLimits> = Map::new("channel_limits");
+// limits of single accounts, by (channel_id, sender). They replace those of the channel
+pub const ACCOUNT_LIMITS: Map<(&str, &str), SpendingLimits> = Map::new("account_limits");
+// what each limited account spent in its current window, by (channel_id, sender)
+pub const SPENDING: Map<(&str, &str), Spending> = Map::new("spending");
+// channels that refuse all packets until unfrozen
+pub const FROZEN_CHANNELS: Map<&str, Empty> = Map::new("frozen_channels");
+// funds rescued from closed channels, by (channel_id, sender)
+pub const RESCUED_FUNDS: Map<(&str, &str), RescuedFunds> = Map::new("rescued_funds");
 
 // this stores all results from current dispatch
-pub const RESULTS: Item<Vec<Binary>> = Item::new("results");
+pub const RESULTS: Item<DispatchResponse> = Item::new("results");
+// the account migrated by the current packet
+pub const PENDING_MIGRATION: Item<PendingMigration> = Item::new("pending_migration");
+// the messages left of the current dispatch, in the partial modes
+pub const PARTIAL_DISPATCH: Item<PartialDispatch> = Item::new("partial_dispatch");
+// the spending of the messages the current dispatch is running
+pub const PENDING_SPENDING: Item<PendingSpending> = Item::new("pending_spending");
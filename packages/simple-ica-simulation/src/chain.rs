@@ -0,0 +1,1060 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::rc::Rc;
+
+use cosmwasm_std::testing::{mock_env, MockApi};
+use cosmwasm_std::{
+    coin, from_slice, to_binary, to_vec, Addr, AllBalanceResponse, Attribute, BalanceResponse,
+    BankMsg, BankQuery, Binary, BlockInfo, Coin, ContractInfo, CosmosMsg, Deps, DepsMut, Empty,
+    Env, Event, IbcAcknowledgement, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
+    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcMsg, IbcOrder, IbcPacket,
+    IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, IbcTimeout,
+    MessageInfo, Order, Querier, QuerierResult, QuerierWrapper, QueryRequest, Record, Reply,
+    ReplyOn, Response, Storage, SubMsg, SubMsgResponse, SubMsgResult, SystemError, SystemResult,
+    TransactionInfo, Uint128, WasmMsg, WasmQuery,
+};
+use cw_utils::NativeBalance;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::contract::Contract;
+use crate::error::SimError;
+
+/// Port of the ics20 transfer module
+pub const TRANSFER_PORT: &str = "transfer";
+/// Version of the ics20 transfer module
+pub const TRANSFER_VERSION: &str = "ics20-1";
+
+/// Port bound by a contract to send and receive packets
+pub fn contract_port(contract: &Addr) -> String {
+    format!("wasm.{}", contract)
+}
+
+/// Everything a message did on the chain, including the submessages it triggered
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AppResponse {
+    pub events: Vec<Event>,
+    pub data: Option<Binary>,
+}
+
+/// A contract's response, whichever entry point it came from
+struct ContractResponse {
+    messages: Vec<SubMsg>,
+    events: Vec<Event>,
+    data: Option<Binary>,
+}
+
+impl From<Response> for ContractResponse {
+    fn from(res: Response) -> Self {
+        ContractResponse {
+            messages: res.messages,
+            events: wasm_events(res.attributes, res.events),
+            data: res.data,
+        }
+    }
+}
+
+impl From<IbcBasicResponse> for ContractResponse {
+    fn from(res: IbcBasicResponse) -> Self {
+        ContractResponse {
+            messages: res.messages,
+            events: wasm_events(res.attributes, res.events),
+            data: None,
+        }
+    }
+}
+
+impl From<IbcReceiveResponse> for ContractResponse {
+    fn from(res: IbcReceiveResponse) -> Self {
+        ContractResponse {
+            messages: res.messages,
+            events: wasm_events(res.attributes, res.events),
+            data: Some(res.acknowledgement),
+        }
+    }
+}
+
+// the contract address is added to these when we know it
+fn wasm_events(attributes: Vec<Attribute>, events: Vec<Event>) -> Vec<Event> {
+    let mut wasm = vec![];
+    if !attributes.is_empty() {
+        wasm.push(Event::new("wasm").add_attributes(attributes));
+    }
+    let custom = events
+        .into_iter()
+        .map(|e| Event::new(format!("wasm-{}", e.ty)).add_attributes(e.attributes));
+    wasm.extend(custom);
+    wasm
+}
+
+/// Storage of one contract. Unlike `MockStorage`, this can be cloned to roll back failed messages
+#[derive(Clone, Default)]
+struct ContractStorage(BTreeMap<Vec<u8>, Vec<u8>>);
+
+impl Storage for ContractStorage {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key).cloned()
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Box::new(std::iter::empty());
+            }
+        }
+        let start = start.map_or(Bound::Unbounded, |s| Bound::Included(s.to_vec()));
+        let end = end.map_or(Bound::Unbounded, |e| Bound::Excluded(e.to_vec()));
+        let iter = self
+            .0
+            .range((start, end))
+            .map(|(k, v)| (k.clone(), v.clone()));
+        match order {
+            Order::Ascending => Box::new(iter),
+            Order::Descending => Box::new(iter.rev()),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.0.insert(key.to_vec(), value.to_vec());
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.0.remove(key);
+    }
+}
+
+#[derive(Clone)]
+struct ContractData {
+    code_id: u64,
+    admin: Option<Addr>,
+    storage: ContractStorage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChannelState {
+    Init,
+    Open,
+    Closed,
+}
+
+#[derive(Clone)]
+struct ChannelData {
+    channel: IbcChannel,
+    state: ChannelState,
+    next_sequence: u64,
+}
+
+/// Work for the relayer, in the order it happened
+#[derive(Clone)]
+pub(crate) enum Outgoing {
+    Packet(IbcPacket),
+    /// The channel was closed on this side, the endpoint is the counterparty's
+    Close(IbcEndpoint),
+}
+
+/// Ics20 packet data, as the transfer module sends it
+#[derive(Serialize, Deserialize)]
+struct Ics20Packet {
+    amount: Uint128,
+    denom: String,
+    receiver: String,
+    sender: String,
+}
+
+/// Everything that is rolled back if a transaction fails
+#[derive(Clone, Default)]
+struct ChainState {
+    contracts: BTreeMap<Addr, ContractData>,
+    balances: BTreeMap<Addr, NativeBalance>,
+    channels: BTreeMap<String, ChannelData>,
+    outbox: VecDeque<Outgoing>,
+}
+
+/// One mocked chain, running contracts in process with wasmd's semantics for submessages,
+/// replies and ibc. Packets sent by contracts wait for the `relayer` to deliver them.
+pub struct Chain {
+    block: BlockInfo,
+    api: MockApi,
+    codes: Vec<Rc<dyn Contract>>,
+    state: ChainState,
+}
+
+impl Chain {
+    pub fn new(chain_id: &str) -> Self {
+        let mut block = mock_env().block;
+        block.chain_id = chain_id.to_string();
+        Chain {
+            block,
+            api: MockApi::default(),
+            codes: vec![],
+            state: ChainState::default(),
+        }
+    }
+
+    pub fn block_info(&self) -> BlockInfo {
+        self.block.clone()
+    }
+
+    /// Moves on to the next block, the given number of seconds later
+    pub fn next_block(&mut self, seconds: u64) {
+        self.block.height += 1;
+        self.block.time = self.block.time.plus_seconds(seconds);
+    }
+
+    /// Returns the code id to instantiate this contract with
+    pub fn store_code(&mut self, code: Box<dyn Contract>) -> u64 {
+        self.codes.push(Rc::from(code));
+        self.codes.len() as u64
+    }
+
+    pub fn set_balance(&mut self, addr: &Addr, amount: Vec<Coin>) {
+        let mut balance = NativeBalance(amount);
+        balance.normalize();
+        self.state.balances.insert(addr.clone(), balance);
+    }
+
+    pub fn balance(&self, addr: &Addr, denom: &str) -> Coin {
+        self.all_balances(addr)
+            .into_iter()
+            .find(|c| c.denom == denom)
+            .unwrap_or_else(|| coin(0, denom))
+    }
+
+    pub fn all_balances(&self, addr: &Addr) -> Vec<Coin> {
+        self.state
+            .balances
+            .get(addr)
+            .map(|b| b.clone().into_vec())
+            .unwrap_or_default()
+    }
+
+    pub fn instantiate<T: Serialize>(
+        &mut self,
+        code_id: u64,
+        sender: &Addr,
+        msg: &T,
+        funds: &[Coin],
+        label: &str,
+        admin: Option<String>,
+    ) -> Result<Addr, SimError> {
+        let msg = to_binary(msg)?;
+        self.transact(|chain| {
+            let (addr, _) = chain.instantiate_contract(
+                sender,
+                code_id,
+                &msg,
+                funds.to_vec(),
+                label.to_string(),
+                admin,
+            )?;
+            Ok(addr)
+        })
+    }
+
+    pub fn execute<T: Serialize>(
+        &mut self,
+        sender: &Addr,
+        contract: &Addr,
+        msg: &T,
+        funds: &[Coin],
+    ) -> Result<AppResponse, SimError> {
+        let msg = WasmMsg::Execute {
+            contract_addr: contract.to_string(),
+            msg: to_binary(msg)?,
+            funds: funds.to_vec(),
+        };
+        self.transact(|chain| chain.execute_msg(sender, msg.into()))
+    }
+
+    pub fn migrate<T: Serialize>(
+        &mut self,
+        sender: &Addr,
+        contract: &Addr,
+        new_code_id: u64,
+        msg: &T,
+    ) -> Result<AppResponse, SimError> {
+        let msg = WasmMsg::Migrate {
+            contract_addr: contract.to_string(),
+            new_code_id,
+            msg: to_binary(msg)?,
+        };
+        self.transact(|chain| chain.execute_msg(sender, msg.into()))
+    }
+
+    pub fn query<Q: Serialize, T: DeserializeOwned>(
+        &self,
+        contract: &Addr,
+        msg: &Q,
+    ) -> Result<T, SimError> {
+        let res = self.query_contract(contract, &to_vec(msg)?)?;
+        Ok(from_slice(&res)?)
+    }
+
+    /// The channel as this chain sees it, in any state
+    pub fn channel(&self, channel_id: &str) -> Option<IbcChannel> {
+        self.state
+            .channels
+            .get(channel_id)
+            .map(|c| c.channel.clone())
+    }
+
+    pub fn is_channel_open(&self, channel_id: &str) -> bool {
+        matches!(
+            self.state.channels.get(channel_id),
+            Some(ChannelData {
+                state: ChannelState::Open,
+                ..
+            })
+        )
+    }
+
+    /// Id of the next channel created on this chain
+    pub(crate) fn next_channel_id(&self) -> String {
+        format!("channel-{}", self.state.channels.len())
+    }
+
+    pub(crate) fn pop_outgoing(&mut self) -> Option<Outgoing> {
+        self.state.outbox.pop_front()
+    }
+
+    /// Runs a handshake step which may create the channel (init and try).
+    /// Returns the version the contract wants, if it changed it.
+    pub(crate) fn open_channel(&mut self, msg: IbcChannelOpenMsg) -> Result<String, SimError> {
+        self.transact(|chain| {
+            let mut channel = msg.channel().clone();
+            chain.state.channels.insert(
+                channel.endpoint.channel_id.clone(),
+                ChannelData {
+                    channel: channel.clone(),
+                    state: ChannelState::Init,
+                    next_sequence: 1,
+                },
+            );
+            if let Some(contract) = port_owner(&channel.endpoint.port_id) {
+                let res = chain.call_contract(&contract, |code, deps, env| {
+                    code.ibc_channel_open(deps, env, msg)
+                })?;
+                if let Some(res) = res {
+                    channel.version = res.version;
+                }
+            }
+            let version = channel.version.clone();
+            chain.save_channel(channel, ChannelState::Init);
+            Ok(version)
+        })
+    }
+
+    /// Runs the last handshake steps (ack and confirm), which open the channel
+    pub(crate) fn connect_channel(
+        &mut self,
+        msg: IbcChannelConnectMsg,
+    ) -> Result<AppResponse, SimError> {
+        self.transact(|chain| {
+            let channel = msg.channel().clone();
+            chain.save_channel(channel.clone(), ChannelState::Open);
+            match port_owner(&channel.endpoint.port_id) {
+                Some(contract) => {
+                    let res = chain.call_contract(&contract, |code, deps, env| {
+                        code.ibc_channel_connect(deps, env, msg)
+                    })?;
+                    chain.process_response(&contract, res.into())
+                }
+                None => Ok(AppResponse::default()),
+            }
+        })
+    }
+
+    /// Closes the channel on this side, the counterparty must be told by the relayer
+    pub(crate) fn close_channel_init(&mut self, channel_id: &str) -> Result<IbcEndpoint, SimError> {
+        self.transact(|chain| {
+            let channel = chain.open_channel_end(channel_id)?;
+            chain.save_channel(channel.clone(), ChannelState::Closed);
+            if let Some(contract) = port_owner(&channel.endpoint.port_id) {
+                let msg = IbcChannelCloseMsg::new_init(channel.clone());
+                let res = chain.call_contract(&contract, |code, deps, env| {
+                    code.ibc_channel_close(deps, env, msg)
+                })?;
+                chain.process_response(&contract, res.into())?;
+            }
+            Ok(channel.counterparty_endpoint)
+        })
+    }
+
+    /// Closes the channel after the counterparty did
+    pub(crate) fn close_channel_confirm(
+        &mut self,
+        channel_id: &str,
+    ) -> Result<AppResponse, SimError> {
+        self.transact(|chain| {
+            let channel = chain.open_channel_end(channel_id)?;
+            chain.save_channel(channel.clone(), ChannelState::Closed);
+            match port_owner(&channel.endpoint.port_id) {
+                Some(contract) => {
+                    let msg = IbcChannelCloseMsg::new_confirm(channel);
+                    let res = chain.call_contract(&contract, |code, deps, env| {
+                        code.ibc_channel_close(deps, env, msg)
+                    })?;
+                    chain.process_response(&contract, res.into())
+                }
+                None => Ok(AppResponse::default()),
+            }
+        })
+    }
+
+    /// Delivers a packet and returns the acknowledgement written for it.
+    /// Like wasmd, we revert a failed contract call and acknowledge with its error.
+    pub(crate) fn receive_packet(&mut self, packet: IbcPacket) -> Result<Binary, SimError> {
+        self.open_channel_end(&packet.dest.channel_id)?;
+        let contract = match port_owner(&packet.dest.port_id) {
+            Some(contract) => contract,
+            None if packet.dest.port_id == TRANSFER_PORT => {
+                return self.transact(|chain| chain.receive_transfer(packet))
+            }
+            None => return Err(SimError::UnknownChannel(packet.dest.channel_id)),
+        };
+
+        let res = self.transact(|chain| {
+            let msg = IbcPacketReceiveMsg::new(packet, relayer());
+            let res = chain.call_contract(&contract, |code, deps, env| {
+                code.ibc_packet_receive(deps, env, msg)
+            })?;
+            // a reply setting data overrides the acknowledgement
+            let res = chain.process_response(&contract, res.into())?;
+            Ok(res.data.unwrap_or_default())
+        });
+        match res {
+            Ok(ack) => Ok(ack),
+            Err(err) => Ok(to_binary(&Acknowledgement::Error(err.to_string()))?),
+        }
+    }
+
+    pub(crate) fn acknowledge_packet(
+        &mut self,
+        packet: IbcPacket,
+        ack: Binary,
+    ) -> Result<AppResponse, SimError> {
+        self.transact(|chain| match port_owner(&packet.src.port_id) {
+            Some(contract) => {
+                let msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack), packet, relayer());
+                let res = chain.call_contract(&contract, |code, deps, env| {
+                    code.ibc_packet_ack(deps, env, msg)
+                })?;
+                chain.process_response(&contract, res.into())
+            }
+            None => {
+                if let Acknowledgement::Error(_) = from_slice(&ack)? {
+                    chain.refund_transfer(&packet)?;
+                }
+                Ok(AppResponse::default())
+            }
+        })
+    }
+
+    /// Like ibc-go, a timeout closes an ordered channel without calling the contract's
+    /// ibc_channel_close. The relayer closes the counterparty end before relaying anything else.
+    pub(crate) fn timeout_packet(&mut self, packet: IbcPacket) -> Result<AppResponse, SimError> {
+        self.transact(|chain| {
+            if let Ok(channel) = chain.open_channel_end(&packet.src.channel_id) {
+                if channel.order == IbcOrder::Ordered {
+                    let counterparty = channel.counterparty_endpoint.clone();
+                    chain.save_channel(channel, ChannelState::Closed);
+                    chain.state.outbox.push_front(Outgoing::Close(counterparty));
+                }
+            }
+            match port_owner(&packet.src.port_id) {
+                Some(contract) => {
+                    let msg = IbcPacketTimeoutMsg::new(packet, relayer());
+                    let res = chain.call_contract(&contract, |code, deps, env| {
+                        code.ibc_packet_timeout(deps, env, msg)
+                    })?;
+                    chain.process_response(&contract, res.into())
+                }
+                None => {
+                    chain.refund_transfer(&packet)?;
+                    Ok(AppResponse::default())
+                }
+            }
+        })
+    }
+
+    /// Whether the packet can no longer be received on this chain
+    pub(crate) fn packet_expired(&self, packet: &IbcPacket) -> bool {
+        let by_time = matches!(packet.timeout.timestamp(), Some(t) if self.block.time >= t);
+        let by_height = matches!(packet.timeout.block(), Some(b) if self.block.height >= b.height);
+        by_time || by_height
+    }
+
+    // all state changes are reverted if the action fails
+    fn transact<T>(
+        &mut self,
+        action: impl FnOnce(&mut Self) -> Result<T, SimError>,
+    ) -> Result<T, SimError> {
+        let snapshot = self.state.clone();
+        let res = action(self);
+        if res.is_err() {
+            self.state = snapshot;
+        }
+        res
+    }
+
+    fn env(&self, contract: &Addr) -> Env {
+        Env {
+            block: self.block.clone(),
+            transaction: Some(TransactionInfo { index: 0 }),
+            contract: ContractInfo {
+                address: contract.clone(),
+            },
+        }
+    }
+
+    fn code(&self, code_id: u64) -> Result<Rc<dyn Contract>, SimError> {
+        let idx = (code_id as usize)
+            .checked_sub(1)
+            .ok_or(SimError::UnknownCode(code_id))?;
+        self.codes
+            .get(idx)
+            .cloned()
+            .ok_or(SimError::UnknownCode(code_id))
+    }
+
+    /// Calls an entry point of the contract with its storage. Other contracts can be queried
+    /// in the meantime, but queries to this contract don't see its storage until the call returns.
+    fn call_contract<T>(
+        &mut self,
+        contract: &Addr,
+        call: impl FnOnce(&dyn Contract, DepsMut, Env) -> Result<T, SimError>,
+    ) -> Result<T, SimError> {
+        let data = self
+            .state
+            .contracts
+            .get_mut(contract)
+            .ok_or_else(|| SimError::UnknownContract(contract.to_string()))?;
+        let code_id = data.code_id;
+        let mut storage = std::mem::take(&mut data.storage);
+
+        let res = self.code(code_id).and_then(|code| {
+            let querier = ChainQuerier { chain: self };
+            let deps = DepsMut {
+                storage: &mut storage,
+                api: &self.api,
+                querier: QuerierWrapper::new(&querier),
+            };
+            call(code.as_ref(), deps, self.env(contract))
+        });
+
+        if let Some(data) = self.state.contracts.get_mut(contract) {
+            data.storage = storage;
+        }
+        res
+    }
+
+    fn query_contract(&self, contract: &Addr, msg: &[u8]) -> Result<Binary, SimError> {
+        let data = self
+            .state
+            .contracts
+            .get(contract)
+            .ok_or_else(|| SimError::UnknownContract(contract.to_string()))?;
+        let querier = ChainQuerier { chain: self };
+        let deps = Deps {
+            storage: &data.storage,
+            api: &self.api,
+            querier: QuerierWrapper::new(&querier),
+        };
+        self.code(data.code_id)?
+            .query(deps, self.env(contract), msg)
+    }
+
+    /// Executes all submessages of a contract's response and calls its replies, like wasmd does.
+    /// Events of the whole tree are returned along with the data, which replies may override.
+    fn process_response(
+        &mut self,
+        contract: &Addr,
+        res: ContractResponse,
+    ) -> Result<AppResponse, SimError> {
+        let mut events: Vec<Event> = res
+            .events
+            .into_iter()
+            .map(|e| {
+                // attr() refuses reserved keys, but the chain itself may set them
+                let mut attributes = vec![Attribute {
+                    key: "_contract_address".to_string(),
+                    value: contract.to_string(),
+                }];
+                attributes.extend(e.attributes);
+                Event::new(e.ty).add_attributes(attributes)
+            })
+            .collect();
+        let mut data = res.data;
+
+        for SubMsg {
+            id, msg, reply_on, ..
+        } in res.messages
+        {
+            let snapshot = self.state.clone();
+            let result = match self.execute_msg(contract, msg) {
+                Ok(sub) => {
+                    events.extend(sub.events.clone());
+                    if !matches!(reply_on, ReplyOn::Always | ReplyOn::Success) {
+                        continue;
+                    }
+                    SubMsgResult::Ok(SubMsgResponse {
+                        events: sub.events,
+                        data: sub.data,
+                    })
+                }
+                Err(err) => {
+                    self.state = snapshot;
+                    if !matches!(reply_on, ReplyOn::Always | ReplyOn::Error) {
+                        return Err(err);
+                    }
+                    SubMsgResult::Err(err.to_string())
+                }
+            };
+            let reply = self.reply(contract, Reply { id, result })?;
+            events.extend(reply.events);
+            if reply.data.is_some() {
+                data = reply.data;
+            }
+        }
+
+        Ok(AppResponse { events, data })
+    }
+
+    fn reply(&mut self, contract: &Addr, reply: Reply) -> Result<AppResponse, SimError> {
+        let res = self.call_contract(contract, |code, deps, env| code.reply(deps, env, reply))?;
+        let mut res = self.process_response(contract, res.into())?;
+        res.events.insert(
+            0,
+            Event::new("reply").add_attribute("_contract_address", contract),
+        );
+        Ok(res)
+    }
+
+    fn execute_msg(&mut self, sender: &Addr, msg: CosmosMsg) -> Result<AppResponse, SimError> {
+        match msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => {
+                let recipient = Addr::unchecked(to_address);
+                self.send_tokens(sender, &recipient, &amount)?;
+                let event = Event::new("transfer")
+                    .add_attribute("recipient", recipient)
+                    .add_attribute("sender", sender)
+                    .add_attribute("amount", NativeBalance(amount).to_string());
+                Ok(AppResponse {
+                    events: vec![event],
+                    data: None,
+                })
+            }
+            CosmosMsg::Bank(BankMsg::Burn { amount }) => {
+                self.burn(sender, &amount)?;
+                let event = Event::new("burn")
+                    .add_attribute("burner", sender)
+                    .add_attribute("amount", NativeBalance(amount).to_string());
+                Ok(AppResponse {
+                    events: vec![event],
+                    data: None,
+                })
+            }
+            CosmosMsg::Wasm(msg) => self.execute_wasm(sender, msg),
+            CosmosMsg::Ibc(msg) => self.execute_ibc(sender, msg),
+            msg => Err(SimError::Unsupported(format!("{:?}", msg))),
+        }
+    }
+
+    fn execute_wasm(&mut self, sender: &Addr, msg: WasmMsg) -> Result<AppResponse, SimError> {
+        match msg {
+            WasmMsg::Execute {
+                contract_addr,
+                msg,
+                funds,
+            } => {
+                let contract = self.contract_addr(&contract_addr)?;
+                self.send_tokens(sender, &contract, &funds)?;
+                let info = MessageInfo {
+                    sender: sender.clone(),
+                    funds,
+                };
+                let res = self.call_contract(&contract, |code, deps, env| {
+                    code.execute(deps, env, info, &msg)
+                })?;
+                let mut res = self.process_response(&contract, res.into())?;
+                res.events.insert(
+                    0,
+                    Event::new("execute").add_attribute("_contract_address", &contract),
+                );
+                // wasmd wraps the data in a MsgExecuteContractResponse
+                res.data = res.data.map(|data| {
+                    let mut encoded = vec![];
+                    encode_bytes(1, &data, &mut encoded);
+                    encoded.into()
+                });
+                Ok(res)
+            }
+            WasmMsg::Instantiate {
+                admin,
+                code_id,
+                msg,
+                funds,
+                label,
+            } => {
+                let (_, res) =
+                    self.instantiate_contract(sender, code_id, &msg, funds, label, admin)?;
+                Ok(res)
+            }
+            WasmMsg::Migrate {
+                contract_addr,
+                new_code_id,
+                msg,
+            } => {
+                let contract = self.contract_addr(&contract_addr)?;
+                self.check_admin(sender, &contract)?;
+                self.code(new_code_id)?;
+                if let Some(data) = self.state.contracts.get_mut(&contract) {
+                    data.code_id = new_code_id;
+                }
+                let res =
+                    self.call_contract(&contract, |code, deps, env| code.migrate(deps, env, &msg))?;
+                let mut res = self.process_response(&contract, res.into())?;
+                res.events.insert(
+                    0,
+                    Event::new("migrate")
+                        .add_attribute("_contract_address", &contract)
+                        .add_attribute("code_id", new_code_id.to_string()),
+                );
+                Ok(res)
+            }
+            WasmMsg::UpdateAdmin {
+                contract_addr,
+                admin,
+            } => {
+                let contract = self.contract_addr(&contract_addr)?;
+                self.check_admin(sender, &contract)?;
+                if let Some(data) = self.state.contracts.get_mut(&contract) {
+                    data.admin = Some(Addr::unchecked(admin));
+                }
+                Ok(AppResponse::default())
+            }
+            WasmMsg::ClearAdmin { contract_addr } => {
+                let contract = self.contract_addr(&contract_addr)?;
+                self.check_admin(sender, &contract)?;
+                if let Some(data) = self.state.contracts.get_mut(&contract) {
+                    data.admin = None;
+                }
+                Ok(AppResponse::default())
+            }
+            msg => Err(SimError::Unsupported(format!("{:?}", msg))),
+        }
+    }
+
+    fn instantiate_contract(
+        &mut self,
+        sender: &Addr,
+        code_id: u64,
+        msg: &[u8],
+        funds: Vec<Coin>,
+        label: String,
+        admin: Option<String>,
+    ) -> Result<(Addr, AppResponse), SimError> {
+        self.code(code_id)?;
+        let contract = Addr::unchecked(format!("contract{}", self.state.contracts.len()));
+        let data = ContractData {
+            code_id,
+            admin: admin.map(Addr::unchecked),
+            storage: ContractStorage::default(),
+        };
+        self.state.contracts.insert(contract.clone(), data);
+
+        self.send_tokens(sender, &contract, &funds)?;
+        let info = MessageInfo {
+            sender: sender.clone(),
+            funds,
+        };
+        let res = self.call_contract(&contract, |code, deps, env| {
+            code.instantiate(deps, env, info, msg)
+        })?;
+        let mut res = self.process_response(&contract, res.into())?;
+        res.events.insert(
+            0,
+            Event::new("instantiate")
+                .add_attribute("_contract_address", &contract)
+                .add_attribute("code_id", code_id.to_string())
+                .add_attribute("label", label),
+        );
+        // wasmd returns a MsgInstantiateContractResponse
+        let mut encoded = vec![];
+        encode_bytes(1, contract.as_bytes(), &mut encoded);
+        if let Some(data) = res.data {
+            encode_bytes(2, &data, &mut encoded);
+        }
+        res.data = Some(encoded.into());
+        Ok((contract, res))
+    }
+
+    fn execute_ibc(&mut self, sender: &Addr, msg: IbcMsg) -> Result<AppResponse, SimError> {
+        match msg {
+            IbcMsg::SendPacket {
+                channel_id,
+                data,
+                timeout,
+            } => {
+                let packet =
+                    self.send_packet(&contract_port(sender), &channel_id, data, timeout)?;
+                Ok(AppResponse {
+                    events: vec![self.send_packet_event(&packet)],
+                    data: None,
+                })
+            }
+            IbcMsg::Transfer {
+                channel_id,
+                to_address,
+                amount,
+                timeout,
+            } => {
+                self.burn(sender, std::slice::from_ref(&amount))?;
+                let data = to_binary(&Ics20Packet {
+                    amount: amount.amount,
+                    denom: amount.denom,
+                    receiver: to_address,
+                    sender: sender.to_string(),
+                })?;
+                let packet = self.send_packet(TRANSFER_PORT, &channel_id, data, timeout)?;
+                Ok(AppResponse {
+                    events: vec![self.send_packet_event(&packet)],
+                    data: None,
+                })
+            }
+            IbcMsg::CloseChannel { channel_id } => {
+                let channel = self.open_channel_end(&channel_id)?;
+                if channel.endpoint.port_id != contract_port(sender) {
+                    return Err(SimError::UnknownChannel(channel_id));
+                }
+                let counterparty = self.close_channel_init(&channel_id)?;
+                self.state.outbox.push_back(Outgoing::Close(counterparty));
+                let event = Event::new("channel_close_init")
+                    .add_attribute("port_id", channel.endpoint.port_id)
+                    .add_attribute("channel_id", channel_id);
+                Ok(AppResponse {
+                    events: vec![event],
+                    data: None,
+                })
+            }
+            msg => Err(SimError::Unsupported(format!("{:?}", msg))),
+        }
+    }
+
+    fn send_packet(
+        &mut self,
+        port_id: &str,
+        channel_id: &str,
+        data: Binary,
+        timeout: IbcTimeout,
+    ) -> Result<IbcPacket, SimError> {
+        let channel = self
+            .state
+            .channels
+            .get_mut(channel_id)
+            .filter(|c| c.state == ChannelState::Open && c.channel.endpoint.port_id == port_id)
+            .ok_or_else(|| SimError::UnknownChannel(channel_id.to_string()))?;
+        let packet = IbcPacket::new(
+            data,
+            channel.channel.endpoint.clone(),
+            channel.channel.counterparty_endpoint.clone(),
+            channel.next_sequence,
+            timeout,
+        );
+        channel.next_sequence += 1;
+        self.state
+            .outbox
+            .push_back(Outgoing::Packet(packet.clone()));
+        Ok(packet)
+    }
+
+    fn send_packet_event(&self, packet: &IbcPacket) -> Event {
+        let timeout = packet.timeout.timestamp().unwrap_or_default().nanos();
+        Event::new("send_packet")
+            .add_attribute("packet_data", String::from_utf8_lossy(&packet.data))
+            .add_attribute("packet_timeout_timestamp", timeout.to_string())
+            .add_attribute("packet_sequence", packet.sequence.to_string())
+            .add_attribute("packet_src_port", &packet.src.port_id)
+            .add_attribute("packet_src_channel", &packet.src.channel_id)
+            .add_attribute("packet_dst_port", &packet.dest.port_id)
+            .add_attribute("packet_dst_channel", &packet.dest.channel_id)
+    }
+
+    // tokens coming back lose the prefix they got on the way out, others get ours
+    fn receive_transfer(&mut self, packet: IbcPacket) -> Result<Binary, SimError> {
+        let transfer: Ics20Packet = from_slice(&packet.data)?;
+        let source_prefix = format!("{}/{}/", packet.src.port_id, packet.src.channel_id);
+        let denom = match transfer.denom.strip_prefix(&source_prefix) {
+            Some(denom) => denom.to_string(),
+            None => format!(
+                "{}/{}/{}",
+                packet.dest.port_id, packet.dest.channel_id, transfer.denom
+            ),
+        };
+        let amount = Coin {
+            denom,
+            amount: transfer.amount,
+        };
+        self.mint(&Addr::unchecked(transfer.receiver), &[amount]);
+        Ok(to_binary(&Acknowledgement::Result(Binary::from(&[1])))?)
+    }
+
+    fn refund_transfer(&mut self, packet: &IbcPacket) -> Result<(), SimError> {
+        let transfer: Ics20Packet = from_slice(&packet.data)?;
+        let amount = Coin {
+            denom: transfer.denom,
+            amount: transfer.amount,
+        };
+        self.mint(&Addr::unchecked(transfer.sender), &[amount]);
+        Ok(())
+    }
+
+    fn save_channel(&mut self, channel: IbcChannel, state: ChannelState) {
+        let next_sequence = self
+            .state
+            .channels
+            .get(&channel.endpoint.channel_id)
+            .map_or(1, |c| c.next_sequence);
+        self.state.channels.insert(
+            channel.endpoint.channel_id.clone(),
+            ChannelData {
+                channel,
+                state,
+                next_sequence,
+            },
+        );
+    }
+
+    fn open_channel_end(&self, channel_id: &str) -> Result<IbcChannel, SimError> {
+        self.state
+            .channels
+            .get(channel_id)
+            .filter(|c| c.state == ChannelState::Open)
+            .map(|c| c.channel.clone())
+            .ok_or_else(|| SimError::UnknownChannel(channel_id.to_string()))
+    }
+
+    fn contract_addr(&self, addr: &str) -> Result<Addr, SimError> {
+        let addr = Addr::unchecked(addr);
+        if !self.state.contracts.contains_key(&addr) {
+            return Err(SimError::UnknownContract(addr.into()));
+        }
+        Ok(addr)
+    }
+
+    fn check_admin(&self, sender: &Addr, contract: &Addr) -> Result<(), SimError> {
+        match self.state.contracts.get(contract) {
+            Some(ContractData {
+                admin: Some(admin), ..
+            }) if admin == sender => Ok(()),
+            _ => Err(SimError::Unauthorized {}),
+        }
+    }
+
+    fn send_tokens(&mut self, from: &Addr, to: &Addr, amount: &[Coin]) -> Result<(), SimError> {
+        self.burn(from, amount)?;
+        self.mint(to, amount);
+        Ok(())
+    }
+
+    fn burn(&mut self, from: &Addr, amount: &[Coin]) -> Result<(), SimError> {
+        let mut balance = self.state.balances.get(from).cloned().unwrap_or_default();
+        for c in amount.iter().filter(|c| !c.amount.is_zero()) {
+            balance = (balance - c.clone()).map_err(|_| SimError::InsufficientFunds {
+                address: from.to_string(),
+                amount: c.clone(),
+            })?;
+        }
+        self.state.balances.insert(from.clone(), balance);
+        Ok(())
+    }
+
+    fn mint(&mut self, to: &Addr, amount: &[Coin]) {
+        let balance = self.state.balances.entry(to.clone()).or_default();
+        for c in amount.iter().filter(|c| !c.amount.is_zero()) {
+            *balance += c.clone();
+        }
+    }
+}
+
+/// The acknowledgement ibc-go writes for the transfer module and for failed contract calls
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Acknowledgement {
+    Result(Binary),
+    Error(String),
+}
+
+fn relayer() -> Addr {
+    Addr::unchecked("relayer")
+}
+
+fn port_owner(port_id: &str) -> Option<Addr> {
+    port_id.strip_prefix("wasm.").map(Addr::unchecked)
+}
+
+// protobuf encoding of a length-delimited field
+fn encode_bytes(field: u8, value: &[u8], out: &mut Vec<u8>) {
+    out.push((field << 3) | 2);
+    let mut len = value.len();
+    while len >= 0x80 {
+        out.push((len as u8 & 0x7f) | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+    out.extend_from_slice(value);
+}
+
+/// Answers the queries of contracts with the current state of the chain
+struct ChainQuerier<'a> {
+    chain: &'a Chain,
+}
+
+impl Querier for ChainQuerier<'_> {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = match from_slice(bin_request) {
+            Ok(request) => request,
+            Err(e) => {
+                return SystemResult::Err(SystemError::InvalidRequest {
+                    error: format!("Parsing query request: {}", e),
+                    request: bin_request.into(),
+                })
+            }
+        };
+        let res = match request {
+            QueryRequest::Bank(BankQuery::Balance { address, denom }) => {
+                let amount = self.chain.balance(&Addr::unchecked(address), &denom);
+                to_binary(&BalanceResponse { amount }).map_err(SimError::from)
+            }
+            QueryRequest::Bank(BankQuery::AllBalances { address }) => {
+                let amount = self.chain.all_balances(&Addr::unchecked(address));
+                to_binary(&AllBalanceResponse { amount }).map_err(SimError::from)
+            }
+            QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) => self
+                .chain
+                .query_contract(&Addr::unchecked(contract_addr), &msg),
+            QueryRequest::Wasm(WasmQuery::Raw { contract_addr, key }) => {
+                match self
+                    .chain
+                    .state
+                    .contracts
+                    .get(&Addr::unchecked(&contract_addr))
+                {
+                    Some(data) => Ok(data.storage.get(&key).unwrap_or_default().into()),
+                    None => Err(SimError::UnknownContract(contract_addr)),
+                }
+            }
+            request => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: format!("{:?}", request),
+                })
+            }
+        };
+        SystemResult::Ok(res.map_err(|e| e.to_string()).into())
+    }
+}
@@ -0,0 +1,325 @@
+use std::fmt::Display;
+
+use cosmwasm_std::{
+    from_slice, Binary, Deps, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse,
+    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcPacketAckMsg,
+    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo, Reply, Response,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::SimError;
+
+/// The entry points of a contract as the chain calls them, with messages still serialized
+pub trait Contract {
+    fn instantiate(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: &[u8],
+    ) -> Result<Response, SimError>;
+
+    fn execute(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: &[u8],
+    ) -> Result<Response, SimError>;
+
+    fn query(&self, deps: Deps, env: Env, msg: &[u8]) -> Result<Binary, SimError>;
+
+    fn migrate(&self, deps: DepsMut, env: Env, msg: &[u8]) -> Result<Response, SimError>;
+
+    fn reply(&self, deps: DepsMut, env: Env, msg: Reply) -> Result<Response, SimError>;
+
+    fn ibc_channel_open(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcChannelOpenMsg,
+    ) -> Result<Option<Ibc3ChannelOpenResponse>, SimError>;
+
+    fn ibc_channel_connect(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcChannelConnectMsg,
+    ) -> Result<IbcBasicResponse, SimError>;
+
+    fn ibc_channel_close(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcChannelCloseMsg,
+    ) -> Result<IbcBasicResponse, SimError>;
+
+    fn ibc_packet_receive(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcPacketReceiveMsg,
+    ) -> Result<IbcReceiveResponse, SimError>;
+
+    fn ibc_packet_ack(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcPacketAckMsg,
+    ) -> Result<IbcBasicResponse, SimError>;
+
+    fn ibc_packet_timeout(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcPacketTimeoutMsg,
+    ) -> Result<IbcBasicResponse, SimError>;
+}
+
+/// Signature of the instantiate and execute entry points
+pub type MessageEntryPoint<T, E> = fn(DepsMut, Env, MessageInfo, T) -> Result<Response, E>;
+/// Signature of the query entry point
+pub type QueryEntryPoint<T, E> = fn(Deps, Env, T) -> Result<Binary, E>;
+/// Signature of the migrate entry point
+pub type MigrateEntryPoint<T, E> = fn(DepsMut, Env, T) -> Result<Response, E>;
+/// Signature of the reply and all ibc entry points
+pub type IbcEntryPoint<M, R, E> = fn(DepsMut, Env, M) -> Result<R, E>;
+
+type MessageFn = Box<dyn Fn(DepsMut, Env, MessageInfo, &[u8]) -> Result<Response, SimError>>;
+type QueryFn = Box<dyn Fn(Deps, Env, &[u8]) -> Result<Binary, SimError>>;
+type MigrateFn = Box<dyn Fn(DepsMut, Env, &[u8]) -> Result<Response, SimError>>;
+type IbcFn<M, R> = Box<dyn Fn(DepsMut, Env, M) -> Result<R, SimError>>;
+
+struct IbcEntryPoints {
+    channel_open: IbcFn<IbcChannelOpenMsg, Option<Ibc3ChannelOpenResponse>>,
+    channel_connect: IbcFn<IbcChannelConnectMsg, IbcBasicResponse>,
+    channel_close: IbcFn<IbcChannelCloseMsg, IbcBasicResponse>,
+    packet_receive: IbcFn<IbcPacketReceiveMsg, IbcReceiveResponse>,
+    packet_ack: IbcFn<IbcPacketAckMsg, IbcBasicResponse>,
+    packet_timeout: IbcFn<IbcPacketTimeoutMsg, IbcBasicResponse>,
+}
+
+/// Builds a `Contract` from the entry point functions of a contract crate.
+/// Only instantiate and query are required, calling any other missing entry point fails.
+pub struct ContractWrapper {
+    instantiate: MessageFn,
+    execute: Option<MessageFn>,
+    query: QueryFn,
+    migrate: Option<MigrateFn>,
+    reply: Option<IbcFn<Reply, Response>>,
+    ibc: Option<IbcEntryPoints>,
+}
+
+impl ContractWrapper {
+    pub fn new<I, Q, E1, E2>(
+        instantiate: MessageEntryPoint<I, E1>,
+        query: QueryEntryPoint<Q, E2>,
+    ) -> Self
+    where
+        I: DeserializeOwned + 'static,
+        Q: DeserializeOwned + 'static,
+        E1: Display + 'static,
+        E2: Display + 'static,
+    {
+        ContractWrapper {
+            instantiate: message_fn(instantiate),
+            execute: None,
+            query: Box::new(move |deps: Deps, env: Env, msg: &[u8]| {
+                query(deps, env, from_slice(msg)?).map_err(contract_error)
+            }),
+            migrate: None,
+            reply: None,
+            ibc: None,
+        }
+    }
+
+    pub fn with_execute<T, E>(mut self, execute: MessageEntryPoint<T, E>) -> Self
+    where
+        T: DeserializeOwned + 'static,
+        E: Display + 'static,
+    {
+        self.execute = Some(message_fn(execute));
+        self
+    }
+
+    pub fn with_migrate<T, E>(mut self, migrate: MigrateEntryPoint<T, E>) -> Self
+    where
+        T: DeserializeOwned + 'static,
+        E: Display + 'static,
+    {
+        self.migrate = Some(Box::new(move |deps: DepsMut, env: Env, msg: &[u8]| {
+            migrate(deps, env, from_slice(msg)?).map_err(contract_error)
+        }));
+        self
+    }
+
+    pub fn with_reply<E>(mut self, reply: IbcEntryPoint<Reply, Response, E>) -> Self
+    where
+        E: Display + 'static,
+    {
+        self.reply = Some(ibc_fn(reply));
+        self
+    }
+
+    pub fn with_ibc<E1, E2, E3, E4, E5, E6>(
+        mut self,
+        channel_open: IbcEntryPoint<IbcChannelOpenMsg, Option<Ibc3ChannelOpenResponse>, E1>,
+        channel_connect: IbcEntryPoint<IbcChannelConnectMsg, IbcBasicResponse, E2>,
+        channel_close: IbcEntryPoint<IbcChannelCloseMsg, IbcBasicResponse, E3>,
+        packet_receive: IbcEntryPoint<IbcPacketReceiveMsg, IbcReceiveResponse, E4>,
+        packet_ack: IbcEntryPoint<IbcPacketAckMsg, IbcBasicResponse, E5>,
+        packet_timeout: IbcEntryPoint<IbcPacketTimeoutMsg, IbcBasicResponse, E6>,
+    ) -> Self
+    where
+        E1: Display + 'static,
+        E2: Display + 'static,
+        E3: Display + 'static,
+        E4: Display + 'static,
+        E5: Display + 'static,
+        E6: Display + 'static,
+    {
+        self.ibc = Some(IbcEntryPoints {
+            channel_open: ibc_fn(channel_open),
+            channel_connect: ibc_fn(channel_connect),
+            channel_close: ibc_fn(channel_close),
+            packet_receive: ibc_fn(packet_receive),
+            packet_ack: ibc_fn(packet_ack),
+            packet_timeout: ibc_fn(packet_timeout),
+        });
+        self
+    }
+
+    fn ibc(&self) -> Result<&IbcEntryPoints, SimError> {
+        self.ibc
+            .as_ref()
+            .ok_or(SimError::MissingEntryPoint("ibc entry points"))
+    }
+}
+
+// chains report contract errors as strings
+fn contract_error<E: Display>(err: E) -> SimError {
+    SimError::Contract(err.to_string())
+}
+
+fn message_fn<T, E>(entry_point: MessageEntryPoint<T, E>) -> MessageFn
+where
+    T: DeserializeOwned + 'static,
+    E: Display + 'static,
+{
+    Box::new(
+        move |deps: DepsMut, env: Env, info: MessageInfo, msg: &[u8]| {
+            entry_point(deps, env, info, from_slice(msg)?).map_err(contract_error)
+        },
+    )
+}
+
+fn ibc_fn<M, R, E>(entry_point: IbcEntryPoint<M, R, E>) -> IbcFn<M, R>
+where
+    M: 'static,
+    R: 'static,
+    E: Display + 'static,
+{
+    Box::new(move |deps: DepsMut, env: Env, msg: M| {
+        entry_point(deps, env, msg).map_err(contract_error)
+    })
+}
+
+impl Contract for ContractWrapper {
+    fn instantiate(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: &[u8],
+    ) -> Result<Response, SimError> {
+        (self.instantiate)(deps, env, info, msg)
+    }
+
+    fn execute(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: &[u8],
+    ) -> Result<Response, SimError> {
+        let execute = self
+            .execute
+            .as_ref()
+            .ok_or(SimError::MissingEntryPoint("execute"))?;
+        execute(deps, env, info, msg)
+    }
+
+    fn query(&self, deps: Deps, env: Env, msg: &[u8]) -> Result<Binary, SimError> {
+        (self.query)(deps, env, msg)
+    }
+
+    fn migrate(&self, deps: DepsMut, env: Env, msg: &[u8]) -> Result<Response, SimError> {
+        let migrate = self
+            .migrate
+            .as_ref()
+            .ok_or(SimError::MissingEntryPoint("migrate"))?;
+        migrate(deps, env, msg)
+    }
+
+    fn reply(&self, deps: DepsMut, env: Env, msg: Reply) -> Result<Response, SimError> {
+        let reply = self
+            .reply
+            .as_ref()
+            .ok_or(SimError::MissingEntryPoint("reply"))?;
+        reply(deps, env, msg)
+    }
+
+    fn ibc_channel_open(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcChannelOpenMsg,
+    ) -> Result<Option<Ibc3ChannelOpenResponse>, SimError> {
+        (self.ibc()?.channel_open)(deps, env, msg)
+    }
+
+    fn ibc_channel_connect(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcChannelConnectMsg,
+    ) -> Result<IbcBasicResponse, SimError> {
+        (self.ibc()?.channel_connect)(deps, env, msg)
+    }
+
+    fn ibc_channel_close(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcChannelCloseMsg,
+    ) -> Result<IbcBasicResponse, SimError> {
+        (self.ibc()?.channel_close)(deps, env, msg)
+    }
+
+    fn ibc_packet_receive(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcPacketReceiveMsg,
+    ) -> Result<IbcReceiveResponse, SimError> {
+        (self.ibc()?.packet_receive)(deps, env, msg)
+    }
+
+    fn ibc_packet_ack(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcPacketAckMsg,
+    ) -> Result<IbcBasicResponse, SimError> {
+        (self.ibc()?.packet_ack)(deps, env, msg)
+    }
+
+    fn ibc_packet_timeout(
+        &self,
+        deps: DepsMut,
+        env: Env,
+        msg: IbcPacketTimeoutMsg,
+    ) -> Result<IbcBasicResponse, SimError> {
+        (self.ibc()?.packet_timeout)(deps, env, msg)
+    }
+}
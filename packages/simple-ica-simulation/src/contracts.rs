@@ -0,0 +1,64 @@
+use cosmwasm_std::{DepsMut, Empty, Env, Response, StdResult};
+
+use crate::contract::{Contract, ContractWrapper};
+
+pub fn simple_ica_controller() -> Box<dyn Contract> {
+    use simple_ica_controller::{contract, ibc};
+    let contract = ContractWrapper::new(contract::instantiate, contract::query)
+        .with_execute(contract::execute)
+        .with_migrate(contract::migrate)
+        .with_reply(contract::reply)
+        .with_ibc(
+            ibc::ibc_channel_open,
+            ibc::ibc_channel_connect,
+            ibc::ibc_channel_close,
+            ibc::ibc_packet_receive,
+            ibc::ibc_packet_ack,
+            ibc::ibc_packet_timeout,
+        );
+    Box::new(contract)
+}
+
+pub fn simple_ica_host() -> Box<dyn Contract> {
+    use simple_ica_host::contract;
+    let contract = ContractWrapper::new(contract::instantiate, contract::query)
+        .with_execute(contract::execute)
+        .with_migrate(contract::migrate)
+        .with_reply(contract::reply)
+        .with_ibc(
+            contract::ibc_channel_open,
+            contract::ibc_channel_connect,
+            contract::ibc_channel_close,
+            contract::ibc_packet_receive,
+            contract::ibc_packet_ack,
+            contract::ibc_packet_timeout,
+        );
+    Box::new(contract)
+}
+
+/// The reflect accounts created by the host
+pub fn cw1_whitelist() -> Box<dyn Contract> {
+    use cw1_whitelist::contract;
+    let contract = ContractWrapper::new(contract::instantiate, contract::query)
+        .with_execute(contract::execute);
+    Box::new(contract)
+}
+
+/// A later release of the reflect accounts, that they can be migrated to
+pub fn cw1_whitelist_upgrade() -> Box<dyn Contract> {
+    use cw1_whitelist::contract;
+    fn migrate(_deps: DepsMut, _env: Env, _msg: Empty) -> StdResult<Response> {
+        Ok(Response::new())
+    }
+    let contract = ContractWrapper::new(contract::instantiate, contract::query)
+        .with_execute(contract::execute)
+        .with_migrate(migrate);
+    Box::new(contract)
+}
+
+pub fn callback_capturer() -> Box<dyn Contract> {
+    use callback_capturer::contract;
+    let contract = ContractWrapper::new(contract::instantiate, contract::query)
+        .with_execute(contract::execute);
+    Box::new(contract)
+}
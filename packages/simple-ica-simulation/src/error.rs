@@ -0,0 +1,34 @@
+use thiserror::Error;
+
+use cosmwasm_std::{Coin, StdError};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SimError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    /// Any error returned by a contract, as the chain would report it
+    #[error("{0}")]
+    Contract(String),
+
+    #[error("Contract does not implement {0}")]
+    MissingEntryPoint(&'static str),
+
+    #[error("No contract at {0}")]
+    UnknownContract(String),
+
+    #[error("No code with id {0}")]
+    UnknownCode(u64),
+
+    #[error("Channel {0} is not open on this port")]
+    UnknownChannel(String),
+
+    #[error("{address} cannot pay {amount}")]
+    InsufficientFunds { address: String, amount: Coin },
+
+    #[error("Only the contract admin can do this")]
+    Unauthorized {},
+
+    #[error("Unsupported message: {0}")]
+    Unsupported(String),
+}
@@ -0,0 +1,913 @@
+//! Runs the simple-ica contracts on two mocked chains in one process, relaying packets
+//! between them, so the whole flow can be tested without real chains or a relayer.
+
+mod chain;
+mod contract;
+pub mod contracts;
+mod error;
+pub mod relayer;
+
+pub use crate::chain::{contract_port, AppResponse, Chain, TRANSFER_PORT, TRANSFER_VERSION};
+pub use crate::contract::{
+    Contract, ContractWrapper, IbcEntryPoint, MessageEntryPoint, MigrateEntryPoint, QueryEntryPoint,
+};
+pub use crate::error::SimError;
+pub use crate::relayer::{PacketOutcome, RelayedPacket};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relayer::{close_channel, create_channel, create_transfer_channel, relay_packets};
+
+    use cosmwasm_std::{
+        coin, coins, from_slice, to_binary, wasm_execute, Addr, BankMsg, Binary, CosmosMsg, Empty,
+        IbcOrder, WasmMsg,
+    };
+    use simple_ica::{
+        DispatchMode, DispatchResponse, ErrorCode, MessageOutcome, ProtocolVersion, StdAck,
+        WhoAmIResponse, APP_ORDER, IBC_APP_VERSION,
+    };
+    use simple_ica_controller::ibc::DEFAULT_PACKET_LIFETIME;
+    use simple_ica_controller::msg as controller_msg;
+    use simple_ica_controller::state::{AccessPolicy, ClosedBy, PacketStatus};
+    use simple_ica_host::backend::AccountBackend;
+    use simple_ica_host::msg as host_msg;
+
+    const ADMIN: &str = "admin";
+
+    struct Suite {
+        controller_chain: Chain,
+        host_chain: Chain,
+        controller: Addr,
+        host: Addr,
+        controller_channel: String,
+        host_channel: String,
+    }
+
+    impl Suite {
+        // relays the packets from the controller, and all acks back
+        fn relay(&mut self) -> Vec<RelayedPacket> {
+            relay_packets(&mut self.controller_chain, &mut self.host_chain).unwrap()
+        }
+
+        fn send_msgs(
+            &mut self,
+            sender: &str,
+            msgs: Vec<CosmosMsg>,
+        ) -> Result<AppResponse, SimError> {
+            let msg = controller_msg::ExecuteMsg::SendMsgs {
+                channel_id: self.controller_channel.clone(),
+                msgs,
+                callback_id: None,
+                timeout_seconds: None,
+                mode: None,
+            };
+            self.controller_chain
+                .execute(&Addr::unchecked(sender), &self.controller, &msg, &[])
+        }
+
+        fn remote_account(&self, sender: &str) -> Option<Addr> {
+            let msg = host_msg::QueryMsg::Account {
+                channel_id: self.host_channel.clone(),
+                sender: sender.into(),
+            };
+            let res: host_msg::AccountResponse = self.host_chain.query(&self.host, &msg).unwrap();
+            res.account.map(Addr::unchecked)
+        }
+
+        fn channel_settings(&self) -> controller_msg::ChannelSettingsResponse {
+            let msg = controller_msg::QueryMsg::ChannelSettings {
+                channel_id: self.controller_channel.clone(),
+            };
+            self.controller_chain.query(&self.controller, &msg).unwrap()
+        }
+
+        fn channel_account(&self) -> controller_msg::AccountResponse {
+            let msg = controller_msg::QueryMsg::Account {
+                channel_id: self.controller_channel.clone(),
+            };
+            self.controller_chain.query(&self.controller, &msg).unwrap()
+        }
+    }
+
+    // instantiates both contracts and connects them, the WhoAmI packet is not relayed yet
+    fn setup() -> Suite {
+        let admin = Addr::unchecked(ADMIN);
+
+        let mut controller_chain = Chain::new("controller-chain");
+        let controller_id = controller_chain.store_code(contracts::simple_ica_controller());
+        let msg = controller_msg::InstantiateMsg {
+            default_timeout: None,
+            access_policy: Some(AccessPolicy::Open),
+            callback_gas_limit: None,
+        };
+        let controller = controller_chain
+            .instantiate(
+                controller_id,
+                &admin,
+                &msg,
+                &[],
+                "controller",
+                Some(ADMIN.into()),
+            )
+            .unwrap();
+
+        let mut host_chain = Chain::new("host-chain");
+        let cw1_code_id = host_chain.store_code(contracts::cw1_whitelist());
+        let host_id = host_chain.store_code(contracts::simple_ica_host());
+        let msg = host_msg::InstantiateMsg {
+            cw1_code_id,
+            account_backend: None,
+        };
+        let host = host_chain
+            .instantiate(host_id, &admin, &msg, &[], "host", Some(ADMIN.into()))
+            .unwrap();
+
+        let (controller_channel, host_channel) = create_channel(
+            &mut controller_chain,
+            &contract_port(&controller),
+            &mut host_chain,
+            &contract_port(&host),
+            APP_ORDER,
+            IBC_APP_VERSION,
+        )
+        .unwrap();
+
+        Suite {
+            controller_chain,
+            host_chain,
+            controller,
+            host,
+            controller_channel,
+            host_channel,
+        }
+    }
+
+    fn parse_ack(outcome: &PacketOutcome) -> StdAck {
+        match outcome {
+            PacketOutcome::Acknowledged(ack) => from_slice(ack).unwrap(),
+            PacketOutcome::TimedOut => panic!("packet timed out"),
+        }
+    }
+
+    #[test]
+    fn connect_creates_admin_account() {
+        let mut suite = setup();
+        // nothing on the host until the WhoAmI arrives
+        assert_eq!(None, suite.remote_account(ADMIN));
+        assert_eq!(None, suite.channel_account().remote_addr);
+
+        let relayed = suite.relay();
+        assert_eq!(1, relayed.len());
+        let WhoAmIResponse { account } = parse_ack(&relayed[0].outcome).unwrap_into();
+
+        // the account was instantiated in the reply, and both sides know it
+        assert_eq!(Some(Addr::unchecked(&account)), suite.remote_account(ADMIN));
+        assert_eq!(Some(account), suite.channel_account().remote_addr);
+
+        // the controller recorded the packet outcome
+        let msg = controller_msg::QueryMsg::Packet {
+            channel_id: suite.controller_channel.clone(),
+            sequence: relayed[0].packet.sequence,
+        };
+        let packet: controller_msg::PacketResponse = suite
+            .controller_chain
+            .query(&suite.controller, &msg)
+            .unwrap();
+        assert_eq!(PacketStatus::Acknowledged, packet.status);
+    }
+
+    #[test]
+    fn channels_agree_on_newest_version() {
+        let mut suite = setup();
+        suite.relay();
+        // asking for v2 gets v2
+        assert_eq!(ProtocolVersion::V2, suite.channel_settings().version);
+
+        // without a version, both sides settle on the newest
+        let (controller_channel, host_channel) = create_channel(
+            &mut suite.controller_chain,
+            &contract_port(&suite.controller),
+            &mut suite.host_chain,
+            &contract_port(&suite.host),
+            APP_ORDER,
+            "",
+        )
+        .unwrap();
+        suite.controller_channel = controller_channel;
+        suite.host_channel = host_channel;
+        assert_eq!(ProtocolVersion::V3, suite.channel_settings().version);
+
+        // and the packets on it work as before
+        let relayed = suite.relay();
+        let WhoAmIResponse { account } = parse_ack(&relayed[0].outcome).unwrap_into();
+        assert_eq!(Some(Addr::unchecked(account)), suite.remote_account(ADMIN));
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        let relayed = suite.relay();
+        parse_ack(&relayed[0].outcome).try_into_dispatch().unwrap();
+    }
+
+    #[test]
+    fn ordered_channel_closes_on_timeout() {
+        let mut suite = setup();
+        let (controller_channel, host_channel) = create_channel(
+            &mut suite.controller_chain,
+            &contract_port(&suite.controller),
+            &mut suite.host_chain,
+            &contract_port(&suite.host),
+            IbcOrder::Ordered,
+            "",
+        )
+        .unwrap();
+        suite.controller_channel = controller_channel;
+        suite.host_channel = host_channel;
+        let settings = suite.channel_settings();
+        assert_eq!(ProtocolVersion::V3, settings.version);
+        assert_eq!(IbcOrder::Ordered, settings.order);
+        suite.relay();
+        let remote_addr = suite.channel_account().remote_addr;
+        assert!(remote_addr.is_some());
+
+        // packets run in the order they were sent
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        let relayed = suite.relay();
+        let sequences: Vec<_> = relayed.iter().map(|r| r.packet.sequence).collect();
+        assert_eq!(vec![2, 3], sequences);
+
+        // once one times out, the channel closes and nothing after it arrives
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        suite.host_chain.next_block(DEFAULT_PACKET_LIFETIME);
+        let relayed = suite.relay();
+        assert_eq!(2, relayed.len());
+        for packet in relayed {
+            assert_eq!(PacketOutcome::TimedOut, packet.outcome);
+        }
+        assert!(!suite
+            .controller_chain
+            .is_channel_open(&suite.controller_channel));
+        assert!(!suite.host_chain.is_channel_open(&suite.host_channel));
+
+        // the controller keeps the account with the closed ones
+        let msg = controller_msg::QueryMsg::ClosedAccount {
+            channel_id: suite.controller_channel.clone(),
+        };
+        let account: controller_msg::ClosedAccountResponse = suite
+            .controller_chain
+            .query(&suite.controller, &msg)
+            .unwrap();
+        assert_eq!(remote_addr, account.remote_addr);
+        assert_eq!(
+            suite.controller_chain.block_info().time,
+            account.closed_time
+        );
+        assert_eq!(ClosedBy::Timeout, account.closed_by);
+        let err = suite.send_msgs(ADMIN, vec![]).unwrap_err();
+        assert!(err.to_string().contains("is closed"), "{}", err);
+    }
+
+    #[test]
+    fn dispatch_per_sender() {
+        let mut suite = setup();
+        suite.relay();
+        let admin_account = suite.remote_account(ADMIN).unwrap();
+        suite
+            .host_chain
+            .set_balance(&admin_account, coins(1000, "uatom"));
+
+        // the admin spends from their account
+        let friend = Addr::unchecked("friend");
+        let send = |amount| -> CosmosMsg {
+            BankMsg::Send {
+                to_address: friend.to_string(),
+                amount: coins(amount, "uatom"),
+            }
+            .into()
+        };
+        suite.send_msgs(ADMIN, vec![send(300)]).unwrap();
+        let relayed = suite.relay();
+        let res: DispatchResponse = parse_ack(&relayed[0].outcome).unwrap_into();
+        assert_eq!(res.results, vec![Binary::default()]);
+        assert!(res.events[0].iter().any(|e| e.ty == "transfer"));
+        assert_eq!(
+            coin(300, "uatom"),
+            suite.host_chain.balance(&friend, "uatom")
+        );
+        assert_eq!(
+            coin(700, "uatom"),
+            suite.host_chain.balance(&admin_account, "uatom")
+        );
+
+        // another sender gets their own account on first dispatch
+        suite.send_msgs("alice", vec![]).unwrap();
+        let relayed = suite.relay();
+        parse_ack(&relayed[0].outcome).unwrap();
+        let alice_account = suite.remote_account("alice").unwrap();
+        assert_ne!(alice_account, admin_account);
+
+        // which cannot spend the admin's funds. The error is acked, and the account stays
+        suite.send_msgs("alice", vec![send(100)]).unwrap();
+        let relayed = suite.relay();
+        let err = parse_ack(&relayed[0].outcome).ica_error().unwrap();
+        assert_eq!(ErrorCode::DispatchFailed, err.code);
+        assert_eq!(Some(alice_account), suite.remote_account("alice"));
+        assert_eq!(
+            coin(300, "uatom"),
+            suite.host_chain.balance(&friend, "uatom")
+        );
+    }
+
+    #[test]
+    fn senders_register_their_account() {
+        let mut suite = setup();
+        suite.relay();
+        let alice = Addr::unchecked("alice");
+        let sender_account = |suite: &Suite| -> controller_msg::AccountResponse {
+            let msg = controller_msg::QueryMsg::SenderAccount {
+                channel_id: suite.controller_channel.clone(),
+                sender: alice.to_string(),
+            };
+            suite
+                .controller_chain
+                .query(&suite.controller, &msg)
+                .unwrap()
+        };
+        let check_balance = controller_msg::ExecuteMsg::CheckRemoteBalance {
+            channel_id: suite.controller_channel.clone(),
+            timeout_seconds: None,
+        };
+
+        // alice has no account yet, so the host cannot tell her balance
+        suite
+            .controller_chain
+            .execute(&alice, &suite.controller, &check_balance, &[])
+            .unwrap();
+        let relayed = suite.relay();
+        let err = parse_ack(&relayed[0].outcome).ica_error().unwrap();
+        assert_eq!(ErrorCode::UnregisteredAccount, err.code);
+
+        // registering creates it, and tells us where it is
+        let msg = controller_msg::ExecuteMsg::RegisterAccount {
+            channel_id: suite.controller_channel.clone(),
+            timeout_seconds: None,
+        };
+        suite
+            .controller_chain
+            .execute(&alice, &suite.controller, &msg, &[])
+            .unwrap();
+        suite.relay();
+        let account = suite.remote_account("alice").unwrap();
+        assert_eq!(
+            Some(account.to_string()),
+            sender_account(&suite).remote_addr
+        );
+
+        // so she can check her balance, without touching the admin's account
+        suite.host_chain.set_balance(&account, coins(500, "uatom"));
+        suite
+            .controller_chain
+            .execute(&alice, &suite.controller, &check_balance, &[])
+            .unwrap();
+        suite.relay();
+        assert_eq!(
+            vec![coin(500, "uatom")],
+            sender_account(&suite).remote_balance
+        );
+        assert_eq!(
+            suite.remote_account(ADMIN).map(String::from),
+            suite.channel_account().remote_addr
+        );
+    }
+
+    #[test]
+    fn callbacks_and_timeouts() {
+        let mut suite = setup();
+        suite.relay();
+
+        let capturer_id = suite
+            .controller_chain
+            .store_code(contracts::callback_capturer());
+        let msg = callback_capturer::msg::InstantiateMsg {
+            simple_ica_controller: suite.controller.to_string(),
+        };
+        let capturer = suite
+            .controller_chain
+            .instantiate(
+                capturer_id,
+                &Addr::unchecked(ADMIN),
+                &msg,
+                &[],
+                "capturer",
+                None,
+            )
+            .unwrap();
+        let send_msgs = |suite: &mut Suite, callback_id: &str| {
+            let msg = callback_capturer::msg::ExecuteMsg::SendMsgs {
+                channel_id: suite.controller_channel.clone(),
+                msgs: vec![],
+                callback_id: callback_id.into(),
+            };
+            suite
+                .controller_chain
+                .execute(&Addr::unchecked(ADMIN), &capturer, &msg, &[])
+                .unwrap();
+        };
+        let result = |suite: &Suite, id: &str| {
+            let msg = callback_capturer::msg::QueryMsg::Result { id: id.into() };
+            let res: callback_capturer::msg::ResultResponse =
+                suite.controller_chain.query(&capturer, &msg).unwrap();
+            res.result
+        };
+
+        // the ack is passed on to the capturer
+        send_msgs(&mut suite, "acked");
+        suite.relay();
+        let res: DispatchResponse = result(&suite, "acked").unwrap_into();
+        assert_eq!(DispatchResponse::default(), res);
+
+        // a packet that arrives too late times out, and the capturer hears of it
+        send_msgs(&mut suite, "late");
+        suite.host_chain.next_block(DEFAULT_PACKET_LIFETIME);
+        let relayed = suite.relay();
+        assert_eq!(PacketOutcome::TimedOut, relayed[0].outcome);
+        assert_eq!(StdAck::Timeout {}, result(&suite, "late"));
+    }
+
+    #[test]
+    fn dispatch_reports_each_message() {
+        let mut suite = setup();
+        suite.relay();
+        let admin_account = suite.remote_account(ADMIN).unwrap();
+        suite
+            .host_chain
+            .set_balance(&admin_account, coins(1000, "uatom"));
+
+        // instantiate a contract remotely, and pay someone
+        let msg = host_msg::QueryMsg::Config {};
+        let cfg: host_msg::ConfigResponse = suite.host_chain.query(&suite.host, &msg).unwrap();
+        let init_msg = cw1_whitelist::msg::InstantiateMsg {
+            admins: vec![admin_account.to_string()],
+            mutable: true,
+        };
+        let instantiate: CosmosMsg = WasmMsg::Instantiate {
+            admin: None,
+            code_id: cfg.cw1_code_id,
+            msg: to_binary(&init_msg).unwrap(),
+            funds: vec![],
+            label: "remote".into(),
+        }
+        .into();
+        let pay: CosmosMsg = BankMsg::Send {
+            to_address: "friend".into(),
+            amount: coins(100, "uatom"),
+        }
+        .into();
+        suite.send_msgs(ADMIN, vec![instantiate, pay]).unwrap();
+        let relayed = suite.relay();
+        let res: DispatchResponse = parse_ack(&relayed[0].outcome).unwrap_into();
+
+        // there is one result per message
+        assert_eq!(2, res.results.len());
+        assert_eq!(2, res.events.len());
+        // and the new address can be read from the events of the first
+        let contract = res.events[0]
+            .iter()
+            .find(|e| e.ty == "instantiate")
+            .and_then(|e| e.attributes.iter().find(|a| a.key == "_contract_address"))
+            .map(|a| Addr::unchecked(&a.value))
+            .unwrap();
+        let msg = cw1_whitelist::msg::QueryMsg::<Empty>::AdminList {};
+        let admins: cw1_whitelist::msg::AdminListResponse =
+            suite.host_chain.query(&contract, &msg).unwrap();
+        assert_eq!(vec![admin_account.to_string()], admins.admins);
+        // the payment shows up in the second
+        assert!(res.events[1].iter().any(|e| e.ty == "transfer"));
+        assert!(!res.events[1].iter().any(|e| e.ty == "instantiate"));
+    }
+
+    #[test]
+    fn partial_dispatch_pays_who_it_can() {
+        let mut suite = setup();
+        suite.relay();
+        let account = suite.remote_account(ADMIN).unwrap();
+        suite.host_chain.set_balance(&account, coins(1000, "uatom"));
+        let pay = |to: &str, amount| -> CosmosMsg {
+            BankMsg::Send {
+                to_address: to.into(),
+                amount: coins(amount, "uatom"),
+            }
+            .into()
+        };
+        let payouts = vec![pay("alice", 300), pay("bob", 5000), pay("carol", 200)];
+        let send = |suite: &mut Suite, mode| {
+            let msg = controller_msg::ExecuteMsg::SendMsgs {
+                channel_id: suite.controller_channel.clone(),
+                msgs: payouts.clone(),
+                callback_id: None,
+                timeout_seconds: None,
+                mode: Some(mode),
+            };
+            suite
+                .controller_chain
+                .execute(&Addr::unchecked(ADMIN), &suite.controller, &msg, &[])
+                .unwrap();
+            let relayed = suite.relay();
+            parse_ack(&relayed[0].outcome)
+        };
+
+        // stopping at the first failure pays alice only
+        let res: DispatchResponse = send(&mut suite, DispatchMode::StopOnError).unwrap_into();
+        assert!(matches!(res.outcomes[1], MessageOutcome::Error(_)));
+        assert_eq!(MessageOutcome::Skipped {}, res.outcomes[2]);
+        assert_eq!(
+            coin(300, "uatom"),
+            suite.host_chain.balance(&Addr::unchecked("alice"), "uatom")
+        );
+        assert_eq!(
+            coin(0, "uatom"),
+            suite.host_chain.balance(&Addr::unchecked("carol"), "uatom")
+        );
+
+        // best effort pays carol as well
+        let res: DispatchResponse = send(&mut suite, DispatchMode::BestEffort).unwrap_into();
+        assert_eq!(MessageOutcome::Success {}, res.outcomes[0]);
+        assert!(matches!(res.outcomes[1], MessageOutcome::Error(_)));
+        assert_eq!(MessageOutcome::Success {}, res.outcomes[2]);
+        assert_eq!(
+            coin(200, "uatom"),
+            suite.host_chain.balance(&Addr::unchecked("carol"), "uatom")
+        );
+        assert_eq!(
+            coin(200, "uatom"),
+            suite.host_chain.balance(&account, "uatom")
+        );
+
+        // while an atomic dispatch pays nobody
+        let ack = send(&mut suite, DispatchMode::Atomic);
+        assert_eq!(ErrorCode::DispatchFailed, ack.ica_error().unwrap().code);
+        assert_eq!(
+            coin(200, "uatom"),
+            suite.host_chain.balance(&account, "uatom")
+        );
+    }
+
+    #[test]
+    fn funds_and_balances() {
+        let mut suite = setup();
+        suite.relay();
+        let (transfer_channel, host_transfer_channel) =
+            create_transfer_channel(&mut suite.controller_chain, &mut suite.host_chain).unwrap();
+
+        // the admin sends tokens to their remote account over ics20
+        let sender = Addr::unchecked(ADMIN);
+        suite
+            .controller_chain
+            .set_balance(&sender, coins(500, "ucosm"));
+        let msg = controller_msg::ExecuteMsg::SendFunds {
+            ica_channel_id: suite.controller_channel.clone(),
+            transfer_channel_id: transfer_channel,
+            timeout_seconds: None,
+        };
+        suite
+            .controller_chain
+            .execute(&sender, &suite.controller, &msg, &coins(200, "ucosm"))
+            .unwrap();
+        let relayed = suite.relay();
+        assert_eq!(1, relayed.len());
+        assert_eq!(
+            coin(300, "ucosm"),
+            suite.controller_chain.balance(&sender, "ucosm")
+        );
+
+        // the controller picks up the new balance
+        let msg = controller_msg::ExecuteMsg::CheckRemoteBalance {
+            channel_id: suite.controller_channel.clone(),
+            timeout_seconds: None,
+        };
+        suite
+            .controller_chain
+            .execute(&Addr::unchecked(ADMIN), &suite.controller, &msg, &[])
+            .unwrap();
+        suite.relay();
+        let denom = format!("transfer/{}/ucosm", host_transfer_channel);
+        assert_eq!(
+            suite.channel_account().remote_balance,
+            vec![coin(200, denom)]
+        );
+    }
+
+    #[test]
+    fn closing_channel_rescues_funds() {
+        let mut suite = setup();
+        suite.relay();
+        let account = suite.remote_account(ADMIN).unwrap();
+        suite.host_chain.set_balance(&account, coins(1000, "uatom"));
+
+        // a packet still in flight when the channel closes times out
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        let controller_channel = suite.controller_channel.clone();
+        close_channel(
+            &mut suite.controller_chain,
+            &controller_channel,
+            &mut suite.host_chain,
+        )
+        .unwrap();
+        let relayed = suite.relay();
+        assert_eq!(PacketOutcome::TimedOut, relayed[0].outcome);
+
+        // the host took the funds and forgot the account
+        assert_eq!(None, suite.remote_account(ADMIN));
+        assert_eq!(
+            coin(1000, "uatom"),
+            suite.host_chain.balance(&suite.host, "uatom")
+        );
+        assert_eq!(
+            Vec::<cosmwasm_std::Coin>::new(),
+            suite.host_chain.all_balances(&account)
+        );
+
+        // and nothing more can be sent
+        let err = suite.send_msgs(ADMIN, vec![]).unwrap_err();
+        assert!(matches!(err, SimError::Contract(_)));
+    }
+
+    #[test]
+    fn rescued_funds_come_back_with_the_account() {
+        let mut suite = setup();
+        suite.relay();
+        let account = suite.remote_account(ADMIN).unwrap();
+        suite.host_chain.set_balance(&account, coins(1000, "uatom"));
+        let controller_channel = suite.controller_channel.clone();
+        let closed_channel = suite.host_channel.clone();
+        close_channel(
+            &mut suite.controller_chain,
+            &controller_channel,
+            &mut suite.host_chain,
+        )
+        .unwrap();
+
+        // the host keeps the funds for the sender
+        let msg = host_msg::QueryMsg::ListRescuedFunds {};
+        let res: host_msg::ListRescuedFundsResponse =
+            suite.host_chain.query(&suite.host, &msg).unwrap();
+        assert_eq!(1, res.funds.len());
+        assert_eq!(ADMIN, res.funds[0].sender);
+        assert_eq!(coins(1000, "uatom"), res.funds[0].balance);
+
+        // a new channel between the same contracts takes over the account
+        let (controller_channel, host_channel) = create_channel(
+            &mut suite.controller_chain,
+            &contract_port(&suite.controller),
+            &mut suite.host_chain,
+            &contract_port(&suite.host),
+            APP_ORDER,
+            IBC_APP_VERSION,
+        )
+        .unwrap();
+        suite.controller_channel = controller_channel;
+        suite.host_channel = host_channel;
+        suite.relay();
+        assert_eq!(account, suite.remote_account(ADMIN).unwrap());
+        assert_eq!(
+            Some(account.to_string()),
+            suite.channel_account().remote_addr
+        );
+
+        // and sends the funds back to it
+        assert_eq!(
+            coin(1000, "uatom"),
+            suite.host_chain.balance(&account, "uatom")
+        );
+        assert_eq!(
+            coin(0, "uatom"),
+            suite.host_chain.balance(&suite.host, "uatom")
+        );
+        let msg = host_msg::QueryMsg::ListRescuedFunds {};
+        let res: host_msg::ListRescuedFundsResponse =
+            suite.host_chain.query(&suite.host, &msg).unwrap();
+        assert_eq!(0, res.funds.len());
+
+        // so there is nothing left to reclaim
+        let msg = controller_msg::ExecuteMsg::ReclaimFunds {
+            channel_id: suite.controller_channel.clone(),
+            closed_channel_id: closed_channel,
+            timeout_seconds: None,
+        };
+        suite
+            .controller_chain
+            .execute(&Addr::unchecked(ADMIN), &suite.controller, &msg, &[])
+            .unwrap();
+        let relayed = suite.relay();
+        let err = parse_ack(&relayed[0].outcome).ica_error().unwrap();
+        assert_eq!(ErrorCode::Other, err.code);
+    }
+
+    #[test]
+    fn accounts_can_be_migrated() {
+        let mut suite = setup();
+        suite.relay();
+        let account = suite.remote_account(ADMIN).unwrap();
+        let admin = Addr::unchecked(ADMIN);
+        let upgrade_id = suite
+            .host_chain
+            .store_code(contracts::cw1_whitelist_upgrade());
+        let migrate = controller_msg::ExecuteMsg::MigrateAccount {
+            channel_id: suite.controller_channel.clone(),
+            code_id: upgrade_id,
+            msg: to_binary(&Empty {}).unwrap(),
+            timeout_seconds: None,
+        };
+
+        // the host admin must allow the code first
+        suite
+            .controller_chain
+            .execute(&admin, &suite.controller, &migrate, &[])
+            .unwrap();
+        let relayed = suite.relay();
+        let err = parse_ack(&relayed[0].outcome).ica_error().unwrap();
+        assert_eq!(ErrorCode::Unauthorized, err.code);
+
+        let msg = host_msg::ExecuteMsg::AllowAccountCode {
+            code_id: upgrade_id,
+            account_backend: AccountBackend::Cw1Whitelist,
+        };
+        suite
+            .host_chain
+            .execute(&admin, &suite.host, &msg, &[])
+            .unwrap();
+        suite
+            .controller_chain
+            .execute(&admin, &suite.controller, &migrate, &[])
+            .unwrap();
+        let relayed = suite.relay();
+        let res: simple_ica::MigrateAccountResponse = parse_ack(&relayed[0].outcome).unwrap_into();
+        assert_eq!(account.as_str(), res.account);
+        assert_eq!(upgrade_id, res.code_id);
+
+        // the admin may migrate them all, and the account still works afterwards
+        let msg = host_msg::ExecuteMsg::MigrateAccounts {
+            code_id: upgrade_id,
+            msg: to_binary(&Empty {}).unwrap(),
+            channel_id: None,
+        };
+        suite
+            .host_chain
+            .execute(&admin, &suite.host, &msg, &[])
+            .unwrap();
+        suite.host_chain.set_balance(&account, coins(100, "uatom"));
+        let send = BankMsg::Send {
+            to_address: "friend".into(),
+            amount: coins(100, "uatom"),
+        };
+        suite.send_msgs(ADMIN, vec![send.into()]).unwrap();
+        let relayed = suite.relay();
+        parse_ack(&relayed[0].outcome).unwrap();
+        assert_eq!(
+            coin(100, "uatom"),
+            suite
+                .host_chain
+                .balance(&Addr::unchecked("friend"), "uatom")
+        );
+    }
+
+    #[test]
+    fn host_admin_closes_channel() {
+        let mut suite = setup();
+        suite.relay();
+        let account = suite.remote_account(ADMIN).unwrap();
+        suite.host_chain.set_balance(&account, coins(500, "uatom"));
+
+        let msg = host_msg::ExecuteMsg::CloseChannel {
+            channel_id: suite.host_channel.clone(),
+        };
+        suite
+            .host_chain
+            .execute(&Addr::unchecked(ADMIN), &suite.host, &msg, &[])
+            .unwrap();
+        relay_packets(&mut suite.host_chain, &mut suite.controller_chain).unwrap();
+
+        // both ends are closed, and the funds are rescued
+        assert!(!suite.host_chain.is_channel_open(&suite.host_channel));
+        assert!(!suite
+            .controller_chain
+            .is_channel_open(&suite.controller_channel));
+        assert_eq!(None, suite.remote_account(ADMIN));
+        assert_eq!(
+            coin(500, "uatom"),
+            suite.host_chain.balance(&suite.host, "uatom")
+        );
+    }
+
+    #[test]
+    fn upgrade_keeps_accounts() {
+        let mut suite = setup();
+        suite.relay();
+        let account = suite.remote_account(ADMIN).unwrap();
+        let admin = Addr::unchecked(ADMIN);
+
+        // move both sides to freshly stored code
+        let controller_id = suite
+            .controller_chain
+            .store_code(contracts::simple_ica_controller());
+        suite
+            .controller_chain
+            .migrate(
+                &admin,
+                &suite.controller,
+                controller_id,
+                &controller_msg::MigrateMsg {},
+            )
+            .unwrap();
+        let host_id = suite.host_chain.store_code(contracts::simple_ica_host());
+        let msg = host_msg::MigrateMsg {
+            admin: None,
+            legacy_senders: vec![],
+        };
+        suite
+            .host_chain
+            .migrate(&admin, &suite.host, host_id, &msg)
+            .unwrap();
+
+        // the channel keeps working with the same account
+        suite.send_msgs(ADMIN, vec![]).unwrap();
+        let relayed = suite.relay();
+        parse_ack(&relayed[0].outcome).unwrap();
+        assert_eq!(Some(account.clone()), suite.remote_account(ADMIN));
+        assert_eq!(
+            Some(account.to_string()),
+            suite.channel_account().remote_addr
+        );
+    }
+
+    #[test]
+    fn failed_callbacks_can_be_retried() {
+        let mut suite = setup();
+        suite.relay();
+        let admin = Addr::unchecked(ADMIN);
+
+        // a cw1 contract does not understand callbacks
+        let cw1_id = suite
+            .controller_chain
+            .store_code(contracts::cw1_whitelist());
+        let msg = cw1_whitelist::msg::InstantiateMsg {
+            admins: vec![ADMIN.into()],
+            mutable: false,
+        };
+        let proxy = suite
+            .controller_chain
+            .instantiate(cw1_id, &admin, &msg, &[], "proxy", None)
+            .unwrap();
+        let via_proxy = |suite: &mut Suite, msg: &controller_msg::ExecuteMsg| {
+            let msg: CosmosMsg = wasm_execute(&suite.controller, msg, vec![]).unwrap().into();
+            let msg = cw1_whitelist::msg::ExecuteMsg::Execute { msgs: vec![msg] };
+            suite.controller_chain.execute(&admin, &proxy, &msg, &[])
+        };
+        let failed_callbacks = |suite: &Suite| {
+            let msg = controller_msg::QueryMsg::FailedCallbacks {
+                receiver: proxy.to_string(),
+                start_after: None,
+                limit: None,
+            };
+            let res: controller_msg::FailedCallbacksResponse = suite
+                .controller_chain
+                .query(&suite.controller, &msg)
+                .unwrap();
+            res.callbacks
+        };
+
+        let msg = controller_msg::ExecuteMsg::SendMsgs {
+            channel_id: suite.controller_channel.clone(),
+            msgs: vec![],
+            callback_id: Some("lost".into()),
+            timeout_seconds: None,
+            mode: None,
+        };
+        via_proxy(&mut suite, &msg).unwrap();
+
+        // the ack still goes through, and the callback is queued
+        let relayed = suite.relay();
+        parse_ack(&relayed[0].outcome).unwrap();
+        let msg = controller_msg::QueryMsg::Packet {
+            channel_id: suite.controller_channel.clone(),
+            sequence: relayed[0].packet.sequence,
+        };
+        let packet: controller_msg::PacketResponse = suite
+            .controller_chain
+            .query(&suite.controller, &msg)
+            .unwrap();
+        assert_eq!(PacketStatus::Acknowledged, packet.status);
+        let callbacks = failed_callbacks(&suite);
+        assert_eq!(1, callbacks.len());
+        assert_eq!("lost", callbacks[0].callback_id);
+
+        // retrying fails the same way, and keeps it queued
+        let retry = |id, drop| controller_msg::ExecuteMsg::RetryCallback { id, drop };
+        via_proxy(&mut suite, &retry(callbacks[0].id, false)).unwrap_err();
+        assert_eq!(callbacks, failed_callbacks(&suite));
+
+        // so it is dropped
+        via_proxy(&mut suite, &retry(callbacks[0].id, true)).unwrap();
+        assert_eq!(0, failed_callbacks(&suite).len());
+    }
+}
@@ -0,0 +1,133 @@
+use cosmwasm_std::{
+    Binary, IbcChannel, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcOrder, IbcPacket,
+};
+
+use crate::chain::{Chain, Outgoing, TRANSFER_PORT, TRANSFER_VERSION};
+use crate::error::SimError;
+
+const CONNECTION_ID: &str = "connection-0";
+
+/// What became of a relayed packet
+#[derive(Clone, Debug, PartialEq)]
+pub enum PacketOutcome {
+    /// Received on the other side, with the acknowledgement it wrote
+    Acknowledged(Binary),
+    /// Expired or the channel closed before it could be received
+    TimedOut,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelayedPacket {
+    pub packet: IbcPacket,
+    pub outcome: PacketOutcome,
+}
+
+/// Runs the four step handshake between two ports, starting on `a`.
+/// Returns the channel ids on `a` and `b`.
+pub fn create_channel(
+    a: &mut Chain,
+    port_a: &str,
+    b: &mut Chain,
+    port_b: &str,
+    order: IbcOrder,
+    version: &str,
+) -> Result<(String, String), SimError> {
+    let endpoint_a = IbcEndpoint {
+        port_id: port_a.to_string(),
+        channel_id: a.next_channel_id(),
+    };
+    let endpoint_b = IbcEndpoint {
+        port_id: port_b.to_string(),
+        channel_id: b.next_channel_id(),
+    };
+
+    // the counterparty channel is not known on init
+    let counterparty = IbcEndpoint {
+        port_id: port_b.to_string(),
+        channel_id: "".to_string(),
+    };
+    let init = IbcChannel::new(
+        endpoint_a.clone(),
+        counterparty,
+        order.clone(),
+        version,
+        CONNECTION_ID,
+    );
+    let version_a = a.open_channel(IbcChannelOpenMsg::new_init(init))?;
+
+    let try_channel = IbcChannel::new(
+        endpoint_b.clone(),
+        endpoint_a.clone(),
+        order.clone(),
+        version_a.clone(),
+        CONNECTION_ID,
+    );
+    let version_b = b.open_channel(IbcChannelOpenMsg::new_try(try_channel, version_a))?;
+
+    let ack = IbcChannel::new(
+        endpoint_a.clone(),
+        endpoint_b.clone(),
+        order.clone(),
+        version_b.clone(),
+        CONNECTION_ID,
+    );
+    a.connect_channel(IbcChannelConnectMsg::new_ack(ack, version_b.clone()))?;
+
+    let confirm = IbcChannel::new(
+        endpoint_b.clone(),
+        endpoint_a.clone(),
+        order,
+        version_b,
+        CONNECTION_ID,
+    );
+    b.connect_channel(IbcChannelConnectMsg::new_confirm(confirm))?;
+
+    Ok((endpoint_a.channel_id, endpoint_b.channel_id))
+}
+
+/// Creates a channel between the transfer modules of both chains
+pub fn create_transfer_channel(a: &mut Chain, b: &mut Chain) -> Result<(String, String), SimError> {
+    create_channel(
+        a,
+        TRANSFER_PORT,
+        b,
+        TRANSFER_PORT,
+        IbcOrder::Unordered,
+        TRANSFER_VERSION,
+    )
+}
+
+/// Closes the channel on `a`, then on its counterparty on `b`
+pub fn close_channel(a: &mut Chain, channel_id: &str, b: &mut Chain) -> Result<(), SimError> {
+    let counterparty = a.close_channel_init(channel_id)?;
+    b.close_channel_confirm(&counterparty.channel_id)?;
+    Ok(())
+}
+
+/// Delivers everything `src` sent to `dst` and brings back the acknowledgements, or times
+/// the packets out on `src` if `dst` can no longer receive them. Packets sent while handling
+/// the acknowledgements are relayed as well. A packet that fails is dropped, the rest stay queued.
+pub fn relay_packets(src: &mut Chain, dst: &mut Chain) -> Result<Vec<RelayedPacket>, SimError> {
+    let mut relayed = vec![];
+    while let Some(outgoing) = src.pop_outgoing() {
+        match outgoing {
+            Outgoing::Packet(packet) => {
+                let outcome = if dst.packet_expired(&packet)
+                    || !dst.is_channel_open(&packet.dest.channel_id)
+                {
+                    src.timeout_packet(packet.clone())?;
+                    PacketOutcome::TimedOut
+                } else {
+                    let ack = dst.receive_packet(packet.clone())?;
+                    src.acknowledge_packet(packet.clone(), ack.clone())?;
+                    PacketOutcome::Acknowledged(ack)
+                };
+                relayed.push(RelayedPacket { packet, outcome });
+            }
+            Outgoing::Close(counterparty) => {
+                dst.close_channel_confirm(&counterparty.channel_id)?;
+            }
+        }
+    }
+    Ok(relayed)
+}
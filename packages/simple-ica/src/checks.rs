@@ -1,29 +1,236 @@
 pub use crate::{APP_ORDER, IBC_APP_VERSION};
-use cosmwasm_std::IbcOrder;
+use cosmwasm_std::{IbcChannelConnectMsg, IbcChannelOpenMsg, IbcOrder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
 pub enum SimpleIcaError {
-    #[error("Only supports unordered channels")]
-    InvalidChannelOrder,
+    #[error("Ordered channels require {0}")]
+    InvalidChannelOrder(String),
 
-    #[error("Counterparty version must be '{0}'")]
-    InvalidChannelVersion(&'static str),
+    #[error("Counterparty version must be one of '{0}'")]
+    InvalidChannelVersion(String),
+
+    #[error("Channels on connection {0} are not allowed")]
+    ConnectionNotAllowed(String),
+
+    #[error("Counterparty port {0} is not allowed")]
+    PortNotAllowed(String),
+
+    #[error("Cannot migrate from contract {0}")]
+    WrongContract(String),
+
+    #[error("Cannot migrate from version {previous} down to {current}")]
+    CannotDowngrade { previous: String, current: String },
+
+    #[error("Invalid contract version: {0}")]
+    SemVer(String),
 }
 
-pub fn check_order(order: &IbcOrder) -> Result<(), SimpleIcaError> {
-    if order != &APP_ORDER {
-        Err(SimpleIcaError::InvalidChannelOrder)
-    } else {
-        Ok(())
+impl From<semver::Error> for SimpleIcaError {
+    fn from(err: semver::Error) -> Self {
+        SimpleIcaError::SemVer(err.to_string())
     }
 }
 
-pub fn check_version(version: &str) -> Result<(), SimpleIcaError> {
-    if version != IBC_APP_VERSION {
-        Err(SimpleIcaError::InvalidChannelVersion(IBC_APP_VERSION))
-    } else {
+/// The versions of the packet protocol. Both sides agree on one when opening a channel
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+pub enum ProtocolVersion {
+    #[serde(rename = "simple-ica-v2")]
+    V2,
+    #[serde(rename = "simple-ica-v3")]
+    V3,
+}
+
+/// All versions we speak, from the newest
+pub const SUPPORTED_VERSIONS: [ProtocolVersion; 2] = [ProtocolVersion::V3, ProtocolVersion::V2];
+
+impl ProtocolVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V2 => IBC_APP_VERSION,
+            ProtocolVersion::V3 => "simple-ica-v3",
+        }
+    }
+
+    pub fn parse(version: &str) -> Option<Self> {
+        SUPPORTED_VERSIONS
+            .iter()
+            .find(|v| v.as_str() == version)
+            .copied()
+    }
+
+    /// simple-ica-v2 only runs on unordered channels, later versions support both
+    pub fn supports_order(&self, order: &IbcOrder) -> bool {
+        match self {
+            ProtocolVersion::V2 => order == &APP_ORDER,
+            ProtocolVersion::V3 => true,
+        }
+    }
+}
+
+/// What both sides agreed on when the channel connected
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelSettings {
+    pub version: ProtocolVersion,
+    pub order: IbcOrder,
+}
+
+/// Channels opened before we negotiated anything are unordered and speak simple-ica-v2
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            version: ProtocolVersion::V2,
+            order: APP_ORDER,
+        }
+    }
+}
+
+fn supported_versions() -> String {
+    versions_for(&APP_ORDER)
+}
+
+/// All versions we speak on a channel with this order, comma separated
+fn versions_for(order: &IbcOrder) -> String {
+    let versions: Vec<_> = SUPPORTED_VERSIONS
+        .iter()
+        .filter(|v| v.supports_order(order))
+        .map(|v| v.as_str())
+        .collect();
+    versions.join(",")
+}
+
+/// Fails if this version cannot run on a channel with this order
+pub fn check_order(order: &IbcOrder, version: ProtocolVersion) -> Result<(), SimpleIcaError> {
+    if version.supports_order(order) {
         Ok(())
+    } else {
+        Err(SimpleIcaError::InvalidChannelOrder(versions_for(order)))
+    }
+}
+
+/// Fails unless this is exactly one version we support
+pub fn check_version(version: &str) -> Result<ProtocolVersion, SimpleIcaError> {
+    ProtocolVersion::parse(version)
+        .ok_or_else(|| SimpleIcaError::InvalidChannelVersion(supported_versions()))
+}
+
+/// Picks the newest version we support from a comma separated proposal
+pub fn negotiate_version(proposal: &str) -> Result<ProtocolVersion, SimpleIcaError> {
+    proposal
+        .split(',')
+        .filter_map(|v| ProtocolVersion::parse(v.trim()))
+        .max()
+        .ok_or_else(|| SimpleIcaError::InvalidChannelVersion(supported_versions()))
+}
+
+/// The version to answer a channel open with.
+/// On init we propose what was asked for, or all versions we support on this order if
+/// nothing was. Proposing several lets the counterparty pick, a single one works with
+/// older contracts that only accept their own. On try we pick the newest version we share.
+pub fn open_version(msg: &IbcChannelOpenMsg) -> Result<String, SimpleIcaError> {
+    let channel = msg.channel();
+    match msg.counterparty_version() {
+        Some(proposal) => {
+            let version = negotiate_version(proposal)?;
+            check_order(&channel.order, version)?;
+            Ok(version.as_str().to_string())
+        }
+        None if channel.version.is_empty() => Ok(versions_for(&channel.order)),
+        None => {
+            check_order(&channel.order, negotiate_version(&channel.version)?)?;
+            Ok(channel.version.clone())
+        }
+    }
+}
+
+/// The version and order both sides agreed on, once the channel connects
+pub fn connect_settings(msg: &IbcChannelConnectMsg) -> Result<ChannelSettings, SimpleIcaError> {
+    let channel = msg.channel();
+    // on ack, the channel still has our proposal, the counterparty tells what it picked
+    let version = check_version(msg.counterparty_version().unwrap_or(&channel.version))?;
+    check_order(&channel.order, version)?;
+    Ok(ChannelSettings {
+        version,
+        order: channel.order.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BAD_APP_ORDER;
+    use cosmwasm_std::testing::{
+        mock_ibc_channel_connect_ack, mock_ibc_channel_open_init, mock_ibc_channel_open_try,
+    };
+
+    #[test]
+    fn negotiate_picks_newest() {
+        assert_eq!(
+            ProtocolVersion::V3,
+            negotiate_version("simple-ica-v2, simple-ica-v3").unwrap()
+        );
+        assert_eq!(
+            ProtocolVersion::V2,
+            negotiate_version("simple-ica-v1,simple-ica-v2").unwrap()
+        );
+        let err = negotiate_version("simple-ica-v1,ics20-1").unwrap_err();
+        assert_eq!(
+            SimpleIcaError::InvalidChannelVersion("simple-ica-v3,simple-ica-v2".to_string()),
+            err
+        );
+    }
+
+    #[test]
+    fn open_version_on_init() {
+        // nothing asked, we propose all we speak on this order
+        let msg = mock_ibc_channel_open_init("channel-1", IbcOrder::Unordered, "");
+        assert_eq!("simple-ica-v3,simple-ica-v2", open_version(&msg).unwrap());
+        let msg = mock_ibc_channel_open_init("channel-1", IbcOrder::Ordered, "");
+        assert_eq!("simple-ica-v3", open_version(&msg).unwrap());
+
+        // what was asked for is kept, if it runs on the order
+        let msg = mock_ibc_channel_open_init("channel-1", IbcOrder::Unordered, IBC_APP_VERSION);
+        assert_eq!(IBC_APP_VERSION, open_version(&msg).unwrap());
+        let msg = mock_ibc_channel_open_init("channel-1", BAD_APP_ORDER, IBC_APP_VERSION);
+        let err = open_version(&msg).unwrap_err();
+        assert_eq!(
+            SimpleIcaError::InvalidChannelOrder("simple-ica-v3".to_string()),
+            err
+        );
+    }
+
+    #[test]
+    fn open_version_on_try() {
+        let msg = mock_ibc_channel_open_try(
+            "channel-1",
+            IbcOrder::Unordered,
+            "simple-ica-v3,simple-ica-v2",
+        );
+        assert_eq!("simple-ica-v3", open_version(&msg).unwrap());
+        let msg = mock_ibc_channel_open_try("channel-1", IbcOrder::Unordered, "ics20-1");
+        open_version(&msg).unwrap_err();
+        let msg = mock_ibc_channel_open_try("channel-1", BAD_APP_ORDER, IBC_APP_VERSION);
+        open_version(&msg).unwrap_err();
+    }
+
+    #[test]
+    fn settings_on_connect() {
+        let msg = mock_ibc_channel_connect_ack("channel-1", IbcOrder::Ordered, "simple-ica-v3");
+        let settings = connect_settings(&msg).unwrap();
+        assert_eq!(ProtocolVersion::V3, settings.version);
+        assert_eq!(IbcOrder::Ordered, settings.order);
+
+        // the counterparty must pick exactly one version
+        let msg = mock_ibc_channel_connect_ack(
+            "channel-1",
+            IbcOrder::Unordered,
+            "simple-ica-v3,simple-ica-v2",
+        );
+        connect_settings(&msg).unwrap_err();
     }
 }
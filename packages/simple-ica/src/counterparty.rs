@@ -0,0 +1,113 @@
+use cosmwasm_std::IbcChannel;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::SimpleIcaError;
+
+/// Which counterparties may open channels to us. A list that is not set allows
+/// anything, so the default is open to all. An empty list allows nothing.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct CounterpartyPolicy {
+    /// Connections channels may be opened on
+    pub connections: Option<Vec<String>>,
+    /// Ports on the other end that may open channels
+    pub ports: Option<Vec<PortFilter>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PortFilter {
+    /// Exactly this port, e.g. `wasm.juno1...` for one contract
+    Exact(String),
+    /// Any port starting with this, e.g. `wasm.` for all contracts
+    Prefix(String),
+}
+
+impl PortFilter {
+    pub fn matches(&self, port_id: &str) -> bool {
+        match self {
+            PortFilter::Exact(port) => port == port_id,
+            PortFilter::Prefix(prefix) => port_id.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+impl CounterpartyPolicy {
+    pub fn is_open(&self) -> bool {
+        self.connections.is_none() && self.ports.is_none()
+    }
+
+    /// Fails unless the other end of this channel is allowed
+    pub fn check(&self, channel: &IbcChannel) -> Result<(), SimpleIcaError> {
+        if let Some(connections) = &self.connections {
+            if !connections.contains(&channel.connection_id) {
+                return Err(SimpleIcaError::ConnectionNotAllowed(
+                    channel.connection_id.clone(),
+                ));
+            }
+        }
+        let port_id = &channel.counterparty_endpoint.port_id;
+        if let Some(ports) = &self.ports {
+            if !ports.iter().any(|filter| filter.matches(port_id)) {
+                return Err(SimpleIcaError::PortNotAllowed(port_id.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::mock_ibc_channel;
+    use cosmwasm_std::IbcOrder;
+
+    // on connection-2, from their_port
+    fn channel() -> IbcChannel {
+        mock_ibc_channel("channel-1", IbcOrder::Unordered, "simple-ica-v2")
+    }
+
+    #[test]
+    fn open_policy_allows_all() {
+        let policy = CounterpartyPolicy::default();
+        assert!(policy.is_open());
+        policy.check(&channel()).unwrap();
+    }
+
+    #[test]
+    fn checks_connection_and_port() {
+        let policy = CounterpartyPolicy {
+            connections: Some(vec!["connection-2".to_string()]),
+            ports: Some(vec![PortFilter::Prefix("their".to_string())]),
+        };
+        assert!(!policy.is_open());
+        policy.check(&channel()).unwrap();
+
+        let policy = CounterpartyPolicy {
+            connections: Some(vec!["connection-0".to_string()]),
+            ports: None,
+        };
+        let err = policy.check(&channel()).unwrap_err();
+        assert_eq!(
+            SimpleIcaError::ConnectionNotAllowed("connection-2".to_string()),
+            err
+        );
+
+        let policy = CounterpartyPolicy {
+            connections: None,
+            ports: Some(vec![PortFilter::Exact("their".to_string())]),
+        };
+        let err = policy.check(&channel()).unwrap_err();
+        assert_eq!(
+            SimpleIcaError::PortNotAllowed("their_port".to_string()),
+            err
+        );
+
+        // an empty list allows nothing
+        let policy = CounterpartyPolicy {
+            connections: Some(vec![]),
+            ports: None,
+        };
+        policy.check(&channel()).unwrap_err();
+    }
+}
@@ -1,7 +1,12 @@
-use cosmwasm_std::{from_slice, to_binary, Binary, Coin, CosmosMsg, Empty, QueryRequest};
+use cosmwasm_std::{
+    from_slice, to_binary, Binary, Coin, CosmosMsg, Empty, Event, QueryRequest, StdError, StdResult,
+};
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::checks::ProtocolVersion;
 
 /// This is the message we send over the IBC channel
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -11,14 +16,86 @@ pub enum PacketMsg {
         sender: String,
         msgs: Vec<CosmosMsg>,
         callback_id: Option<String>,
+        /// Missing from older controllers, which always dispatch atomically
+        #[serde(default)]
+        mode: DispatchMode,
     },
     IbcQuery {
         sender: String,
         msgs: Vec<QueryRequest<Empty>>,
         callback_id: Option<String>,
     },
-    WhoAmI {},
-    Balances {},
+    /// Returns the sender's account on the remote chain, creating it if needed.
+    /// Controllers before 0.3.0 send no sender, and get the account of the channel
+    WhoAmI {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sender: Option<String>,
+    },
+    /// Missing the sender like `WhoAmI`, from controllers before 0.3.0
+    Balances {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sender: Option<String>,
+    },
+    /// Moves the funds rescued from the sender's account on a closed channel into its account
+    /// on this one. Both channels must come from the same controller.
+    ReclaimFunds {
+        sender: String,
+        /// The closed channel, as the host knows it
+        channel_id: String,
+    },
+    /// Migrates the sender's account to another code. The host decides which codes it may use
+    MigrateAccount {
+        sender: String,
+        code_id: u64,
+        /// The migrate message of the new code
+        msg: Binary,
+    },
+}
+
+/// The packet as sent on a `simple-ica-v3` channel. It names its version, so a packet
+/// from a controller that disagrees on the version fails to parse instead of being misread
+#[derive(Serialize, Deserialize)]
+struct PacketV3 {
+    v3: PacketMsg,
+}
+
+impl PacketMsg {
+    /// Serializes the packet for a channel of the given version
+    pub fn encode(&self, version: ProtocolVersion) -> StdResult<Binary> {
+        match version {
+            ProtocolVersion::V2 => to_binary(self),
+            ProtocolVersion::V3 => to_binary(&PacketV3 { v3: self.clone() }),
+        }
+    }
+
+    /// Parses a packet received on a channel of the given version
+    pub fn decode(data: &[u8], version: ProtocolVersion) -> StdResult<Self> {
+        match version {
+            ProtocolVersion::V2 => from_slice(data),
+            ProtocolVersion::V3 => from_slice::<PacketV3>(data).map(|packet| packet.v3),
+        }
+    }
+}
+
+/// How the host runs the messages of a `Dispatch`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DispatchMode {
+    /// All messages succeed, or the whole packet fails
+    Atomic,
+    /// Runs the messages one by one until the first failure. The rest are skipped
+    StopOnError,
+    /// Runs every message, whether the ones before failed or not
+    BestEffort,
+}
+
+// Deriving Default for an enum needs `#[default]` (rust 1.62), but we still build with 1.58.
+// The same goes for the other enums with a manual Default impl.
+#[allow(clippy::derivable_impls)]
+impl Default for DispatchMode {
+    fn default() -> Self {
+        DispatchMode::Atomic
+    }
 }
 
 /// This is a generic ICS acknowledgement format.
@@ -29,13 +106,16 @@ pub enum PacketMsg {
 pub enum StdAck {
     Result(Binary),
     Error(String),
+    /// This is never sent over the wire by the host. The controller uses it to report
+    /// to the original sender that the packet timed out and was never executed remotely.
+    Timeout {},
 }
 
 impl StdAck {
     // create a serialized success message
-    pub fn success(data: impl Serialize) -> Binary {
-        let res = to_binary(&data).unwrap();
-        StdAck::Result(res).ack()
+    pub fn success(data: impl Serialize) -> StdResult<Binary> {
+        let res = to_binary(&data)?;
+        Ok(StdAck::Result(res).ack())
     }
 
     // create a serialized error message
@@ -44,32 +124,203 @@ impl StdAck {
     }
 
     pub fn ack(&self) -> Binary {
+        // an enum of a binary or a string always serializes
         to_binary(self).unwrap()
     }
 
+    /// The data of a success ack, or the error text.
+    /// A timeout is an error as well, the packet was never executed
+    pub fn into_result(self) -> Result<Binary, String> {
+        self.into()
+    }
+
+    /// Parses the data of a success ack. Error acks and timeouts give a generic error
+    pub fn try_into_typed<T: DeserializeOwned>(self) -> StdResult<T> {
+        let data = self.into_result().map_err(StdError::generic_err)?;
+        from_slice(&data)
+    }
+
+    /// The ack of a `PacketMsg::Dispatch`
+    pub fn try_into_dispatch(self) -> StdResult<DispatchResponse> {
+        self.try_into_typed()
+    }
+
+    /// The ack of a `PacketMsg::IbcQuery`
+    pub fn try_into_query(self) -> StdResult<IbcQueryResponse> {
+        self.try_into_typed()
+    }
+
+    /// The ack of a `PacketMsg::WhoAmI`
+    pub fn try_into_who_am_i(self) -> StdResult<WhoAmIResponse> {
+        self.try_into_typed()
+    }
+
+    /// The ack of a `PacketMsg::Balances`
+    pub fn try_into_balances(self) -> StdResult<BalancesResponse> {
+        self.try_into_typed()
+    }
+
+    /// Panics unless this is a success ack. Use `into_result` outside of tests
     pub fn unwrap(self) -> Binary {
         match self {
             StdAck::Result(data) => data,
             StdAck::Error(err) => panic!("{}", err),
+            StdAck::Timeout {} => panic!("packet timed out"),
         }
     }
 
+    /// Panics unless this is a success ack with data of type `T`.
+    /// Use `try_into_typed` outside of tests
     pub fn unwrap_into<T: DeserializeOwned>(self) -> T {
         from_slice(&self.unwrap()).unwrap()
     }
 
+    /// Panics unless this is an error ack. Use `into_result` outside of tests
     pub fn unwrap_err(self) -> String {
         match self {
-            StdAck::Result(_) => panic!("not an error"),
+            StdAck::Result(_) | StdAck::Timeout {} => panic!("not an error"),
             StdAck::Error(err) => err,
         }
     }
+
+    /// The code and message of an error ack, `None` for anything else.
+    /// Errors without a code, e.g. from older hosts, have `ErrorCode::Other`
+    pub fn ica_error(&self) -> Option<IcaError> {
+        match self {
+            StdAck::Error(err) => Some(IcaError::parse(err)),
+            StdAck::Result(_) | StdAck::Timeout {} => None,
+        }
+    }
 }
 
-/// Return the data field for each message
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+impl From<StdAck> for Result<Binary, String> {
+    fn from(ack: StdAck) -> Self {
+        match ack {
+            StdAck::Result(data) => Ok(data),
+            StdAck::Error(err) => Err(err),
+            StdAck::Timeout {} => Err("packet timed out".to_string()),
+        }
+    }
+}
+
+/// What went wrong with a packet, for receivers to match on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The packet could not be parsed
+    InvalidPacket,
+    /// The sender has no account on the channel
+    UnregisteredAccount,
+    /// The sender may not do this
+    Unauthorized,
+    QueryFailed,
+    DispatchFailed,
+    /// The channel refuses all packets for now
+    Frozen,
+    /// The host does not allow one of the messages on this channel
+    MsgNotAllowed,
+    /// The packet would spend more than the account may
+    LimitExceeded,
+    /// Anything else, and errors that did not come with a code
+    Other,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 9] = [
+        ErrorCode::InvalidPacket,
+        ErrorCode::UnregisteredAccount,
+        ErrorCode::Unauthorized,
+        ErrorCode::QueryFailed,
+        ErrorCode::DispatchFailed,
+        ErrorCode::Frozen,
+        ErrorCode::MsgNotAllowed,
+        ErrorCode::LimitExceeded,
+        ErrorCode::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidPacket => "invalid_packet",
+            ErrorCode::UnregisteredAccount => "unregistered_account",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::QueryFailed => "query_failed",
+            ErrorCode::DispatchFailed => "dispatch_failed",
+            ErrorCode::Frozen => "frozen",
+            ErrorCode::MsgNotAllowed => "msg_not_allowed",
+            ErrorCode::LimitExceeded => "limit_exceeded",
+            ErrorCode::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error of a failed packet, with a code and a message for humans.
+/// It goes over the wire as the text of a plain ICS error ack, `{"error":"[code] message"}`,
+/// so anything that only knows `StdAck` still reads it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IcaError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl IcaError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        IcaError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Reads the text of an error ack. Text without a known code is kept whole, as `Other`
+    pub fn parse(err: &str) -> Self {
+        let coded = err.strip_prefix('[').and_then(|rest| {
+            let (code, message) = rest.split_once("] ")?;
+            let code = ErrorCode::ALL.iter().find(|c| c.as_str() == code)?;
+            Some(IcaError::new(*code, message))
+        });
+        coded.unwrap_or_else(|| IcaError::new(ErrorCode::Other, err))
+    }
+
+    /// The serialized error ack
+    pub fn ack(&self) -> Binary {
+        StdAck::fail(self.to_string())
+    }
+}
+
+impl fmt::Display for IcaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+/// Return the data field and events for each message
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
 pub struct DispatchResponse {
+    /// The data returned by each message, in order. Empty if the account did not forward it
     pub results: Vec<Binary>,
+    /// The events emitted by each message, in order. Empty from hosts that do not report them
+    #[serde(default)]
+    pub events: Vec<Vec<Event>>,
+    /// What became of each message, in order. Empty from hosts that do not report them
+    #[serde(default)]
+    pub outcomes: Vec<MessageOutcome>,
+}
+
+/// What became of one message of a `Dispatch`.
+/// Only the partial dispatch modes report anything but success.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageOutcome {
+    Success {},
+    /// It failed and changed nothing
+    Error(String),
+    /// It was not run, as an earlier message failed
+    Skipped {},
 }
 
 /// Return the data field for each message
@@ -92,3 +343,123 @@ pub struct BalancesResponse {
     pub account: String,
     pub balances: Vec<Coin>,
 }
+
+/// This is the success response we send on ack for PacketMsg::ReclaimFunds.
+/// Return the account that received the funds and how much it got
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReclaimFundsResponse {
+    pub account: String,
+    pub amount: Vec<Coin>,
+}
+
+/// This is the success response we send on ack for PacketMsg::MigrateAccount.
+/// Return the account and the code it runs now
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateAccountResponse {
+    pub account: String,
+    pub code_id: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ica_error_round_trips() {
+        let err = IcaError::new(
+            ErrorCode::UnregisteredAccount,
+            "No account for sender alice",
+        );
+        assert_eq!(
+            "[unregistered_account] No account for sender alice",
+            err.to_string()
+        );
+        assert_eq!(err, IcaError::parse(&err.to_string()));
+        for code in ErrorCode::ALL {
+            let err = IcaError::new(code, "boom");
+            assert_eq!(err, IcaError::parse(&err.to_string()));
+        }
+
+        // text without a known code is kept whole
+        for text in ["failed to execute", "[unknown_code] boom", "[other]boom"] {
+            assert_eq!(IcaError::new(ErrorCode::Other, text), IcaError::parse(text));
+        }
+    }
+
+    #[test]
+    fn typed_acks() {
+        let res = DispatchResponse {
+            results: vec![b"data".into()],
+            ..DispatchResponse::default()
+        };
+        let ack = StdAck::Result(to_binary(&res).unwrap());
+        assert_eq!(None, ack.ica_error());
+        assert_eq!(res, ack.clone().try_into_dispatch().unwrap());
+        // the wrong type does not parse
+        ack.try_into_who_am_i().unwrap_err();
+
+        let who = WhoAmIResponse {
+            account: "account".into(),
+        };
+        let ack = StdAck::Result(to_binary(&who).unwrap());
+        assert_eq!(who, ack.try_into_who_am_i().unwrap());
+
+        let err = IcaError::new(ErrorCode::Frozen, "Channel channel-1 is frozen");
+        let ack: StdAck = from_slice(&err.ack()).unwrap();
+        assert_eq!(Some(err.clone()), ack.ica_error());
+        match ack.try_into_balances().unwrap_err() {
+            StdError::GenericErr { msg, .. } => assert_eq!(err.to_string(), msg),
+            other => panic!("unexpected error: {}", other),
+        }
+
+        let ack = StdAck::Timeout {};
+        assert_eq!(None, ack.ica_error());
+        match ack.try_into_query().unwrap_err() {
+            StdError::GenericErr { msg, .. } => assert_eq!("packet timed out", msg),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn packets_keep_their_version() {
+        let packet = PacketMsg::WhoAmI {
+            sender: Some("alice".into()),
+        };
+        for version in [ProtocolVersion::V2, ProtocolVersion::V3] {
+            let data = packet.encode(version).unwrap();
+            assert_eq!(packet, PacketMsg::decode(&data, version).unwrap());
+        }
+
+        let v2 = packet.encode(ProtocolVersion::V2).unwrap();
+        assert_eq!(br#"{"who_am_i":{"sender":"alice"}}"#, v2.as_slice());
+        let v3 = packet.encode(ProtocolVersion::V3).unwrap();
+        assert_eq!(br#"{"v3":{"who_am_i":{"sender":"alice"}}}"#, v3.as_slice());
+
+        // a packet of the other version does not parse
+        PacketMsg::decode(&v2, ProtocolVersion::V3).unwrap_err();
+        PacketMsg::decode(&v3, ProtocolVersion::V2).unwrap_err();
+
+        // dispatches from older controllers have no mode
+        let data = br#"{"dispatch":{"sender":"alice","msgs":[],"callback_id":null}}"#;
+        let packet = PacketMsg::decode(data, ProtocolVersion::V2).unwrap();
+        assert_eq!(
+            PacketMsg::Dispatch {
+                sender: "alice".into(),
+                msgs: vec![],
+                callback_id: None,
+                mode: DispatchMode::Atomic,
+            },
+            packet
+        );
+
+        // nor do they name the sender of a WhoAmI or Balances
+        let packet = PacketMsg::decode(br#"{"who_am_i":{}}"#, ProtocolVersion::V2).unwrap();
+        assert_eq!(PacketMsg::WhoAmI { sender: None }, packet);
+        let packet = PacketMsg::decode(br#"{"balances":{}}"#, ProtocolVersion::V2).unwrap();
+        assert_eq!(PacketMsg::Balances { sender: None }, packet);
+        let data = PacketMsg::Balances { sender: None }
+            .encode(ProtocolVersion::V2)
+            .unwrap();
+        assert_eq!(br#"{"balances":{}}"#, data.as_slice());
+    }
+}
@@ -1,16 +1,26 @@
 mod callback;
 mod checks;
+mod counterparty;
 mod ibc_msg;
+mod migrate;
 
 use cosmwasm_std::IbcOrder;
 
 pub use crate::callback::ReceiveIcaResponseMsg;
-pub use crate::checks::{check_order, check_version, SimpleIcaError};
+pub use crate::checks::{
+    check_order, check_version, connect_settings, negotiate_version, open_version, ChannelSettings,
+    ProtocolVersion, SimpleIcaError, SUPPORTED_VERSIONS,
+};
+pub use crate::counterparty::{CounterpartyPolicy, PortFilter};
 pub use crate::ibc_msg::{
-    BalancesResponse, DispatchResponse, IbcQueryResponse, PacketMsg, StdAck, WhoAmIResponse,
+    BalancesResponse, DispatchMode, DispatchResponse, ErrorCode, IbcQueryResponse, IcaError,
+    MessageOutcome, MigrateAccountResponse, PacketMsg, ReclaimFundsResponse, StdAck,
+    WhoAmIResponse,
 };
+pub use crate::migrate::ensure_upgrade;
 
 pub const IBC_APP_VERSION: &str = "simple-ica-v2";
+// the default order. Ordered channels are optional and need simple-ica-v3
 pub const APP_ORDER: IbcOrder = IbcOrder::Unordered;
-// we use this for tests to ensure it is rejected
+// we use this for tests to ensure it is rejected with IBC_APP_VERSION
 pub const BAD_APP_ORDER: IbcOrder = IbcOrder::Ordered;
@@ -0,0 +1,63 @@
+use cw2::ContractVersion;
+use semver::Version;
+
+use crate::checks::SimpleIcaError;
+
+/// Fails unless `previous` is an older or equal version of the contract `name` at `version`.
+/// Returns the previous version, so the caller can pick the migrations it needs
+pub fn ensure_upgrade(
+    previous: &ContractVersion,
+    name: &str,
+    version: &str,
+) -> Result<Version, SimpleIcaError> {
+    if previous.contract != name {
+        return Err(SimpleIcaError::WrongContract(previous.contract.clone()));
+    }
+    let previous_version: Version = previous.version.parse()?;
+    let current_version: Version = version.parse()?;
+    if previous_version > current_version {
+        return Err(SimpleIcaError::CannotDowngrade {
+            previous: previous.version.clone(),
+            current: version.to_string(),
+        });
+    }
+    Ok(previous_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &str = "crates.io:simple-ica-host";
+
+    fn stored(contract: &str, version: &str) -> ContractVersion {
+        ContractVersion {
+            contract: contract.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    #[test]
+    fn upgrades_only_same_contract() {
+        let previous = ensure_upgrade(&stored(NAME, "0.2.1"), NAME, "0.3.0").unwrap();
+        assert_eq!(Version::new(0, 2, 1), previous);
+        ensure_upgrade(&stored(NAME, "0.3.0"), NAME, "0.3.0").unwrap();
+
+        let err = ensure_upgrade(&stored(NAME, "0.4.0"), NAME, "0.3.0").unwrap_err();
+        assert_eq!(
+            SimpleIcaError::CannotDowngrade {
+                previous: "0.4.0".to_string(),
+                current: "0.3.0".to_string()
+            },
+            err
+        );
+        let err =
+            ensure_upgrade(&stored("crates.io:cw20-base", "0.1.0"), NAME, "0.3.0").unwrap_err();
+        assert_eq!(
+            SimpleIcaError::WrongContract("crates.io:cw20-base".to_string()),
+            err
+        );
+        let err = ensure_upgrade(&stored(NAME, "latest"), NAME, "0.3.0").unwrap_err();
+        assert!(matches!(err, SimpleIcaError::SemVer(_)));
+    }
+}